@@ -47,7 +47,9 @@ create table main.diff_comments
     author              TEXT,
     thread_id           TEXT,
     reply_to_comment_id TEXT,
-    update_memory       INTEGER
+    update_memory       INTEGER,
+    addressed_turn_id   TEXT,
+    remote_comment_id   INTEGER
 );
 
 create index main.idx_diff_comments_workspace
@@ -119,7 +121,9 @@ create table main.sessions
     is_hidden            INTEGER default 0,
     agent_type,
     title                TEXT    default 'Untitled',
-    context_used_percent FLOAT
+    context_used_percent FLOAT,
+    cost_usd             REAL,
+    fork_session         INTEGER
 );
 
 create index main.idx_sessions_workspace_id
@@ -166,6 +170,10 @@ create table main.workspaces
     pinned_at                    TEXT,
     linked_workspace_ids         TEXT,
     notes                        TEXT,
-    intended_target_branch       TEXT
+    intended_target_branch       TEXT,
+    pr_number                    INTEGER,
+    pr_url                       TEXT,
+    pr_checks                    TEXT,
+    display_order                INTEGER
 );
 
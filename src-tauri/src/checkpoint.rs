@@ -0,0 +1,341 @@
+use crate::commands::load_workspace_context;
+use crate::git::{run_git, run_git_with_env};
+use crate::session::rewind_resume_point;
+use crate::AppState;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+use tauri::State;
+
+/// Hidden ref namespace holding one snapshot commit per agent turn
+const CHECKPOINT_REF_PREFIX: &str = "refs/letsvibe/checkpoints";
+
+/// Identity for checkpoint commits, so snapshots work without a configured git user
+const CHECKPOINT_IDENTITY: [(&str, &str); 4] = [
+    ("GIT_AUTHOR_NAME", "letsvibe"),
+    ("GIT_AUTHOR_EMAIL", "letsvibe@localhost"),
+    ("GIT_COMMITTER_NAME", "letsvibe"),
+    ("GIT_COMMITTER_EMAIL", "letsvibe@localhost"),
+];
+
+/// Snapshot of a workspace worktree taken before an agent turn
+#[derive(Debug, Clone, Serialize)]
+pub struct Checkpoint {
+    pub session_id: String,
+    pub turn_id: String,
+    pub commit: String,
+    pub created_at: String,
+    pub prompt: Option<String>,
+}
+
+fn checkpoint_ref(session_id: &str, turn_id: &str) -> String {
+    format!("{}/{}/{}", CHECKPOINT_REF_PREFIX, session_id, turn_id)
+}
+
+/// Snapshot the worktree into a checkpoint for this turn. Returns None for repositories
+/// without any commits.
+pub(crate) fn create_checkpoint(
+    worktree_path: &Path,
+    session_id: &str,
+    turn_id: &str,
+) -> Result<Option<String>, String> {
+    let message = format!("letsvibe checkpoint before turn {}", turn_id);
+    snapshot_worktree(
+        worktree_path,
+        &checkpoint_ref(session_id, turn_id),
+        &message,
+    )
+}
+
+/// Snapshot the worktree, including untracked (but not ignored) files, into a commit
+/// stored under `refname`. The commit's parent is the current HEAD, so diffing the two
+/// gives the uncommitted changes. Uses a temporary index, so the worktree and its
+/// staging area are left untouched. Returns None for repositories without any commits.
+pub(crate) fn snapshot_worktree(
+    worktree_path: &Path,
+    refname: &str,
+    message: &str,
+) -> Result<Option<String>, String> {
+    let head = match run_git(worktree_path, &["rev-parse", "--verify", "HEAD"]) {
+        Ok(head) => head.trim().to_string(),
+        Err(_) => return Ok(None),
+    };
+
+    let tree = write_worktree_tree(worktree_path, &head)?;
+    let commit = run_git_with_env(
+        worktree_path,
+        &["commit-tree", &tree, "-p", &head, "-m", message],
+        &CHECKPOINT_IDENTITY,
+    )?;
+    let commit = commit.trim().to_string();
+    run_git(worktree_path, &["update-ref", refname, &commit])?;
+    Ok(Some(commit))
+}
+
+/// Store the worktree's current files, untracked ones included, as a tree object on
+/// top of `head`. Uses a temporary index, so the staging area is left untouched.
+pub(crate) fn write_worktree_tree(worktree_path: &Path, head: &str) -> Result<String, String> {
+    let index_path = std::env::temp_dir().join(format!(
+        "letsvibe-checkpoint-{}.index",
+        uuid::Uuid::new_v4()
+    ));
+    let index = index_path.to_string_lossy().to_string();
+    let envs = [("GIT_INDEX_FILE", index.as_str())];
+
+    let result = (|| {
+        run_git_with_env(worktree_path, &["read-tree", head], &envs)?;
+        run_git_with_env(worktree_path, &["add", "-A"], &envs)?;
+        let tree = run_git_with_env(worktree_path, &["write-tree"], &envs)?;
+        Ok(tree.trim().to_string())
+    })();
+
+    let _ = std::fs::remove_file(&index_path);
+    result
+}
+
+/// Roll the worktree back to a checkpoint: the branch is reset to the commit that was
+/// HEAD at the time, and uncommitted and untracked files are restored as they were.
+fn restore_worktree(worktree_path: &Path, commit: &str) -> Result<(), String> {
+    let parent = format!("{}^", commit);
+    run_git(worktree_path, &["reset", "--hard", &parent])?;
+    // Drop files created since the checkpoint; ignored files are left alone
+    run_git(worktree_path, &["clean", "-fd"])?;
+    // Bring the working tree to the snapshot, removing files deleted before it was taken
+    run_git(worktree_path, &["read-tree", "--reset", "-u", commit])?;
+    // Unstage everything again so the snapshot shows up as uncommitted changes
+    run_git(worktree_path, &["reset", "--quiet"])?;
+    Ok(())
+}
+
+/// Delete the checkpoint of one turn
+pub(crate) fn delete_checkpoint(
+    worktree_path: &Path,
+    session_id: &str,
+    turn_id: &str,
+) -> Result<(), String> {
+    run_git(
+        worktree_path,
+        &["update-ref", "-d", &checkpoint_ref(session_id, turn_id)],
+    )?;
+    Ok(())
+}
+
+/// Delete every checkpoint of a session. Returns how many were removed.
+pub(crate) fn delete_session_checkpoints(
+    repo_path: &Path,
+    session_id: &str,
+) -> Result<usize, String> {
+    let prefix = format!("{}/{}/", CHECKPOINT_REF_PREFIX, session_id);
+    let refs = run_git(repo_path, &["for-each-ref", "--format=%(refname)", &prefix])?;
+
+    let mut deleted = 0;
+    for refname in refs.lines().filter(|r| !r.is_empty()) {
+        run_git(repo_path, &["update-ref", "-d", refname])?;
+        deleted += 1;
+    }
+
+    Ok(deleted)
+}
+
+/// List the checkpoints of a session in turn order
+#[tauri::command]
+pub async fn list_checkpoints(
+    state: State<'_, AppState>,
+    session_id: String,
+) -> Result<Vec<Checkpoint>, String> {
+    let db = state.db.lock().await;
+    let pool = db.as_ref().ok_or("Database not initialized")?;
+
+    let workspace_id: Option<String> =
+        sqlx::query_scalar("SELECT workspace_id FROM sessions WHERE id = ?")
+            .bind(&session_id)
+            .fetch_one(pool)
+            .await
+            .map_err(|e| format!("Session not found: {}", e))?;
+    let workspace_id = workspace_id.ok_or("Session has no workspace")?;
+    let context = load_workspace_context(pool, &workspace_id).await?;
+
+    let prefix = format!("{}/{}/", CHECKPOINT_REF_PREFIX, session_id);
+    let refs = run_git(
+        &context.worktree_path,
+        &[
+            "for-each-ref",
+            "--format=%(refname:lstrip=4) %(objectname) %(creatordate:iso-strict)",
+            &prefix,
+        ],
+    )?;
+
+    let mut commits: HashMap<&str, (&str, &str)> = HashMap::new();
+    for line in refs.lines() {
+        let mut parts = line.splitn(3, ' ');
+        if let (Some(turn_id), Some(commit), Some(created_at)) =
+            (parts.next(), parts.next(), parts.next())
+        {
+            commits.insert(turn_id, (commit, created_at));
+        }
+    }
+
+    let turns: Vec<(String, Option<String>)> = sqlx::query_as(
+        "SELECT turn_id, content FROM session_messages WHERE session_id = ? AND role = 'user' ORDER BY rowid",
+    )
+    .bind(&session_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let checkpoints = turns
+        .into_iter()
+        .filter_map(|(turn_id, prompt)| {
+            let (commit, created_at) = commits.get(turn_id.as_str())?;
+            Some(Checkpoint {
+                session_id: session_id.clone(),
+                turn_id: turn_id.clone(),
+                commit: commit.to_string(),
+                created_at: created_at.to_string(),
+                prompt,
+            })
+        })
+        .collect();
+
+    Ok(checkpoints)
+}
+
+/// Roll a workspace back to the state it was in before the given turn ran. With
+/// `truncate`, that turn and every later message are removed from the session too.
+#[tauri::command]
+pub async fn restore_checkpoint(
+    state: State<'_, AppState>,
+    turn_id: String,
+    truncate: Option<bool>,
+) -> Result<(), String> {
+    let db = state.db.lock().await;
+    let pool = db.as_ref().ok_or("Database not initialized")?;
+
+    let session_id: Option<String> =
+        sqlx::query_scalar("SELECT session_id FROM session_messages WHERE turn_id = ? LIMIT 1")
+            .bind(&turn_id)
+            .fetch_optional(pool)
+            .await
+            .map_err(|e| e.to_string())?
+            .flatten();
+    let session_id = session_id.ok_or("Turn not found")?;
+
+    if state.agent_turns.lock().await.contains_key(&session_id) {
+        return Err("Cannot restore a checkpoint while the agent is running".to_string());
+    }
+
+    let workspace_id: Option<String> =
+        sqlx::query_scalar("SELECT workspace_id FROM sessions WHERE id = ?")
+            .bind(&session_id)
+            .fetch_one(pool)
+            .await
+            .map_err(|e| format!("Session not found: {}", e))?;
+    let workspace_id = workspace_id.ok_or("Session has no workspace")?;
+    let context = load_workspace_context(pool, &workspace_id).await?;
+
+    let commit = run_git(
+        &context.worktree_path,
+        &[
+            "rev-parse",
+            "--verify",
+            &checkpoint_ref(&session_id, &turn_id),
+        ],
+    )
+    .map_err(|_| "No checkpoint exists for this turn".to_string())?;
+    restore_worktree(&context.worktree_path, commit.trim())?;
+
+    if truncate.unwrap_or(false) {
+        let first_rowid: i64 =
+            sqlx::query_scalar("SELECT MIN(rowid) FROM session_messages WHERE turn_id = ?")
+                .bind(&turn_id)
+                .fetch_one(pool)
+                .await
+                .map_err(|e| e.to_string())?;
+
+        let removed_turns: Vec<String> = sqlx::query_scalar(
+            "SELECT DISTINCT turn_id FROM session_messages WHERE session_id = ? AND rowid >= ? AND turn_id IS NOT NULL",
+        )
+        .bind(&session_id)
+        .bind(first_rowid)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        sqlx::query("DELETE FROM session_messages WHERE session_id = ? AND rowid >= ?")
+            .bind(&session_id)
+            .bind(first_rowid)
+            .execute(pool)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        for removed in &removed_turns {
+            delete_checkpoint(&context.worktree_path, &session_id, removed)?;
+        }
+
+        // The agent's own transcript must forget the removed turns as well
+        rewind_resume_point(pool, &session_id).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn git(dir: &Path, args: &[&str]) -> String {
+        run_git_with_env(dir, args, &CHECKPOINT_IDENTITY).unwrap()
+    }
+
+    #[test]
+    fn test_checkpoint_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("letsvibe-ckpt-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        git(&dir, &["init", "-q"]);
+        fs::write(dir.join("tracked.txt"), "original\n").unwrap();
+        fs::write(dir.join("deleted.txt"), "keep me deleted\n").unwrap();
+        git(&dir, &["add", "."]);
+        git(&dir, &["commit", "-qm", "initial"]);
+        let head = git(&dir, &["rev-parse", "HEAD"]);
+
+        // State before the turn: an edit, a deletion and an untracked file
+        fs::write(dir.join("tracked.txt"), "edited\n").unwrap();
+        fs::remove_file(dir.join("deleted.txt")).unwrap();
+        fs::write(dir.join("untracked.txt"), "new\n").unwrap();
+
+        let commit = create_checkpoint(&dir, "s1", "t1").unwrap().unwrap();
+        assert_eq!(git(&dir, &["status", "--porcelain"]).lines().count(), 3);
+
+        // The "agent" commits, then leaves more changes behind
+        fs::write(dir.join("tracked.txt"), "agent\n").unwrap();
+        git(&dir, &["add", "-A"]);
+        git(&dir, &["commit", "-qm", "agent work"]);
+        fs::write(dir.join("agent.txt"), "scratch\n").unwrap();
+        fs::remove_file(dir.join("untracked.txt")).unwrap();
+
+        restore_worktree(&dir, &commit).unwrap();
+
+        assert_eq!(git(&dir, &["rev-parse", "HEAD"]), head);
+        assert_eq!(
+            fs::read_to_string(dir.join("tracked.txt")).unwrap(),
+            "edited\n"
+        );
+        assert!(!dir.join("deleted.txt").exists());
+        assert!(!dir.join("agent.txt").exists());
+        assert_eq!(
+            fs::read_to_string(dir.join("untracked.txt")).unwrap(),
+            "new\n"
+        );
+        assert!(git(&dir, &["status", "--porcelain"]).contains("?? untracked.txt"));
+
+        delete_checkpoint(&dir, "s1", "t1").unwrap();
+        assert!(run_git(
+            &dir,
+            &["rev-parse", "--verify", &checkpoint_ref("s1", "t1")]
+        )
+        .is_err());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
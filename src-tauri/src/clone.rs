@@ -0,0 +1,310 @@
+use crate::commands::{detect_main_branch, get_setting, insert_repo, NewRepo};
+use crate::db::models::Repo;
+use crate::{AppState, DbPool};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use tauri::{AppHandle, Emitter, State};
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+use tokio::sync::Notify;
+
+/// Settings key holding the directory new clones are placed in
+const CLONE_DIRECTORY_SETTING: &str = "clone_directory";
+
+/// Progress event emitted as `clone-progress` while `git clone` runs
+#[derive(Debug, Clone, Serialize)]
+pub struct CloneProgress {
+    pub url: String,
+    pub phase: String,
+    pub percent: Option<u8>,
+    pub bytes: Option<u64>,
+}
+
+/// A single parsed `git clone --progress` line
+#[derive(Debug, PartialEq)]
+struct ProgressStep {
+    phase: String,
+    percent: Option<u8>,
+    bytes: Option<u64>,
+}
+
+/// Clone a remote repository and register it as a repo
+#[tauri::command]
+pub async fn clone_repository(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    url: String,
+    directory: Option<String>,
+) -> Result<Repo, String> {
+    // Cloning can take minutes, so don't hold the database lock while it runs
+    let pool = state.pool().await?;
+
+    let url = url.trim().to_string();
+    let name = repo_name_from_url(&url).ok_or("Cannot determine repository name from URL")?;
+
+    let parent = match directory {
+        Some(directory) => PathBuf::from(directory),
+        None => default_clone_directory(&pool).await?,
+    };
+    std::fs::create_dir_all(&parent)
+        .map_err(|e| format!("Failed to create clone directory: {}", e))?;
+
+    let target = parent.join(&name);
+    if target.exists() {
+        return Err(format!("Directory {} already exists", target.display()));
+    }
+
+    let cancel = Arc::new(Notify::new());
+    {
+        let mut clones = state.clones.lock().await;
+        if clones.contains_key(&url) {
+            return Err("This repository is already being cloned".to_string());
+        }
+        clones.insert(url.clone(), cancel.clone());
+    }
+
+    let result = run_clone(&app, &url, &target, &cancel).await;
+    state.clones.lock().await.remove(&url);
+
+    if let Err(e) = result {
+        // Don't leave a partial clone behind; the directory did not exist before we started
+        let _ = std::fs::remove_dir_all(&target);
+        return Err(e);
+    }
+
+    let root_path = target.to_string_lossy().to_string();
+    let default_branch = detect_main_branch(&root_path).ok();
+
+    insert_repo(
+        &pool,
+        NewRepo {
+            name,
+            root_path: Some(root_path),
+            remote_url: Some(url),
+            default_branch,
+            remote: Some("origin".to_string()),
+        },
+    )
+    .await
+}
+
+/// Cancel an in-progress clone started by `clone_repository`
+#[tauri::command]
+pub async fn cancel_clone(state: State<'_, AppState>, url: String) -> Result<(), String> {
+    let clones = state.clones.lock().await;
+    let cancel = clones
+        .get(url.trim())
+        .ok_or("No clone in progress for this URL")?;
+    cancel.notify_one();
+    Ok(())
+}
+
+/// Directory clones go into: the `clone_directory` setting, or ~/letsvibe-repos
+async fn default_clone_directory(pool: &DbPool) -> Result<PathBuf, String> {
+    if let Some(directory) = get_setting(pool, CLONE_DIRECTORY_SETTING).await? {
+        return Ok(PathBuf::from(directory));
+    }
+    let home_dir = dirs::home_dir().ok_or("Cannot determine home directory")?;
+    Ok(home_dir.join("letsvibe-repos"))
+}
+
+/// Run `git clone --progress`, emitting progress events until it exits or is cancelled
+async fn run_clone(
+    app: &AppHandle,
+    url: &str,
+    target: &Path,
+    cancel: &Notify,
+) -> Result<(), String> {
+    let mut child = Command::new("git")
+        .arg("clone")
+        .arg("--progress")
+        .arg(url)
+        .arg(target)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("Failed to execute git command: {}", e))?;
+
+    let mut stderr = child.stderr.take().ok_or("Failed to capture git output")?;
+    let mut buf = [0u8; 4096];
+    let mut pending: Vec<u8> = Vec::new();
+    let mut messages: Vec<String> = Vec::new();
+
+    loop {
+        tokio::select! {
+            _ = cancel.notified() => {
+                let _ = child.kill().await;
+                return Err("Clone cancelled".to_string());
+            }
+            read = stderr.read(&mut buf) => {
+                let n = read.map_err(|e| format!("Failed to read git output: {}", e))?;
+                if n == 0 {
+                    break;
+                }
+                pending.extend_from_slice(&buf[..n]);
+
+                // git rewrites progress lines in place with '\r', so split on both
+                while let Some(pos) = pending.iter().position(|b| *b == b'\r' || *b == b'\n') {
+                    let raw: Vec<u8> = pending.drain(..=pos).collect();
+                    let line = String::from_utf8_lossy(&raw[..raw.len() - 1]).trim().to_string();
+                    if line.is_empty() {
+                        continue;
+                    }
+
+                    match parse_clone_progress(&line) {
+                        Some(step) => {
+                            let _ = app.emit(
+                                "clone-progress",
+                                CloneProgress {
+                                    url: url.to_string(),
+                                    phase: step.phase,
+                                    percent: step.percent,
+                                    bytes: step.bytes,
+                                },
+                            );
+                        }
+                        None => messages.push(line),
+                    }
+                }
+            }
+        }
+    }
+
+    let status = tokio::select! {
+        _ = cancel.notified() => {
+            let _ = child.kill().await;
+            return Err("Clone cancelled".to_string());
+        }
+        status = child.wait() => status.map_err(|e| format!("Failed to wait for git: {}", e))?,
+    };
+
+    if !status.success() {
+        let errors: Vec<&str> = messages
+            .iter()
+            .map(|m| m.as_str())
+            .filter(|m| m.starts_with("fatal:") || m.starts_with("error:"))
+            .collect();
+        let detail = if errors.is_empty() {
+            messages.last().cloned().unwrap_or_default()
+        } else {
+            errors.join("\n")
+        };
+        return Err(format!("Git clone failed: {}", detail));
+    }
+
+    Ok(())
+}
+
+/// Derive the repository name from a clone URL or path
+/// (e.g. "git@github.com:user/repo.git" -> "repo")
+fn repo_name_from_url(url: &str) -> Option<String> {
+    let trimmed = url.trim().trim_end_matches('/');
+    let trimmed = trimmed.strip_suffix(".git").unwrap_or(trimmed);
+    let name = trimmed.rsplit(['/', ':', '\\']).next()?;
+
+    if name.is_empty() || name == "." || name == ".." {
+        None
+    } else {
+        Some(name.to_string())
+    }
+}
+
+/// Parse a progress line such as
+/// "Receiving objects:  45% (450/1000), 1.20 MiB | 2.00 MiB/s"
+fn parse_clone_progress(line: &str) -> Option<ProgressStep> {
+    let line = line.strip_prefix("remote:").map(str::trim).unwrap_or(line);
+    let (phase, rest) = line.split_once(':')?;
+
+    let percent_end = rest.find('%')?;
+    let percent = rest[..percent_end]
+        .split_whitespace()
+        .last()
+        .and_then(|p| p.parse::<u8>().ok())?;
+
+    // The transferred size follows the counts: "(450/1000), 1.20 MiB | ..."
+    let bytes = rest
+        .split_once("), ")
+        .and_then(|(_, size)| size.split('|').next())
+        .and_then(parse_size);
+
+    Some(ProgressStep {
+        phase: phase.trim().to_string(),
+        percent: Some(percent),
+        bytes,
+    })
+}
+
+/// Parse a human-readable size like "1.20 MiB" into bytes
+fn parse_size(size: &str) -> Option<u64> {
+    let mut parts = size.split_whitespace();
+    let value: f64 = parts.next()?.parse().ok()?;
+    let multiplier = match parts.next()? {
+        "bytes" | "byte" => 1.0,
+        "KiB" => 1024.0,
+        "MiB" => 1024.0 * 1024.0,
+        "GiB" => 1024.0 * 1024.0 * 1024.0,
+        _ => return None,
+    };
+    Some((value * multiplier) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repo_name_from_url() {
+        assert_eq!(
+            repo_name_from_url("https://github.com/user/repo.git"),
+            Some("repo".to_string())
+        );
+        assert_eq!(
+            repo_name_from_url("git@github.com:user/repo.git"),
+            Some("repo".to_string())
+        );
+        assert_eq!(
+            repo_name_from_url("https://github.com/user/repo/"),
+            Some("repo".to_string())
+        );
+        assert_eq!(
+            repo_name_from_url("git@host:repo"),
+            Some("repo".to_string())
+        );
+        assert_eq!(repo_name_from_url(""), None);
+    }
+
+    #[test]
+    fn test_parse_clone_progress_with_bytes() {
+        let step =
+            parse_clone_progress("Receiving objects:  45% (450/1000), 1.50 MiB | 2.00 MiB/s")
+                .unwrap();
+        assert_eq!(step.phase, "Receiving objects");
+        assert_eq!(step.percent, Some(45));
+        assert_eq!(step.bytes, Some(1572864));
+    }
+
+    #[test]
+    fn test_parse_clone_progress_remote_phase() {
+        let step = parse_clone_progress("remote: Counting objects: 100% (5/5), done.").unwrap();
+        assert_eq!(step.phase, "Counting objects");
+        assert_eq!(step.percent, Some(100));
+        assert_eq!(step.bytes, None);
+    }
+
+    #[test]
+    fn test_parse_clone_progress_ignores_messages() {
+        assert_eq!(parse_clone_progress("Cloning into 'repo'..."), None);
+        assert_eq!(
+            parse_clone_progress("fatal: repository 'x' not found"),
+            None
+        );
+        assert_eq!(
+            parse_clone_progress("remote: Enumerating objects: 5, done."),
+            None
+        );
+    }
+}
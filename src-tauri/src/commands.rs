@@ -1,10 +1,81 @@
+use crate::checkpoint::{delete_session_checkpoints, snapshot_worktree, write_worktree_tree};
 use crate::db::models::{Repo, Workspace};
-use crate::place_names::select_available_name;
-use crate::AppState;
-use serde::Serialize;
-use std::path::Path;
+use crate::diff::compute_diff_stats;
+use crate::git::{run_git, run_git_with_env};
+use crate::links::link_group;
+use crate::place_names::{select_available_name, workspace_port};
+use crate::run_script::stop_workspace_script;
+use crate::scripts::{run_script, ScriptOutput};
+use crate::session::clone_session_history;
+use crate::setup::{copy_initialization_files, load_initialization_files, start_setup_script};
+use crate::terminal::close_workspace_terminals;
+use crate::{AppState, DbPool};
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
 use std::process::Command;
-use tauri::State;
+use tauri::{AppHandle, State};
+
+/// `workspaces.state` of a workspace that is ready to use
+pub const WORKSPACE_STATE_ACTIVE: &str = "active";
+
+/// `workspaces.state` of a workspace whose worktree was archived
+pub const WORKSPACE_STATE_ARCHIVED: &str = "archived";
+
+/// Hidden ref namespace holding the snapshots of archived workspaces
+const ARCHIVE_REF_PREFIX: &str = "refs/letsvibe/archives";
+
+/// Order of a repository's workspaces in the sidebar: pinned ones first, then the
+/// others. Within each group, ones never moved by hand come first (pinned ones in the
+/// order they were pinned, the others most recent first), then the rest in the order
+/// they were moved into.
+const WORKSPACE_ORDER: &str =
+    "pinned_at IS NULL, display_order IS NOT NULL, display_order, pinned_at, updated_at DESC";
+
+/// A newly created workspace along with the files copied into it
+#[derive(Debug, Clone, Serialize)]
+pub struct CreateWorkspaceResult {
+    #[serde(flatten)]
+    pub workspace: Workspace,
+    /// Files copied (or symlinked) from the repository root, relative to it
+    pub copied_files: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+/// What a new workspace starts from
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WorkspaceSource {
+    /// A new branch off the repository's main branch
+    #[default]
+    MainBranch,
+    /// An existing local branch, checked out as it is, or on a new branch off its head
+    /// when it is already checked out elsewhere
+    Branch { name: String },
+    /// A branch of the repository's remote, fetched first and tracked by a local branch
+    RemoteBranch { name: String },
+    /// The head of a pull request, fetched from `refs/pull/<number>/head`
+    PullRequest { number: u64 },
+    /// Any commit, on a new branch
+    Commit { sha: String },
+}
+
+/// A workspace source worked out into what `git worktree add` needs
+#[derive(Debug, PartialEq)]
+struct ResolvedSource {
+    /// Commit-ish the worktree starts from
+    start_point: String,
+    /// Recorded as the workspace's `initialization_parent_branch`
+    parent: String,
+    checkout: SourceCheckout,
+}
+
+#[derive(Debug, PartialEq)]
+enum SourceCheckout {
+    /// Check out an existing branch
+    Existing(String),
+    /// Create a branch, named after the workspace unless a name is given
+    New { name: Option<String>, track: bool },
+}
 
 #[derive(Debug, Clone, Serialize)]
 pub struct RepoWithWorkspaces {
@@ -13,6 +84,81 @@ pub struct RepoWithWorkspaces {
     pub workspaces: Vec<Workspace>,
 }
 
+/// A workspace together with its repository and worktree location
+pub(crate) struct WorkspaceContext {
+    pub workspace: Workspace,
+    pub repo: Repo,
+    pub worktree_path: PathBuf,
+}
+
+impl WorkspaceContext {
+    /// Branch the workspace's changes are compared against and merged into
+    pub fn base_branch(&self) -> String {
+        workspace_base_branch(&self.workspace, &self.repo)
+    }
+}
+
+/// The workspace's `intended_target_branch`, falling back to the branch it was created from
+pub(crate) fn workspace_base_branch(workspace: &Workspace, repo: &Repo) -> String {
+    workspace
+        .intended_target_branch
+        .clone()
+        .or_else(|| workspace.initialization_parent_branch.clone())
+        .or_else(|| repo.default_branch.clone())
+        .unwrap_or_else(|| "main".to_string())
+}
+
+/// Calculate the worktree path for a workspace: ~/letsvibe-workspaces/<repo_name>/<directory_name>
+pub(crate) fn worktree_path(repo_path: &str, directory_name: &str) -> Result<PathBuf, String> {
+    let home_dir = dirs::home_dir().ok_or("Cannot determine home directory")?;
+    let repo_name = Path::new(repo_path)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or("Cannot determine repository name")?;
+    Ok(home_dir
+        .join("letsvibe-workspaces")
+        .join(repo_name)
+        .join(directory_name))
+}
+
+/// Load a workspace, its repository and its worktree path
+pub(crate) async fn load_workspace_context(
+    pool: &DbPool,
+    workspace_id: &str,
+) -> Result<WorkspaceContext, String> {
+    let workspace: Workspace = sqlx::query_as("SELECT * FROM workspaces WHERE id = ?")
+        .bind(workspace_id)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| format!("Workspace not found: {}", e))?;
+
+    let repo_id = workspace
+        .repository_id
+        .as_ref()
+        .ok_or("Workspace has no repository")?;
+    let repo: Repo = sqlx::query_as("SELECT * FROM repos WHERE id = ?")
+        .bind(repo_id)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| format!("Repository not found: {}", e))?;
+
+    let repo_path = repo
+        .root_path
+        .as_ref()
+        .ok_or("Repository has no root path")?;
+    let directory_name = workspace
+        .directory_name
+        .as_ref()
+        .ok_or("Workspace has no directory name")?;
+    let worktree_path = worktree_path(repo_path, directory_name)?;
+
+    Ok(WorkspaceContext {
+        workspace,
+        repo,
+        worktree_path,
+    })
+}
+
 /// Get the last active time for a workspace (last commit time or current time if there are uncommitted changes)
 fn get_last_active_time(repo_path: &str, workspace_directory: &str) -> Option<String> {
     let home_dir = dirs::home_dir()?;
@@ -60,57 +206,11 @@ fn get_last_active_time(repo_path: &str, workspace_directory: &str) -> Option<St
     None
 }
 
-/// Calculate git statistics for a workspace
-fn get_git_stats(repo_path: &str, workspace_directory: &str) -> Option<(i64, i64)> {
-    // Calculate worktree path
-    let home_dir = dirs::home_dir()?;
-    let repo_name = Path::new(repo_path).file_name()?.to_str()?;
-    let worktree_path = home_dir
-        .join("letsvibe-workspaces")
-        .join(repo_name)
-        .join(workspace_directory);
-
-    if !worktree_path.exists() {
-        return None;
-    }
-
-    // Get the parent branch (initialization_parent_branch) for comparison
-    // For now, we'll use HEAD to compare against the working tree changes
-    let output = Command::new("git")
-        .arg("-C")
-        .arg(&worktree_path)
-        .arg("diff")
-        .arg("--numstat")
-        .output()
-        .ok()?;
-
-    if !output.status.success() {
-        return None;
-    }
-
-    let diff_output = String::from_utf8_lossy(&output.stdout);
-    let mut total_insertions: i64 = 0;
-    let mut total_deletions: i64 = 0;
-
-    for line in diff_output.lines() {
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.len() >= 2 {
-            if let (Ok(insertions), Ok(deletions)) = (parts[0].parse::<i64>(), parts[1].parse::<i64>()) {
-                total_insertions += insertions;
-                total_deletions += deletions;
-            }
-        }
-    }
-
-    if total_insertions > 0 || total_deletions > 0 {
-        Some((total_insertions, total_deletions))
-    } else {
-        None
-    }
-}
-
 #[tauri::command]
-pub async fn get_repositories(state: State<'_, AppState>) -> Result<Vec<RepoWithWorkspaces>, String> {
+pub async fn get_repositories(
+    state: State<'_, AppState>,
+    include_archived: Option<bool>,
+) -> Result<Vec<RepoWithWorkspaces>, String> {
     let db = state.db.lock().await;
     let pool = db.as_ref().ok_or("Database not initialized")?;
 
@@ -121,22 +221,36 @@ pub async fn get_repositories(state: State<'_, AppState>) -> Result<Vec<RepoWith
 
     let mut result = Vec::new();
     for repo in repos {
-        let mut workspaces: Vec<Workspace> = sqlx::query_as(
-            "SELECT * FROM workspaces WHERE repository_id = ? ORDER BY updated_at DESC",
-        )
-        .bind(&repo.id)
-        .fetch_all(pool)
-        .await
-        .map_err(|e| e.to_string())?;
+        let query = format!(
+            r#"
+            SELECT * FROM workspaces
+            WHERE repository_id = ? AND (? OR COALESCE(state, 'active') != ?)
+            ORDER BY {}
+            "#,
+            WORKSPACE_ORDER
+        );
+        let mut workspaces: Vec<Workspace> = sqlx::query_as(&query)
+            .bind(&repo.id)
+            .bind(include_archived.unwrap_or(false))
+            .bind(WORKSPACE_STATE_ARCHIVED)
+            .fetch_all(pool)
+            .await
+            .map_err(|e| e.to_string())?;
 
         // Populate git statistics and last active time for each workspace
         if let Some(repo_path) = &repo.root_path {
             for workspace in &mut workspaces {
                 if let Some(directory_name) = &workspace.directory_name {
-                    // Get git statistics
-                    if let Some((insertions, deletions)) = get_git_stats(repo_path, directory_name) {
-                        workspace.git_insertions = Some(insertions);
-                        workspace.git_deletions = Some(deletions);
+                    // Get git statistics against the branch the workspace will merge into
+                    let stats = worktree_path(repo_path, directory_name)
+                        .ok()
+                        .filter(|path| path.exists())
+                        .and_then(|path| {
+                            compute_diff_stats(&path, &workspace_base_branch(workspace, &repo)).ok()
+                        });
+                    if let Some(stats) = stats {
+                        workspace.git_insertions = Some(stats.insertions);
+                        workspace.git_deletions = Some(stats.deletions);
                     }
 
                     // Get last active time
@@ -163,18 +277,46 @@ pub async fn create_repo(
     let db = state.db.lock().await;
     let pool = db.as_ref().ok_or("Database not initialized")?;
 
+    insert_repo(
+        pool,
+        NewRepo {
+            name,
+            root_path,
+            remote_url,
+            default_branch: None,
+            remote: None,
+        },
+    )
+    .await
+}
+
+/// Fields for a new `repos` row
+pub(crate) struct NewRepo {
+    pub name: String,
+    pub root_path: Option<String>,
+    pub remote_url: Option<String>,
+    pub default_branch: Option<String>,
+    pub remote: Option<String>,
+}
+
+/// Insert a repository record and return it. Shared by every command that registers a repo.
+/// New repositories go at the bottom of the sidebar.
+pub(crate) async fn insert_repo(pool: &DbPool, new_repo: NewRepo) -> Result<Repo, String> {
     let id = uuid::Uuid::new_v4().to_string();
 
     sqlx::query(
         r#"
-        INSERT INTO repos (id, name, root_path, remote_url)
-        VALUES (?, ?, ?, ?)
+        INSERT INTO repos (id, name, root_path, remote_url, default_branch, remote, display_order)
+        VALUES (?, ?, ?, ?, COALESCE(?, 'main'), ?,
+                (SELECT COALESCE(MAX(display_order) + 1, 0) FROM repos))
         "#,
     )
     .bind(&id)
-    .bind(&name)
-    .bind(&root_path)
-    .bind(&remote_url)
+    .bind(&new_repo.name)
+    .bind(&new_repo.root_path)
+    .bind(&new_repo.remote_url)
+    .bind(&new_repo.default_branch)
+    .bind(&new_repo.remote)
     .execute(pool)
     .await
     .map_err(|e| e.to_string())?;
@@ -188,17 +330,272 @@ pub async fn create_repo(
     Ok(repo)
 }
 
+/// Read a value from the `settings` table
+pub(crate) async fn get_setting(pool: &DbPool, key: &str) -> Result<Option<String>, String> {
+    sqlx::query_scalar("SELECT value FROM settings WHERE key = ?")
+        .bind(key)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Insert or replace a value in the `settings` table
+pub(crate) async fn set_setting(pool: &DbPool, key: &str, value: &str) -> Result<(), String> {
+    sqlx::query(
+        r#"
+        INSERT INTO settings (key, value) VALUES (?, ?)
+        ON CONFLICT(key) DO UPDATE SET value = excluded.value, updated_at = datetime('now')
+        "#,
+    )
+    .bind(key)
+    .bind(value)
+    .execute(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Register an existing local folder as a repo, returning the existing record if it
+/// is already registered. Folders that aren't git repositories are rejected unless
+/// `init` is set, in which case `git init` is run first.
 #[tauri::command]
-pub async fn create_workspace(
+pub async fn open_project(
     state: State<'_, AppState>,
-    repository_id: String,
-) -> Result<Workspace, String> {
+    path: String,
+    init: Option<bool>,
+) -> Result<Repo, String> {
     let db = state.db.lock().await;
     let pool = db.as_ref().ok_or("Database not initialized")?;
 
+    let path = Path::new(&path);
+    if !path.is_dir() {
+        return Err(format!("Directory does not exist: {}", path.display()));
+    }
+
+    let root_path = match git_toplevel(path)? {
+        Some(root_path) => root_path,
+        None if init.unwrap_or(false) => {
+            let output = Command::new("git")
+                .arg("-C")
+                .arg(path)
+                .arg("init")
+                .output()
+                .map_err(|e| format!("Failed to execute git command: {}", e))?;
+
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                return Err(format!("Git init failed: {}", stderr));
+            }
+
+            git_toplevel(path)?.ok_or("Git init did not create a repository")?
+        }
+        None => {
+            return Err(format!("Not a git repository: {}", path.display()));
+        }
+    };
+
+    // Return the existing record if this repository is already registered
+    let existing: Option<Repo> =
+        sqlx::query_as("SELECT * FROM repos WHERE root_path = ? OR root_path = ?")
+            .bind(&root_path)
+            .bind(path.to_string_lossy().trim_end_matches('/'))
+            .fetch_optional(pool)
+            .await
+            .map_err(|e| e.to_string())?;
+
+    if let Some(repo) = existing {
+        return Ok(repo);
+    }
+
+    let name = Path::new(&root_path)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or("Cannot determine repository name")?
+        .to_string();
+
+    // Fall back to the checked-out branch for repos without main/master (e.g. freshly initialized)
+    let default_branch = detect_main_branch(&root_path)
+        .ok()
+        .or_else(|| current_branch(&root_path));
+
+    let remote_url = origin_url(&root_path);
+    let remote = remote_url.as_ref().map(|_| "origin".to_string());
+
+    insert_repo(
+        pool,
+        NewRepo {
+            name,
+            root_path: Some(root_path),
+            remote_url,
+            default_branch,
+            remote,
+        },
+    )
+    .await
+}
+
+/// Resolve the top-level directory of the repository containing `path`.
+/// Returns None if `path` is not inside a git repository.
+fn git_toplevel(path: &Path) -> Result<Option<String>, String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(path)
+        .arg("rev-parse")
+        .arg("--show-toplevel")
+        .output()
+        .map_err(|e| format!("Failed to execute git command: {}", e))?;
+
+    if !output.status.success() {
+        return Ok(None);
+    }
+
+    let toplevel = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if toplevel.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(toplevel))
+    }
+}
+
+/// Get the currently checked-out branch name, if HEAD points at a branch
+fn current_branch(repo_path: &str) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo_path)
+        .arg("symbolic-ref")
+        .arg("--short")
+        .arg("HEAD")
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    let branch = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if branch.is_empty() {
+        None
+    } else {
+        Some(branch)
+    }
+}
+
+/// Get the URL of the `origin` remote, if configured
+fn origin_url(repo_path: &str) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo_path)
+        .arg("remote")
+        .arg("get-url")
+        .arg("origin")
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    let url = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if url.is_empty() {
+        None
+    } else {
+        Some(url)
+    }
+}
+
+#[tauri::command]
+pub async fn create_workspace(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    repository_id: String,
+    source: Option<WorkspaceSource>,
+) -> Result<CreateWorkspaceResult, String> {
+    // Fetching the source can take a while, so work with a pool handle instead of the lock
+    let pool = state.pool().await?;
+    create_workspace_with(
+        &app,
+        &pool,
+        &repository_id,
+        source.unwrap_or_default(),
+        WorkspaceOptions::default(),
+    )
+    .await
+}
+
+/// Create a workspace from another one's current state: a new worktree on a branch from
+/// its HEAD, with its uncommitted changes applied. With `clone_session`, the history of
+/// its active session is copied into a session of the new workspace. The two workspaces
+/// end up linked.
+#[tauri::command]
+pub async fn fork_workspace(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    workspace_id: String,
+    clone_session: Option<bool>,
+) -> Result<CreateWorkspaceResult, String> {
+    let pool = state.pool().await?;
+    let source = load_workspace_context(&pool, &workspace_id).await?;
+    if !source.worktree_path.exists() {
+        return Err(format!(
+            "Workspace directory does not exist: {}",
+            source.worktree_path.display()
+        ));
+    }
+
+    let head = run_git(&source.worktree_path, &["rev-parse", "HEAD"])?
+        .trim()
+        .to_string();
+    let tree = write_worktree_tree(&source.worktree_path, &head)?;
+    let patch = run_git(&source.worktree_path, &["diff", "--binary", &head, &tree])?;
+
+    let mut result = create_workspace_with(
+        &app,
+        &pool,
+        &source.repo.id,
+        WorkspaceSource::Commit { sha: head },
+        WorkspaceOptions {
+            intended_target_branch: Some(source.base_branch()),
+            patch: Some(patch),
+        },
+    )
+    .await?;
+    let fork_id = result.workspace.id.clone();
+
+    if clone_session.unwrap_or(false) {
+        if let Some(session_id) = &source.workspace.active_session_id {
+            clone_session_history(&pool, session_id, &fork_id).await?;
+        }
+    }
+    link_group(&pool, &[workspace_id, fork_id.clone()]).await?;
+
+    result.workspace = sqlx::query_as("SELECT * FROM workspaces WHERE id = ?")
+        .bind(&fork_id)
+        .fetch_one(&pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(result)
+}
+
+/// What a workspace is created with besides its source
+#[derive(Debug, Default)]
+pub(crate) struct WorkspaceOptions {
+    /// Branch the workspace merges into, instead of the repository's main branch
+    pub intended_target_branch: Option<String>,
+    /// Uncommitted changes applied to the new worktree before its setup script runs
+    pub patch: Option<String>,
+}
+
+/// Create a worktree for a new workspace, record it and start its setup script
+pub(crate) async fn create_workspace_with(
+    app: &AppHandle,
+    pool: &DbPool,
+    repository_id: &str,
+    source: WorkspaceSource,
+    options: WorkspaceOptions,
+) -> Result<CreateWorkspaceResult, String> {
     // Get repository info
     let repo: Repo = sqlx::query_as("SELECT * FROM repos WHERE id = ?")
-        .bind(&repository_id)
+        .bind(repository_id)
         .fetch_one(pool)
         .await
         .map_err(|e| format!("Repository not found: {}", e))?;
@@ -209,14 +606,23 @@ pub async fn create_workspace(
         .ok_or("Repository has no root path")?;
 
     // Get used place names for this repository
-    let used_names: Vec<String> = sqlx::query_scalar(
+    let mut used_names: Vec<String> = sqlx::query_scalar(
         "SELECT directory_name FROM workspaces WHERE repository_id = ? AND directory_name IS NOT NULL",
     )
-    .bind(&repository_id)
+    .bind(repository_id)
     .fetch_all(pool)
     .await
     .map_err(|e| e.to_string())?;
 
+    // Detect main branch name
+    let main_branch = detect_main_branch(repo_path)?;
+    let remote = repo.remote.as_deref().unwrap_or("origin");
+    let resolved = resolve_workspace_source(Path::new(repo_path), remote, &main_branch, &source)?;
+    // Workspaces started elsewhere still diff against, and merge into, the main branch
+    let intended_target_branch = options
+        .intended_target_branch
+        .or_else(|| (!matches!(source, WorkspaceSource::MainBranch)).then(|| main_branch.clone()));
+
     // Try to create worktree with retry on conflict
     const MAX_RETRIES: usize = 3;
     let mut attempts = 0;
@@ -245,29 +651,56 @@ pub async fn create_workspace(
         if worktree_path.exists() {
             attempts += 1;
             last_error = format!("Directory {} already exists", worktree_path.display());
+            used_names.push(place_name);
             continue;
         }
 
-        // Detect main branch name
-        let main_branch = detect_main_branch(repo_path)?;
-
-        // Create git worktree
-        let output = Command::new("git")
+        // Create git worktree. Its errors are matched below, so keep them in English.
+        let mut command = Command::new("git");
+        command
+            .env("LC_ALL", "C")
             .arg("-C")
             .arg(repo_path)
             .arg("worktree")
-            .arg("add")
-            .arg("-b")
-            .arg(&place_name)
-            .arg(&worktree_path)
-            .arg(&main_branch)
+            .arg("add");
+        let branch = match &resolved.checkout {
+            SourceCheckout::Existing(branch) => {
+                command.arg(&worktree_path).arg(branch);
+                branch.clone()
+            }
+            SourceCheckout::New { name, track } => {
+                let branch = name.clone().unwrap_or_else(|| place_name.clone());
+                if *track {
+                    command.arg("--track");
+                }
+                command
+                    .arg("-b")
+                    .arg(&branch)
+                    .arg(&worktree_path)
+                    .arg(&resolved.start_point);
+                branch
+            }
+        };
+        let output = command
             .output()
             .map_err(|e| format!("Failed to execute git command: {}", e))?;
 
         if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
+            let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            // Only a place name already taken by a directory or branch is worth another
+            // name; a branch name the caller chose stays taken whatever the place name
+            let place_name_branch =
+                matches!(resolved.checkout, SourceCheckout::New { name: None, .. });
+            let taken_directory =
+                stderr.contains(&format!("'{}' already exists", worktree_path.display()));
+            let taken_branch =
+                stderr.contains(&format!("a branch named '{}' already exists", branch));
+            if !(taken_directory || (place_name_branch && taken_branch)) {
+                return Err(format!("Git worktree creation failed: {}", stderr));
+            }
             attempts += 1;
             last_error = format!("Git worktree creation failed: {}", stderr);
+            used_names.push(place_name);
             continue;
         }
 
@@ -276,26 +709,55 @@ pub async fn create_workspace(
 
         sqlx::query(
             r#"
-            INSERT INTO workspaces (id, repository_id, branch, directory_name, initialization_parent_branch)
-            VALUES (?, ?, ?, ?, ?)
+            INSERT INTO workspaces (id, repository_id, branch, directory_name,
+                                    initialization_parent_branch, intended_target_branch)
+            VALUES (?, ?, ?, ?, ?, ?)
             "#,
         )
         .bind(&id)
-        .bind(&repository_id)
-        .bind(&place_name)
+        .bind(repository_id)
+        .bind(&branch)
         .bind(&place_name)
-        .bind(&main_branch)
+        .bind(&resolved.parent)
+        .bind(&intended_target_branch)
         .execute(pool)
         .await
         .map_err(|e| e.to_string())?;
 
+        // Bring over ignored files like .env before the setup script needs them
+        let patterns = load_initialization_files(pool, repository_id).await?;
+        let (copied_files, mut warnings) =
+            copy_initialization_files(Path::new(repo_path), &worktree_path, &patterns);
+        if let Some(patch) = options.patch.as_deref().filter(|p| !p.trim().is_empty()) {
+            if let Err(e) = apply_patch(&worktree_path, patch) {
+                warnings.push(format!("Failed to carry over uncommitted changes: {}", e));
+            }
+        }
+
+        sqlx::query("UPDATE workspaces SET initialization_files_copied = ? WHERE id = ?")
+            .bind(copied_files.len() as i64)
+            .bind(&id)
+            .execute(pool)
+            .await
+            .map_err(|e| e.to_string())?;
+
         let workspace: Workspace = sqlx::query_as("SELECT * FROM workspaces WHERE id = ?")
             .bind(&id)
             .fetch_one(pool)
             .await
             .map_err(|e| e.to_string())?;
 
-        return Ok(workspace);
+        let context = WorkspaceContext {
+            workspace,
+            repo,
+            worktree_path,
+        };
+        let workspace = start_setup_script(app, pool, context).await?;
+        return Ok(CreateWorkspaceResult {
+            workspace,
+            copied_files,
+            warnings,
+        });
     }
 
     Err(format!(
@@ -304,8 +766,151 @@ pub async fn create_workspace(
     ))
 }
 
+/// Apply a patch made by `git diff --binary` to a worktree's files
+fn apply_patch(worktree_path: &Path, patch: &str) -> Result<(), String> {
+    let patch_path =
+        std::env::temp_dir().join(format!("letsvibe-fork-{}.patch", uuid::Uuid::new_v4()));
+    std::fs::write(&patch_path, patch).map_err(|e| format!("Failed to write patch: {}", e))?;
+    let result = run_git(
+        worktree_path,
+        &["apply", "--binary", &patch_path.to_string_lossy()],
+    );
+    let _ = std::fs::remove_file(&patch_path);
+    result.map(|_| ())
+}
+
+/// Work out where a new workspace starts, fetching remote branches and pull requests
+fn resolve_workspace_source(
+    repo_path: &Path,
+    remote: &str,
+    main_branch: &str,
+    source: &WorkspaceSource,
+) -> Result<ResolvedSource, String> {
+    // Fail instead of waiting for credentials nobody can type in
+    let fetch = |refspec: &str| {
+        run_git_with_env(
+            repo_path,
+            &["fetch", remote, refspec],
+            &[("GIT_TERMINAL_PROMPT", "0")],
+        )
+    };
+
+    let resolved = match source {
+        WorkspaceSource::MainBranch => ResolvedSource {
+            start_point: main_branch.to_string(),
+            parent: main_branch.to_string(),
+            checkout: SourceCheckout::New {
+                name: None,
+                track: false,
+            },
+        },
+        WorkspaceSource::Branch { name } => {
+            run_git(
+                repo_path,
+                &[
+                    "rev-parse",
+                    "--verify",
+                    "--quiet",
+                    &format!("refs/heads/{}", name),
+                ],
+            )
+            .map_err(|_| format!("Branch {} does not exist", name))?;
+            // A branch can only be checked out once, so one that's checked out already
+            // (like the main branch in the repository itself) gets a new branch off its head
+            let checkout = if checked_out_branches(repo_path)?.contains(name) {
+                SourceCheckout::New {
+                    name: None,
+                    track: false,
+                }
+            } else {
+                SourceCheckout::Existing(name.clone())
+            };
+            ResolvedSource {
+                start_point: name.clone(),
+                parent: name.clone(),
+                checkout,
+            }
+        }
+        WorkspaceSource::RemoteBranch { name } => {
+            let name = name.strip_prefix(&format!("{}/", remote)).unwrap_or(name);
+            let remote_branch = format!("{}/{}", remote, name);
+            fetch(&format!(
+                "+refs/heads/{}:refs/remotes/{}",
+                name, remote_branch
+            ))?;
+            // Continue on a local branch of the same name, so pushing updates the remote one
+            let local_exists = run_git(
+                repo_path,
+                &[
+                    "rev-parse",
+                    "--verify",
+                    "--quiet",
+                    &format!("refs/heads/{}", name),
+                ],
+            )
+            .is_ok();
+            ResolvedSource {
+                start_point: remote_branch.clone(),
+                parent: remote_branch,
+                checkout: SourceCheckout::New {
+                    name: (!local_exists).then(|| name.to_string()),
+                    track: true,
+                },
+            }
+        }
+        WorkspaceSource::PullRequest { number } => {
+            let pull_ref = format!("{}/pull/{}/head", remote, number);
+            fetch(&format!(
+                "+refs/pull/{}/head:refs/remotes/{}",
+                number, pull_ref
+            ))?;
+            ResolvedSource {
+                start_point: pull_ref.clone(),
+                parent: pull_ref,
+                checkout: SourceCheckout::New {
+                    name: None,
+                    track: false,
+                },
+            }
+        }
+        WorkspaceSource::Commit { sha } => {
+            let commit = run_git(
+                repo_path,
+                &[
+                    "rev-parse",
+                    "--verify",
+                    "--quiet",
+                    &format!("{}^{{commit}}", sha),
+                ],
+            )
+            .map_err(|_| format!("Commit {} does not exist", sha))?
+            .trim()
+            .to_string();
+            ResolvedSource {
+                start_point: commit.clone(),
+                parent: commit,
+                checkout: SourceCheckout::New {
+                    name: None,
+                    track: false,
+                },
+            }
+        }
+    };
+    Ok(resolved)
+}
+
+/// Branches checked out in the repository or any of its worktrees
+fn checked_out_branches(repo_path: &Path) -> Result<Vec<String>, String> {
+    let output = run_git(repo_path, &["worktree", "list", "--porcelain"])?;
+    Ok(output
+        .lines()
+        .filter_map(|line| line.strip_prefix("branch refs/heads/"))
+        .map(str::to_string)
+        .collect())
+}
+
 /// Detect the main branch name (main or master)
-fn detect_main_branch(repo_path: &str) -> Result<String, String> {
+pub(crate) fn detect_main_branch(repo_path: &str) -> Result<String, String> {
     // Try to get default branch from remote
     let output = Command::new("git")
         .arg("-C")
@@ -363,20 +968,681 @@ pub async fn delete_repo(state: State<'_, AppState>, id: String) -> Result<(), S
     Ok(())
 }
 
+/// Move a repository to position `to_index` in the sidebar
 #[tauri::command]
-pub async fn delete_workspace(state: State<'_, AppState>, id: String) -> Result<(), String> {
+pub async fn move_repo(
+    state: State<'_, AppState>,
+    id: String,
+    to_index: usize,
+) -> Result<(), String> {
     let db = state.db.lock().await;
     let pool = db.as_ref().ok_or("Database not initialized")?;
 
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+    let ids: Vec<String> = sqlx::query_scalar("SELECT id FROM repos ORDER BY display_order, name")
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+    let ids = move_id(ids, &id, to_index).ok_or("Repository not found")?;
+    rewrite_display_order(&mut tx, "repos", &ids).await?;
+    tx.commit().await.map_err(|e| e.to_string())
+}
+
+/// What `delete_workspace` removed
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DeleteWorkspaceResult {
+    pub worktree_removed: bool,
+    pub branch_deleted: bool,
+    pub archive_script: Option<ScriptOutput>,
+    pub sessions_deleted: u64,
+    pub messages_deleted: u64,
+    pub attachments_deleted: u64,
+    pub diff_comments_deleted: u64,
+    pub checkpoints_deleted: usize,
+    /// Problems that didn't stop the deletion, e.g. a branch kept because it isn't merged
+    pub warnings: Vec<String>,
+}
+
+/// Delete a workspace: run the repo's archive script, remove the git worktree, and
+/// delete its sessions, messages, attachments and diff comments. A worktree with
+/// uncommitted changes is only removed when `force` is set, which callers should only
+/// pass after the user confirmed discarding them. With `delete_branch`, the workspace
+/// branch is deleted too if it is merged into its target branch.
+#[tauri::command]
+pub async fn delete_workspace(
+    state: State<'_, AppState>,
+    id: String,
+    force: Option<bool>,
+    delete_branch: Option<bool>,
+) -> Result<DeleteWorkspaceResult, String> {
+    // Stopping scripts and running the archive script take a while, so don't hold the lock
+    let pool = state.pool().await?;
+    delete_workspace_with(
+        &state,
+        &pool,
+        &id,
+        force.unwrap_or(false),
+        delete_branch.unwrap_or(false),
+    )
+    .await
+}
+
+async fn delete_workspace_with(
+    state: &AppState,
+    pool: &DbPool,
+    id: &str,
+    force: bool,
+    delete_branch: bool,
+) -> Result<DeleteWorkspaceResult, String> {
+    // Workspaces without a repository or directory only have database records to remove
+    let context = load_workspace_context(pool, id).await.ok();
+
+    // Run every check that can fail before stopping anything, so a refused deletion
+    // doesn't leave the workspace half torn down
+    ensure_no_running_agent(state, pool, id).await?;
+    let removal = match &context {
+        Some(context) => Some(plan_workspace_removal(context, force, delete_branch)?),
+        None => None,
+    };
+
+    stop_workspace_script(&state.setup_scripts, id).await?;
+    stop_workspace_script(&state.run_scripts, id).await?;
+    close_workspace_terminals(&state.terminals, id).await;
+
+    let session_ids: Vec<String> =
+        sqlx::query_scalar("SELECT id FROM sessions WHERE workspace_id = ?")
+            .bind(id)
+            .fetch_all(pool)
+            .await
+            .map_err(|e| e.to_string())?;
+
+    let mut result = DeleteWorkspaceResult::default();
+    if let (Some(context), Some(removal)) = (&context, removal) {
+        remove_workspace_files(context, removal, &session_ids, force, &mut result).await?;
+    }
+
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+
+    result.messages_deleted = sqlx::query(
+        "DELETE FROM session_messages WHERE session_id IN (SELECT id FROM sessions WHERE workspace_id = ?)",
+    )
+    .bind(id)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| e.to_string())?
+    .rows_affected();
+
+    result.attachments_deleted = sqlx::query(
+        "DELETE FROM attachments WHERE session_id IN (SELECT id FROM sessions WHERE workspace_id = ?)",
+    )
+    .bind(id)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| e.to_string())?
+    .rows_affected();
+
+    result.sessions_deleted = sqlx::query("DELETE FROM sessions WHERE workspace_id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?
+        .rows_affected();
+
+    result.diff_comments_deleted = sqlx::query("DELETE FROM diff_comments WHERE workspace_id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?
+        .rows_affected();
+
     sqlx::query("DELETE FROM workspaces WHERE id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    tx.commit().await.map_err(|e| e.to_string())?;
+
+    Ok(result)
+}
+
+/// What removing a workspace's files involves, worked out before anything is touched
+struct WorkspaceRemoval {
+    worktree_exists: bool,
+    /// The branch to delete if it's merged into the target branch that follows it
+    branch_to_delete: Option<(String, String)>,
+}
+
+/// Check that a workspace's files can be removed, failing the way the removal would
+fn plan_workspace_removal(
+    context: &WorkspaceContext,
+    force: bool,
+    delete_branch: bool,
+) -> Result<WorkspaceRemoval, String> {
+    let repo_path = Path::new(
+        context
+            .repo
+            .root_path
+            .as_deref()
+            .ok_or("Repository has no root path")?,
+    );
+
+    let worktree_exists = context.worktree_path.exists();
+    if worktree_exists {
+        check_worktree_removable(repo_path, &context.worktree_path, force)?;
+    }
+    let branch_to_delete = match (&context.workspace.branch, delete_branch) {
+        (Some(branch), true) => {
+            let target = match context
+                .workspace
+                .intended_target_branch
+                .clone()
+                .or_else(|| context.workspace.initialization_parent_branch.clone())
+            {
+                Some(target) => target,
+                None => detect_main_branch(&repo_path.to_string_lossy())?,
+            };
+            Some((branch.clone(), target))
+        }
+        _ => None,
+    };
+
+    Ok(WorkspaceRemoval {
+        worktree_exists,
+        branch_to_delete,
+    })
+}
+
+/// Remove a workspace's worktree, branch and checkpoint refs from the repository
+async fn remove_workspace_files(
+    context: &WorkspaceContext,
+    removal: WorkspaceRemoval,
+    session_ids: &[String],
+    force: bool,
+    result: &mut DeleteWorkspaceResult,
+) -> Result<(), String> {
+    let repo_path = Path::new(
+        context
+            .repo
+            .root_path
+            .as_deref()
+            .ok_or("Repository has no root path")?,
+    );
+    let worktree_path = &context.worktree_path;
+
+    if removal.worktree_exists {
+        result.archive_script = run_archive_script(context).await?;
+        if let Some(output) = result.archive_script.as_ref().filter(|o| !o.success) {
+            result.warnings.push(format!(
+                "Archive script failed with exit code {}",
+                output
+                    .exit_code
+                    .map(|c| c.to_string())
+                    .unwrap_or_else(|| "unknown".to_string())
+            ));
+        }
+
+        let worktree = worktree_path.to_string_lossy();
+        let mut args = vec!["worktree", "remove"];
+        if force {
+            args.push("--force");
+        }
+        args.push(&worktree);
+        run_git(repo_path, &args)?;
+        result.worktree_removed = true;
+    } else {
+        // The directory is already gone; drop git's stale record of it
+        let _ = run_git(repo_path, &["worktree", "prune"]);
+        result.warnings.push(format!(
+            "Worktree {} was already missing",
+            worktree_path.display()
+        ));
+    }
+
+    if let Some((branch, target)) = removal.branch_to_delete {
+        if run_git(
+            repo_path,
+            &["merge-base", "--is-ancestor", &branch, &target],
+        )
+        .is_ok()
+        {
+            run_git(repo_path, &["branch", "-D", &branch])?;
+            result.branch_deleted = true;
+        } else {
+            result.warnings.push(format!(
+                "Kept branch {} because it is not merged into {}",
+                branch, target
+            ));
+        }
+    }
+
+    for session_id in session_ids {
+        result.checkpoints_deleted += delete_session_checkpoints(repo_path, session_id)?;
+    }
+
+    Ok(())
+}
+
+/// Fail when `git worktree remove` would refuse to remove a worktree: git keeps locked
+/// worktrees even when forced, and dirty ones unless forced
+fn check_worktree_removable(
+    repo_path: &Path,
+    worktree_path: &Path,
+    force: bool,
+) -> Result<(), String> {
+    let canonical_worktree = worktree_path
+        .canonicalize()
+        .map_err(|e| format!("Failed to canonicalize worktree path: {}", e))?;
+    let output = run_git(repo_path, &["worktree", "list", "--porcelain"])?;
+    for entry in output.split("\n\n") {
+        let path = entry
+            .lines()
+            .find_map(|line| line.strip_prefix("worktree "))
+            .and_then(|path| Path::new(path).canonicalize().ok());
+        if path.as_ref() != Some(&canonical_worktree) {
+            continue;
+        }
+        if let Some(lock) = entry
+            .lines()
+            .find(|line| *line == "locked" || line.starts_with("locked "))
+        {
+            let reason = lock.trim_start_matches("locked").trim();
+            return Err(if reason.is_empty() {
+                "Workspace worktree is locked. Unlock it with `git worktree unlock` first."
+                    .to_string()
+            } else {
+                format!(
+                    "Workspace worktree is locked ({}). Unlock it with `git worktree unlock` first.",
+                    reason
+                )
+            });
+        }
+    }
+
+    if !force && is_worktree_dirty(worktree_path)? {
+        return Err(
+            "Workspace has uncommitted changes. Confirm to discard them and delete anyway."
+                .to_string(),
+        );
+    }
+    Ok(())
+}
+
+/// Run the repository's archive script in the workspace, if one is configured
+async fn run_archive_script(context: &WorkspaceContext) -> Result<Option<ScriptOutput>, String> {
+    match context
+        .repo
+        .archive_script
+        .as_deref()
+        .filter(|s| !s.trim().is_empty())
+    {
+        Some(script) => {
+            let output = run_script(
+                script,
+                &context.worktree_path,
+                &workspace_script_env(context),
+            )
+            .await?;
+            Ok(Some(output))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Ref holding the snapshot of an archived workspace
+fn archive_ref(workspace_id: &str) -> String {
+    format!("{}/{}", ARCHIVE_REF_PREFIX, workspace_id)
+}
+
+/// Result of `archive_workspace`
+#[derive(Debug, Clone, Serialize)]
+pub struct ArchiveWorkspaceResult {
+    pub workspace: Workspace,
+    pub archive_script: Option<ScriptOutput>,
+}
+
+/// Archive a workspace to free disk space: the branch head and all uncommitted and
+/// untracked changes are saved in a snapshot ref, the archive script runs, and the
+/// worktree directory is removed. `restore_workspace` brings it back.
+#[tauri::command]
+pub async fn archive_workspace(
+    state: State<'_, AppState>,
+    id: String,
+) -> Result<ArchiveWorkspaceResult, String> {
+    // The snapshot and archive script take a while, so don't hold the lock meanwhile
+    let pool = state.pool().await?;
+    archive_workspace_with(&state, &pool, &id).await
+}
+
+pub(crate) async fn archive_workspace_with(
+    state: &AppState,
+    pool: &DbPool,
+    id: &str,
+) -> Result<ArchiveWorkspaceResult, String> {
+    let context = load_workspace_context(pool, id).await?;
+    if context.workspace.state.as_deref() == Some(WORKSPACE_STATE_ARCHIVED) {
+        return Err("Workspace is already archived".to_string());
+    }
+    if !context.worktree_path.exists() {
+        return Err(format!(
+            "Workspace directory does not exist: {}",
+            context.worktree_path.display()
+        ));
+    }
+    let repo_path = Path::new(
+        context
+            .repo
+            .root_path
+            .as_deref()
+            .ok_or("Repository has no root path")?,
+    );
+    ensure_no_running_agent(state, pool, id).await?;
+
+    stop_workspace_script(&state.setup_scripts, id).await?;
+    stop_workspace_script(&state.run_scripts, id).await?;
+    close_workspace_terminals(&state.terminals, id).await;
+
+    let message = format!(
+        "letsvibe archive of workspace {}",
+        context.workspace.directory_name.as_deref().unwrap_or(id)
+    );
+    snapshot_worktree(&context.worktree_path, &archive_ref(id), &message)?
+        .ok_or("Workspace has no commits to archive")?;
+
+    let archive_script = run_archive_script(&context).await?;
+
+    // Everything worth keeping is in the snapshot, so the removal can be forced
+    let worktree = context.worktree_path.to_string_lossy();
+    run_git(repo_path, &["worktree", "remove", "--force", &worktree])?;
+
+    sqlx::query("UPDATE workspaces SET state = ?, updated_at = datetime('now') WHERE id = ?")
+        .bind(WORKSPACE_STATE_ARCHIVED)
+        .bind(id)
+        .execute(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let workspace: Workspace = sqlx::query_as("SELECT * FROM workspaces WHERE id = ?")
+        .bind(id)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(ArchiveWorkspaceResult {
+        workspace,
+        archive_script,
+    })
+}
+
+/// Recreate an archived workspace's worktree from its branch and reapply the
+/// uncommitted changes saved when it was archived
+#[tauri::command]
+pub async fn restore_workspace(
+    state: State<'_, AppState>,
+    id: String,
+) -> Result<Workspace, String> {
+    let db = state.db.lock().await;
+    let pool = db.as_ref().ok_or("Database not initialized")?;
+
+    let context = load_workspace_context(pool, &id).await?;
+    if context.workspace.state.as_deref() != Some(WORKSPACE_STATE_ARCHIVED) {
+        return Err("Workspace is not archived".to_string());
+    }
+    if context.worktree_path.exists() {
+        return Err(format!(
+            "Directory {} already exists",
+            context.worktree_path.display()
+        ));
+    }
+    let repo_path = Path::new(
+        context
+            .repo
+            .root_path
+            .as_deref()
+            .ok_or("Repository has no root path")?,
+    );
+
+    let snapshot = run_git(repo_path, &["rev-parse", "--verify", &archive_ref(&id)])
+        .map_err(|_| "No archive found for this workspace".to_string())?;
+    let snapshot = snapshot.trim();
+    let head = run_git(repo_path, &["rev-parse", &format!("{}^", snapshot)])?;
+    let head = head.trim();
+
+    let branch = context
+        .workspace
+        .branch
+        .as_deref()
+        .or(context.workspace.directory_name.as_deref())
+        .ok_or("Workspace has no branch")?;
+
+    if let Some(parent) = context.worktree_path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create worktree base directory: {}", e))?;
+    }
+
+    // Reuse the branch if it still exists (it may have moved on); otherwise recreate it at the saved head
+    let worktree = context.worktree_path.to_string_lossy();
+    let branch_ref = format!("refs/heads/{}", branch);
+    if run_git(repo_path, &["rev-parse", "--verify", &branch_ref]).is_ok() {
+        run_git(repo_path, &["worktree", "add", &worktree, branch])?;
+    } else {
+        run_git(
+            repo_path,
+            &["worktree", "add", "-b", branch, &worktree, head],
+        )?;
+    }
+
+    if let Err(e) = reapply_archived_changes(repo_path, &context.worktree_path, head, snapshot) {
+        // Leave things as they were so the restore can be retried
+        let _ = run_git(repo_path, &["worktree", "remove", "--force", &worktree]);
+        return Err(e);
+    }
+
+    run_git(repo_path, &["update-ref", "-d", &archive_ref(&id)])?;
+
+    sqlx::query("UPDATE workspaces SET state = ?, updated_at = datetime('now') WHERE id = ?")
+        .bind(WORKSPACE_STATE_ACTIVE)
         .bind(&id)
         .execute(pool)
         .await
         .map_err(|e| e.to_string())?;
 
+    let workspace: Workspace = sqlx::query_as("SELECT * FROM workspaces WHERE id = ?")
+        .bind(&id)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(workspace)
+}
+
+/// Pin a workspace to the top of its repository's list, below the ones pinned before it
+#[tauri::command]
+pub async fn pin_workspace(state: State<'_, AppState>, id: String) -> Result<Workspace, String> {
+    set_pinned(&state, &id, true).await
+}
+
+/// Unpin a workspace. It goes back among the unpinned ones that were never moved by hand.
+#[tauri::command]
+pub async fn unpin_workspace(state: State<'_, AppState>, id: String) -> Result<Workspace, String> {
+    set_pinned(&state, &id, false).await
+}
+
+async fn set_pinned(state: &AppState, id: &str, pinned: bool) -> Result<Workspace, String> {
+    let db = state.db.lock().await;
+    let pool = db.as_ref().ok_or("Database not initialized")?;
+    set_pinned_in(pool, id, pinned).await
+}
+
+async fn set_pinned_in(pool: &DbPool, id: &str, pinned: bool) -> Result<Workspace, String> {
+    // A newly pinned workspace goes after the pinned ones that were moved by hand, or
+    // sorts by `pinned_at` when none were. Pinning an already pinned workspace keeps its
+    // place among the pinned ones.
+    let query = if pinned {
+        r#"
+        UPDATE workspaces SET
+            pinned_at = datetime('now'),
+            display_order = (
+                SELECT MAX(pinned.display_order) + 1 FROM workspaces AS pinned
+                WHERE pinned.repository_id = workspaces.repository_id
+                  AND pinned.pinned_at IS NOT NULL
+            )
+        WHERE id = ? AND pinned_at IS NULL
+        "#
+    } else {
+        "UPDATE workspaces SET pinned_at = NULL, display_order = NULL WHERE id = ? AND pinned_at IS NOT NULL"
+    };
+    sqlx::query(query)
+        .bind(id)
+        .execute(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    sqlx::query_as("SELECT * FROM workspaces WHERE id = ?")
+        .bind(id)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| format!("Workspace not found: {}", e))
+}
+
+/// Move a workspace to position `to_index` among its repository's workspaces, as listed
+/// by `get_repositories` without archived ones. Pinned workspaces still come first, so
+/// each one can only be moved among the workspaces sharing its pinned state.
+#[tauri::command]
+pub async fn move_workspace(
+    state: State<'_, AppState>,
+    id: String,
+    to_index: usize,
+) -> Result<(), String> {
+    let db = state.db.lock().await;
+    let pool = db.as_ref().ok_or("Database not initialized")?;
+    move_workspace_in(pool, &id, to_index).await
+}
+
+async fn move_workspace_in(pool: &DbPool, id: &str, to_index: usize) -> Result<(), String> {
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+    let query = format!(
+        r#"
+        SELECT id FROM workspaces
+        WHERE repository_id = (SELECT repository_id FROM workspaces WHERE id = ?)
+          AND COALESCE(state, 'active') != ?
+        ORDER BY {}
+        "#,
+        WORKSPACE_ORDER
+    );
+    let ids: Vec<String> = sqlx::query_scalar(&query)
+        .bind(id)
+        .bind(WORKSPACE_STATE_ARCHIVED)
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+    let ids = move_id(ids, id, to_index).ok_or("Workspace not found")?;
+    rewrite_display_order(&mut tx, "workspaces", &ids).await?;
+    tx.commit().await.map_err(|e| e.to_string())
+}
+
+/// Move `id` to `to_index` in `ids`, or to the end when the index is past it.
+/// Returns None when `id` isn't in the list.
+fn move_id(mut ids: Vec<String>, id: &str, to_index: usize) -> Option<Vec<String>> {
+    let from = ids.iter().position(|other| other == id)?;
+    let moved = ids.remove(from);
+    ids.insert(to_index.min(ids.len()), moved);
+    Some(ids)
+}
+
+/// Number the rows of `table` in the order of `ids`
+async fn rewrite_display_order(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    table: &str,
+    ids: &[String],
+) -> Result<(), String> {
+    let query = format!("UPDATE {} SET display_order = ? WHERE id = ?", table);
+    for (position, id) in ids.iter().enumerate() {
+        sqlx::query(&query)
+            .bind(position as i64)
+            .bind(id)
+            .execute(&mut **tx)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Apply the changes between the archived head and its snapshot to a fresh worktree
+fn reapply_archived_changes(
+    repo_path: &Path,
+    worktree_path: &Path,
+    head: &str,
+    snapshot: &str,
+) -> Result<(), String> {
+    let patch = run_git(repo_path, &["diff", "--binary", head, snapshot])?;
+    if patch.trim().is_empty() {
+        return Ok(());
+    }
+
+    let patch_path =
+        std::env::temp_dir().join(format!("letsvibe-restore-{}.patch", uuid::Uuid::new_v4()));
+    std::fs::write(&patch_path, patch).map_err(|e| format!("Failed to write patch: {}", e))?;
+    let patch_file = patch_path.to_string_lossy();
+
+    // Fall back to a three-way merge when the branch moved on while archived
+    let result = run_git(worktree_path, &["apply", "--binary", &patch_file])
+        .or_else(|_| run_git(worktree_path, &["apply", "--binary", "--3way", &patch_file]))
+        .map(|_| ())
+        .map_err(|e| format!("Failed to reapply archived changes: {}", e));
+
+    let _ = std::fs::remove_file(&patch_path);
+    result
+}
+
+/// Refuse to touch a workspace while an agent is working in one of its sessions
+pub(crate) async fn ensure_no_running_agent(
+    state: &AppState,
+    pool: &DbPool,
+    workspace_id: &str,
+) -> Result<(), String> {
+    let session_ids: Vec<String> =
+        sqlx::query_scalar("SELECT id FROM sessions WHERE workspace_id = ?")
+            .bind(workspace_id)
+            .fetch_all(pool)
+            .await
+            .map_err(|e| e.to_string())?;
+
+    let turns = state.agent_turns.lock().await;
+    if session_ids
+        .iter()
+        .any(|session_id| turns.contains_key(session_id))
+    {
+        return Err("Stop the agent in this workspace first".to_string());
+    }
+
     Ok(())
 }
 
+/// Whether a worktree has uncommitted changes or untracked files
+pub(crate) fn is_worktree_dirty(worktree_path: &Path) -> Result<bool, String> {
+    let status = run_git(worktree_path, &["status", "--porcelain"])?;
+    Ok(!status.trim().is_empty())
+}
+
+/// Environment variables describing the workspace, passed to repository scripts
+pub(crate) fn workspace_script_env(context: &WorkspaceContext) -> Vec<(&'static str, String)> {
+    let mut envs = vec![(
+        "LETSVIBE_WORKSPACE_PATH",
+        context.worktree_path.to_string_lossy().to_string(),
+    )];
+    if let Some(name) = &context.workspace.directory_name {
+        envs.push(("LETSVIBE_WORKSPACE_NAME", name.clone()));
+        if let Some(port) = workspace_port(name) {
+            envs.push(("LETSVIBE_PORT", port.to_string()));
+        }
+    }
+    if let Some(root_path) = &context.repo.root_path {
+        envs.push(("LETSVIBE_ROOT_PATH", root_path.clone()));
+    }
+    envs
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct FileItem {
     pub name: String,
@@ -541,22 +1807,390 @@ pub async fn read_file_content(
         .join(repo_name)
         .join(&directory_name);
 
-    // Build full file path
-    let full_path = worktree_path.join(&file_path);
-
-    // Security check: ensure the file is within the worktree
-    let canonical_worktree = worktree_path
-        .canonicalize()
-        .map_err(|e| format!("Failed to canonicalize worktree path: {}", e))?;
-    let canonical_file = full_path
-        .canonicalize()
-        .map_err(|e| format!("File not found: {}", e))?;
-
-    if !canonical_file.starts_with(&canonical_worktree) {
-        return Err("Access denied: file is outside workspace".to_string());
-    }
+    let full_path = worktree_file(&worktree_path, &file_path)?;
 
     // Read file content
     std::fs::read_to_string(&full_path)
         .map_err(|e| format!("Failed to read file: {}", e))
 }
+
+/// Resolve a path relative to a worktree, refusing ones that lead outside of it
+pub(crate) fn worktree_file(worktree_path: &Path, file_path: &str) -> Result<PathBuf, String> {
+    let outside = || "Access denied: file is outside workspace".to_string();
+
+    // Absolute paths and `..` would escape before the file even exists
+    let relative = Path::new(file_path);
+    if !relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(outside());
+    }
+    let full_path = worktree_path.join(relative);
+
+    // Security check: ensure symlinks don't lead outside of the worktree either
+    if let Ok(canonical_file) = full_path.canonicalize() {
+        let canonical_worktree = worktree_path
+            .canonicalize()
+            .map_err(|e| format!("Failed to canonicalize worktree path: {}", e))?;
+        if !canonical_file.starts_with(&canonical_worktree) {
+            return Err(outside());
+        }
+    }
+    Ok(full_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_pool;
+    use crate::run_script::RunningScript;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+
+    fn git(dir: &Path, args: &[&str]) -> String {
+        run_git(dir, args).unwrap().trim().to_string()
+    }
+
+    #[test]
+    fn test_resolve_workspace_source() {
+        let dir = std::env::temp_dir().join(format!("letsvibe-source-{}", uuid::Uuid::new_v4()));
+        let origin = dir.join("origin");
+        std::fs::create_dir_all(&origin).unwrap();
+        git(&origin, &["init", "-q", "-b", "main"]);
+        git(
+            &origin,
+            &[
+                "-c",
+                "user.name=a",
+                "-c",
+                "user.email=a@b",
+                "commit",
+                "-q",
+                "--allow-empty",
+                "-m",
+                "init",
+            ],
+        );
+        let init = git(&origin, &["rev-parse", "HEAD"]);
+        git(&origin, &["branch", "feature"]);
+        git(&origin, &["update-ref", "refs/pull/3/head", "HEAD"]);
+        run_git(&dir, &["clone", "-q", &origin.to_string_lossy(), "clone"]).unwrap();
+        let clone = dir.join("clone");
+
+        let resolve = |source| resolve_workspace_source(&clone, "origin", "main", &source);
+        assert_eq!(
+            resolve(WorkspaceSource::RemoteBranch {
+                name: "origin/feature".to_string()
+            })
+            .unwrap(),
+            ResolvedSource {
+                start_point: "origin/feature".to_string(),
+                parent: "origin/feature".to_string(),
+                checkout: SourceCheckout::New {
+                    name: Some("feature".to_string()),
+                    track: true,
+                },
+            }
+        );
+
+        let pull_request = resolve(WorkspaceSource::PullRequest { number: 3 }).unwrap();
+        assert_eq!(pull_request.start_point, "origin/pull/3/head");
+        assert_eq!(git(&clone, &["rev-parse", "origin/pull/3/head"]), init);
+
+        // The clone has main checked out, so the workspace needs a branch of its own
+        assert_eq!(
+            resolve(WorkspaceSource::Branch {
+                name: "main".to_string()
+            })
+            .unwrap(),
+            ResolvedSource {
+                start_point: "main".to_string(),
+                parent: "main".to_string(),
+                checkout: SourceCheckout::New {
+                    name: None,
+                    track: false,
+                },
+            }
+        );
+        git(&clone, &["branch", "local", "origin/feature"]);
+        assert_eq!(
+            resolve(WorkspaceSource::Branch {
+                name: "local".to_string()
+            })
+            .unwrap()
+            .checkout,
+            SourceCheckout::Existing("local".to_string())
+        );
+        assert!(resolve(WorkspaceSource::Branch {
+            name: "nope".to_string()
+        })
+        .is_err());
+
+        let commit = resolve(WorkspaceSource::Commit {
+            sha: init[..8].to_string(),
+        })
+        .unwrap();
+        assert_eq!(commit.parent, init);
+        assert!(resolve(WorkspaceSource::PullRequest { number: 4 }).is_err());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_worktree_file() {
+        let root = std::env::temp_dir().join(format!("letsvibe-file-{}", uuid::Uuid::new_v4()));
+        let worktree = root.join("worktree");
+        std::fs::create_dir_all(worktree.join("src")).unwrap();
+        std::fs::write(worktree.join("src/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(root.join("secret"), "hunter2").unwrap();
+
+        assert_eq!(
+            worktree_file(&worktree, "src/main.rs").unwrap(),
+            worktree.join("src/main.rs")
+        );
+        // Files that don't exist yet are fine as long as they'd be inside
+        assert!(worktree_file(&worktree, "./src/new.rs").is_ok());
+        assert!(worktree_file(&worktree, "../secret").is_err());
+        assert!(worktree_file(&worktree, "src/../../secret").is_err());
+        assert!(worktree_file(&worktree, root.join("secret").to_str().unwrap()).is_err());
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(root.join("secret"), worktree.join("link")).unwrap();
+            assert!(worktree_file(&worktree, "link").is_err());
+        }
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_check_worktree_removable() {
+        let root = std::env::temp_dir().join(format!("letsvibe-remove-{}", uuid::Uuid::new_v4()));
+        let repo = root.join("repo");
+        std::fs::create_dir_all(&repo).unwrap();
+        run_git(&repo, &["init", "-q", "-b", "main"]).unwrap();
+        run_git(
+            &repo,
+            &[
+                "-c",
+                "user.name=t",
+                "-c",
+                "user.email=t@t",
+                "commit",
+                "-q",
+                "--allow-empty",
+                "-m",
+                "init",
+            ],
+        )
+        .unwrap();
+        let worktree = root.join("worktree");
+        let worktree_arg = worktree.to_string_lossy().to_string();
+        run_git(&repo, &["worktree", "add", "-q", "-b", "ws", &worktree_arg]).unwrap();
+
+        check_worktree_removable(&repo, &worktree, false).unwrap();
+
+        // Uncommitted changes only stand in the way of an unforced removal
+        std::fs::write(worktree.join("new.txt"), "new").unwrap();
+        assert!(check_worktree_removable(&repo, &worktree, false).is_err());
+        check_worktree_removable(&repo, &worktree, true).unwrap();
+
+        // A locked worktree can't be removed either way
+        run_git(
+            &repo,
+            &["worktree", "lock", "--reason", "in use", &worktree_arg],
+        )
+        .unwrap();
+        let error = check_worktree_removable(&repo, &worktree, true).unwrap_err();
+        assert!(error.contains("in use"), "{}", error);
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn test_refused_delete_stops_nothing() {
+        let pool = test_pool().await;
+        let state = AppState {
+            db: Arc::new(Mutex::new(Some(pool.clone()))),
+            clones: Arc::new(Mutex::new(HashMap::new())),
+            agent_turns: Arc::new(Mutex::new(HashMap::new())),
+            run_scripts: Arc::new(Mutex::new(HashMap::new())),
+            setup_scripts: Arc::new(Mutex::new(HashMap::new())),
+            terminals: Arc::new(Mutex::new(HashMap::new())),
+        };
+
+        // Neither the workspace nor the repository says which branch to compare with, and
+        // there is no main branch to fall back on
+        let repo_path =
+            std::env::temp_dir().join(format!("letsvibe-missing-{}", uuid::Uuid::new_v4()));
+        sqlx::query("INSERT INTO repos (id, name, root_path) VALUES ('r1', 'app', ?)")
+            .bind(repo_path.to_string_lossy().to_string())
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO workspaces (id, repository_id, directory_name, branch) VALUES ('w1', 'r1', 'oslo', 'oslo')",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let stopped = Arc::new(AtomicBool::new(false));
+        state.run_scripts.lock().await.insert(
+            "w1".to_string(),
+            RunningScript {
+                workspace_id: "w1".to_string(),
+                repository_id: "r1".to_string(),
+                run_id: "run".to_string(),
+                pid: None,
+                stopped: stopped.clone(),
+            },
+        );
+
+        assert!(delete_workspace_with(&state, &pool, "w1", false, true)
+            .await
+            .is_err());
+        assert!(state.run_scripts.lock().await.contains_key("w1"));
+        assert!(!stopped.load(Ordering::SeqCst));
+        let remaining: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM workspaces")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(remaining, 1);
+    }
+
+    #[test]
+    fn test_move_id() {
+        let ids = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            move_id(ids(&["a", "b", "c"]), "c", 0),
+            Some(ids(&["c", "a", "b"]))
+        );
+        assert_eq!(
+            move_id(ids(&["a", "b", "c"]), "a", 1),
+            Some(ids(&["b", "a", "c"]))
+        );
+        assert_eq!(
+            move_id(ids(&["a", "b", "c"]), "a", 10),
+            Some(ids(&["b", "c", "a"]))
+        );
+        assert_eq!(move_id(ids(&["a"]), "x", 0), None);
+    }
+
+    #[tokio::test]
+    async fn test_repo_order() {
+        let pool = test_pool().await;
+        let mut ids = Vec::new();
+        for name in ["zeta", "alpha", "mid"] {
+            let repo = insert_repo(
+                &pool,
+                NewRepo {
+                    name: name.to_string(),
+                    root_path: None,
+                    remote_url: None,
+                    default_branch: None,
+                    remote: None,
+                },
+            )
+            .await
+            .unwrap();
+            ids.push(repo.id);
+        }
+
+        // New repositories are added at the end rather than tying with the others
+        let order = || async {
+            sqlx::query_scalar::<_, String>("SELECT name FROM repos ORDER BY display_order, name")
+                .fetch_all(&pool)
+                .await
+                .unwrap()
+        };
+        assert_eq!(order().await, ["zeta", "alpha", "mid"]);
+
+        let mut tx = pool.begin().await.unwrap();
+        let moved = move_id(ids.clone(), &ids[2], 0).unwrap();
+        rewrite_display_order(&mut tx, "repos", &moved)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+        assert_eq!(order().await, ["mid", "zeta", "alpha"]);
+    }
+
+    #[tokio::test]
+    async fn test_workspace_order() {
+        let pool = test_pool().await;
+        sqlx::query(
+            r#"
+            INSERT INTO workspaces (id, repository_id, updated_at, pinned_at) VALUES
+                ('old', 'r1', '2024-01-01 00:00:00', NULL),
+                ('new', 'r1', '2024-01-03 00:00:00', NULL),
+                ('mid', 'r1', '2024-01-02 00:00:00', NULL),
+                ('pin2', 'r1', '2024-01-01 00:00:00', '2024-02-02 00:00:00'),
+                ('pin1', 'r1', '2024-01-01 00:00:00', '2024-02-01 00:00:00'),
+                ('other', 'r2', '2024-01-05 00:00:00', NULL)
+            "#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let order = |pool: DbPool| async move {
+            let query = format!(
+                "SELECT id FROM workspaces WHERE repository_id = 'r1' ORDER BY {}",
+                WORKSPACE_ORDER
+            );
+            sqlx::query_scalar::<_, String>(&query)
+                .fetch_all(&pool)
+                .await
+                .unwrap()
+        };
+        assert_eq!(
+            order(pool.clone()).await,
+            ["pin1", "pin2", "new", "mid", "old"]
+        );
+
+        // Moving renumbers the whole list; pinned workspaces stay on top
+        move_workspace_in(&pool, "old", 2).await.unwrap();
+        assert_eq!(
+            order(pool.clone()).await,
+            ["pin1", "pin2", "old", "new", "mid"]
+        );
+        move_workspace_in(&pool, "mid", 0).await.unwrap();
+        assert_eq!(
+            order(pool.clone()).await,
+            ["pin1", "pin2", "mid", "old", "new"]
+        );
+
+        // Pinned workspaces can be reordered among themselves
+        move_workspace_in(&pool, "pin2", 0).await.unwrap();
+        assert_eq!(
+            order(pool.clone()).await,
+            ["pin2", "pin1", "mid", "old", "new"]
+        );
+        move_workspace_in(&pool, "pin2", 4).await.unwrap();
+        assert_eq!(
+            order(pool.clone()).await,
+            ["pin1", "pin2", "mid", "old", "new"]
+        );
+
+        // Pinning after a move still puts the workspace below the ones pinned before it
+        set_pinned_in(&pool, "old", true).await.unwrap();
+        assert_eq!(
+            order(pool.clone()).await,
+            ["pin1", "pin2", "old", "mid", "new"]
+        );
+        set_pinned_in(&pool, "old", true).await.unwrap();
+        assert_eq!(
+            order(pool.clone()).await,
+            ["pin1", "pin2", "old", "mid", "new"]
+        );
+        // Unpinned, it's back among the workspaces never moved by hand
+        set_pinned_in(&pool, "old", false).await.unwrap();
+        assert_eq!(
+            order(pool.clone()).await,
+            ["pin1", "pin2", "old", "mid", "new"]
+        );
+        assert!(move_workspace_in(&pool, "missing", 0).await.is_err());
+    }
+}
@@ -0,0 +1,758 @@
+use crate::commands::{get_setting, load_workspace_context, worktree_file};
+use crate::db::models::DiffComment;
+use crate::session::start_turn;
+use crate::{AppState, DbPool};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+use tauri::{AppHandle, State};
+
+/// `diff_comments.state` of a comment waiting to be dealt with
+pub const COMMENT_STATE_OPEN: &str = "open";
+
+/// `diff_comments.state` of a thread the reviewer marked as done
+pub const COMMENT_STATE_RESOLVED: &str = "resolved";
+
+/// `diff_comments.state` of an open thread whose line no longer exists
+pub const COMMENT_STATE_OUTDATED: &str = "outdated";
+
+/// `diff_comments.state` of a thread handed to the agent in a review turn
+pub const COMMENT_STATE_ADDRESSED: &str = "addressed";
+
+/// Setting naming the file, relative to the repository root, that `update_memory`
+/// comments are appended to
+const MEMORY_FILE_SETTING: &str = "memory_file";
+const DEFAULT_MEMORY_FILE: &str = "CLAUDE.md";
+
+/// Lines of code shown on each side of a commented line in review prompts
+const REVIEW_CONTEXT_LINES: usize = 3;
+
+/// Lines kept on each side of the commented line to find it again after edits
+const ANCHOR_CONTEXT_LINES: usize = 2;
+
+/// Where a comment points, stored as JSON in `diff_comments.location`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommentAnchor {
+    /// `new` for lines of the workspace's version of the file, `old` for removed lines
+    pub side: String,
+    pub line_content: String,
+    pub context_before: Vec<String>,
+    pub context_after: Vec<String>,
+}
+
+/// A top-level comment and its replies
+#[derive(Debug, Clone, Serialize)]
+pub struct CommentThread {
+    pub thread_id: String,
+    pub file_path: Option<String>,
+    pub line_number: Option<i64>,
+    pub state: Option<String>,
+    pub comments: Vec<DiffComment>,
+}
+
+/// The comment threads on one file
+#[derive(Debug, Clone, Serialize)]
+pub struct FileComments {
+    pub file_path: String,
+    pub threads: Vec<CommentThread>,
+}
+
+/// Outcome of re-anchoring a workspace's comments
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReanchorResult {
+    pub moved: usize,
+    pub outdated: usize,
+    pub restored: usize,
+}
+
+/// Comment on a line of a workspace's diff, starting a new thread
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn create_diff_comment(
+    state: State<'_, AppState>,
+    workspace_id: String,
+    file_path: String,
+    line_number: i64,
+    body: String,
+    side: Option<String>,
+    author: Option<String>,
+    update_memory: Option<bool>,
+) -> Result<DiffComment, String> {
+    let db = state.db.lock().await;
+    let pool = db.as_ref().ok_or("Database not initialized")?;
+
+    if body.trim().is_empty() {
+        return Err("Comment cannot be empty".to_string());
+    }
+    let context = load_workspace_context(pool, &workspace_id).await?;
+    worktree_file(&context.worktree_path, &file_path)?;
+    let side = side.unwrap_or_else(|| "new".to_string());
+    let anchor = anchor_for_line(&context.worktree_path, &file_path, line_number, &side);
+    let location = serde_json::to_string(&anchor).map_err(|e| e.to_string())?;
+
+    let id = uuid::Uuid::new_v4().to_string();
+    sqlx::query(
+        r#"
+        INSERT INTO diff_comments (id, workspace_id, file_path, line_number, body, state, location,
+                                   created_at, author, thread_id, update_memory)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(&id)
+    .bind(&workspace_id)
+    .bind(&file_path)
+    .bind(line_number)
+    .bind(&body)
+    .bind(COMMENT_STATE_OPEN)
+    .bind(&location)
+    .bind(chrono::Utc::now().timestamp_millis())
+    .bind(&author)
+    .bind(&id)
+    .bind(update_memory.unwrap_or(false))
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to create comment: {}", e))?;
+
+    get_comment(pool, &id).await
+}
+
+/// Reply to a comment, adding to its thread
+#[tauri::command]
+pub async fn reply_to_diff_comment(
+    state: State<'_, AppState>,
+    comment_id: String,
+    body: String,
+    author: Option<String>,
+) -> Result<DiffComment, String> {
+    let db = state.db.lock().await;
+    let pool = db.as_ref().ok_or("Database not initialized")?;
+
+    if body.trim().is_empty() {
+        return Err("Comment cannot be empty".to_string());
+    }
+    let parent = get_comment(pool, &comment_id).await?;
+    let thread_id = parent.thread_id.clone().unwrap_or(parent.id.clone());
+
+    let id = uuid::Uuid::new_v4().to_string();
+    sqlx::query(
+        r#"
+        INSERT INTO diff_comments (id, workspace_id, file_path, line_number, body, state, location,
+                                   created_at, author, thread_id, reply_to_comment_id, update_memory)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, 0)
+        "#,
+    )
+    .bind(&id)
+    .bind(&parent.workspace_id)
+    .bind(&parent.file_path)
+    .bind(parent.line_number)
+    .bind(&body)
+    .bind(&parent.state)
+    .bind(&parent.location)
+    .bind(chrono::Utc::now().timestamp_millis())
+    .bind(&author)
+    .bind(&thread_id)
+    .bind(&parent.id)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to create reply: {}", e))?;
+
+    get_comment(pool, &id).await
+}
+
+/// Change a comment's text, and whether it should be remembered for the repository
+#[tauri::command]
+pub async fn edit_diff_comment(
+    state: State<'_, AppState>,
+    comment_id: String,
+    body: String,
+    update_memory: Option<bool>,
+) -> Result<DiffComment, String> {
+    let db = state.db.lock().await;
+    let pool = db.as_ref().ok_or("Database not initialized")?;
+
+    if body.trim().is_empty() {
+        return Err("Comment cannot be empty".to_string());
+    }
+    let result = sqlx::query(
+        "UPDATE diff_comments SET body = ?, update_memory = COALESCE(?, update_memory) WHERE id = ?",
+    )
+    .bind(&body)
+    .bind(update_memory)
+    .bind(&comment_id)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to update comment: {}", e))?;
+    if result.rows_affected() == 0 {
+        return Err("Comment not found".to_string());
+    }
+
+    get_comment(pool, &comment_id).await
+}
+
+/// Mark a thread as resolved
+#[tauri::command]
+pub async fn resolve_diff_thread(
+    state: State<'_, AppState>,
+    thread_id: String,
+) -> Result<Vec<DiffComment>, String> {
+    let db = state.db.lock().await;
+    let pool = db.as_ref().ok_or("Database not initialized")?;
+
+    set_thread_state(pool, &thread_id, COMMENT_STATE_RESOLVED).await
+}
+
+/// Reopen a resolved or addressed thread
+#[tauri::command]
+pub async fn reopen_diff_thread(
+    state: State<'_, AppState>,
+    thread_id: String,
+) -> Result<Vec<DiffComment>, String> {
+    let db = state.db.lock().await;
+    let pool = db.as_ref().ok_or("Database not initialized")?;
+
+    set_thread_state(pool, &thread_id, COMMENT_STATE_OPEN).await
+}
+
+/// Delete a comment. Deleting the first comment of a thread deletes the whole thread;
+/// replies to a deleted reply move up to its parent.
+#[tauri::command]
+pub async fn delete_diff_comment(
+    state: State<'_, AppState>,
+    comment_id: String,
+) -> Result<u64, String> {
+    let db = state.db.lock().await;
+    let pool = db.as_ref().ok_or("Database not initialized")?;
+
+    let comment = get_comment(pool, &comment_id).await?;
+    let is_root = comment
+        .thread_id
+        .as_deref()
+        .is_none_or(|id| id == comment.id);
+
+    if is_root {
+        let result = sqlx::query("DELETE FROM diff_comments WHERE id = ? OR thread_id = ?")
+            .bind(&comment.id)
+            .bind(&comment.id)
+            .execute(pool)
+            .await
+            .map_err(|e| format!("Failed to delete comment: {}", e))?;
+        return Ok(result.rows_affected());
+    }
+
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+    sqlx::query("UPDATE diff_comments SET reply_to_comment_id = ? WHERE reply_to_comment_id = ?")
+        .bind(&comment.reply_to_comment_id)
+        .bind(&comment.id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+    let result = sqlx::query("DELETE FROM diff_comments WHERE id = ?")
+        .bind(&comment.id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| format!("Failed to delete comment: {}", e))?;
+    tx.commit().await.map_err(|e| e.to_string())?;
+
+    Ok(result.rows_affected())
+}
+
+/// List a workspace's comments grouped by file and thread, after moving them to
+/// follow edits to their files
+#[tauri::command]
+pub async fn list_diff_comments(
+    state: State<'_, AppState>,
+    workspace_id: String,
+    include_resolved: Option<bool>,
+) -> Result<Vec<FileComments>, String> {
+    let db = state.db.lock().await;
+    let pool = db.as_ref().ok_or("Database not initialized")?;
+
+    if let Ok(context) = load_workspace_context(pool, &workspace_id).await {
+        if context.worktree_path.exists() {
+            reanchor_comments(pool, &workspace_id, &context.worktree_path).await?;
+        }
+    }
+
+    let comments: Vec<DiffComment> = sqlx::query_as(
+        "SELECT * FROM diff_comments WHERE workspace_id = ? ORDER BY created_at, rowid",
+    )
+    .bind(&workspace_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let mut threads = group_threads(comments);
+    if !include_resolved.unwrap_or(false) {
+        threads.retain(|t| t.state.as_deref() != Some(COMMENT_STATE_RESOLVED));
+    }
+
+    let mut files: BTreeMap<String, Vec<CommentThread>> = BTreeMap::new();
+    for thread in threads {
+        files
+            .entry(thread.file_path.clone().unwrap_or_default())
+            .or_default()
+            .push(thread);
+    }
+    Ok(files
+        .into_iter()
+        .map(|(file_path, mut threads)| {
+            threads.sort_by_key(|t| t.line_number);
+            FileComments { file_path, threads }
+        })
+        .collect())
+}
+
+/// Move a workspace's open comments to wherever their lines went, marking the
+/// ones whose line is gone as outdated
+#[tauri::command]
+pub async fn reanchor_diff_comments(
+    state: State<'_, AppState>,
+    workspace_id: String,
+) -> Result<ReanchorResult, String> {
+    let db = state.db.lock().await;
+    let pool = db.as_ref().ok_or("Database not initialized")?;
+
+    let context = load_workspace_context(pool, &workspace_id).await?;
+    reanchor_comments(pool, &workspace_id, &context.worktree_path).await
+}
+
+/// A review turn started by `address_review_comments`
+#[derive(Debug, Clone, Serialize)]
+pub struct AddressReviewResult {
+    pub session_id: String,
+    pub turn_id: String,
+    pub thread_ids: Vec<String>,
+    /// Memory file the `update_memory` comments were appended to, if there were any
+    pub memory_file: Option<String>,
+    /// Problems that didn't stop the turn, e.g. a memory file that couldn't be written
+    pub warnings: Vec<String>,
+}
+
+/// Send every open comment of a workspace to the agent as a new turn in its active
+/// session, and mark them as addressed by that turn
+#[tauri::command]
+pub async fn address_review_comments(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    workspace_id: String,
+) -> Result<AddressReviewResult, String> {
+    // The turn outlives this command, so work with a pool handle instead of the lock
+    let pool = state.pool().await?;
+    let context = load_workspace_context(&pool, &workspace_id).await?;
+    let session_id = context
+        .workspace
+        .active_session_id
+        .clone()
+        .ok_or("Workspace has no active session")?;
+
+    reanchor_comments(&pool, &workspace_id, &context.worktree_path).await?;
+    let comments: Vec<DiffComment> = sqlx::query_as(
+        r#"
+        SELECT * FROM diff_comments
+        WHERE workspace_id = ? AND state = ?
+        ORDER BY file_path, line_number, created_at, rowid
+        "#,
+    )
+    .bind(&workspace_id)
+    .bind(COMMENT_STATE_OPEN)
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| e.to_string())?;
+    let threads = group_threads(comments);
+    if threads.is_empty() {
+        return Err("There are no open review comments".to_string());
+    }
+
+    // Comments reopened after an earlier review turn already made it into the memory file
+    let notes: Vec<&DiffComment> = threads
+        .iter()
+        .flat_map(|t| &t.comments)
+        .filter(|c| c.update_memory.unwrap_or(0) != 0 && c.addressed_turn_id.is_none())
+        .collect();
+    let memory_path = match (&context.repo.root_path, notes.is_empty()) {
+        (Some(root_path), false) => {
+            let file = get_setting(&pool, MEMORY_FILE_SETTING)
+                .await?
+                .unwrap_or_else(|| DEFAULT_MEMORY_FILE.to_string());
+            let path = worktree_file(Path::new(root_path), &file)
+                .map_err(|_| format!("Memory file {} is outside the repository", file))?;
+            Some(path)
+        }
+        _ => None,
+    };
+
+    let prompt = build_review_prompt(&threads, &context.worktree_path);
+    let turn_id = start_turn(
+        app,
+        pool.clone(),
+        state.agent_turns.clone(),
+        &session_id,
+        &prompt,
+    )
+    .await?;
+
+    let thread_ids: Vec<String> = threads.iter().map(|t| t.thread_id.clone()).collect();
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+    for thread_id in &thread_ids {
+        sqlx::query(
+            "UPDATE diff_comments SET state = ?, addressed_turn_id = ? WHERE thread_id = ? OR id = ?",
+        )
+        .bind(COMMENT_STATE_ADDRESSED)
+        .bind(&turn_id)
+        .bind(thread_id)
+        .bind(thread_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| format!("Failed to update comments: {}", e))?;
+    }
+    tx.commit().await.map_err(|e| e.to_string())?;
+
+    // The turn is running and the threads are addressed by now, so a failed write
+    // can only be reported
+    let mut warnings = Vec::new();
+    let memory_file = match memory_path {
+        Some(path) => match append_memory_notes(&path, &notes) {
+            Ok(()) => Some(path.to_string_lossy().to_string()),
+            Err(e) => {
+                warnings.push(e);
+                None
+            }
+        },
+        None => None,
+    };
+
+    Ok(AddressReviewResult {
+        session_id,
+        turn_id,
+        thread_ids,
+        memory_file,
+        warnings,
+    })
+}
+
+pub(crate) async fn get_comment(pool: &DbPool, comment_id: &str) -> Result<DiffComment, String> {
+    sqlx::query_as("SELECT * FROM diff_comments WHERE id = ?")
+        .bind(comment_id)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| format!("Comment not found: {}", e))
+}
+
+/// Set the state of every comment in a thread
+pub(crate) async fn set_thread_state(
+    pool: &DbPool,
+    thread_id: &str,
+    state: &str,
+) -> Result<Vec<DiffComment>, String> {
+    let result = sqlx::query("UPDATE diff_comments SET state = ? WHERE thread_id = ? OR id = ?")
+        .bind(state)
+        .bind(thread_id)
+        .bind(thread_id)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Failed to update thread: {}", e))?;
+    if result.rows_affected() == 0 {
+        return Err("Thread not found".to_string());
+    }
+
+    sqlx::query_as(
+        "SELECT * FROM diff_comments WHERE thread_id = ? OR id = ? ORDER BY created_at, rowid",
+    )
+    .bind(thread_id)
+    .bind(thread_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| e.to_string())
+}
+
+/// Group comments into threads, keeping each thread's comments in the given order.
+/// A thread takes its location and state from its first comment.
+pub(crate) fn group_threads(comments: Vec<DiffComment>) -> Vec<CommentThread> {
+    let mut threads: Vec<CommentThread> = Vec::new();
+    for comment in comments {
+        let thread_id = comment.thread_id.clone().unwrap_or(comment.id.clone());
+        match threads.iter_mut().find(|t| t.thread_id == thread_id) {
+            Some(thread) => thread.comments.push(comment),
+            None => threads.push(CommentThread {
+                thread_id,
+                file_path: comment.file_path.clone(),
+                line_number: comment.line_number,
+                state: comment.state.clone(),
+                comments: vec![comment],
+            }),
+        }
+    }
+    threads
+}
+
+/// Re-anchor the open and outdated threads of a workspace against the files on disk
+pub(crate) async fn reanchor_comments(
+    pool: &DbPool,
+    workspace_id: &str,
+    worktree_path: &Path,
+) -> Result<ReanchorResult, String> {
+    let roots: Vec<DiffComment> = sqlx::query_as(
+        r#"
+        SELECT * FROM diff_comments
+        WHERE workspace_id = ? AND (thread_id IS NULL OR thread_id = id) AND state IN (?, ?)
+        "#,
+    )
+    .bind(workspace_id)
+    .bind(COMMENT_STATE_OPEN)
+    .bind(COMMENT_STATE_OUTDATED)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let mut result = ReanchorResult::default();
+    for root in roots {
+        let (Some(file_path), Some(line_number), Some(location)) =
+            (&root.file_path, root.line_number, &root.location)
+        else {
+            continue;
+        };
+        let Ok(anchor) = serde_json::from_str::<CommentAnchor>(location) else {
+            continue;
+        };
+        if anchor.side != "new" || anchor.line_content.is_empty() {
+            continue;
+        }
+
+        let found = read_lines(worktree_path, file_path)
+            .and_then(|lines| find_anchor(&lines, &anchor, line_number));
+        let was_outdated = root.state.as_deref() == Some(COMMENT_STATE_OUTDATED);
+        let (state, line_number) = match found {
+            Some(line) if line == line_number && !was_outdated => continue,
+            Some(line) => {
+                if was_outdated {
+                    result.restored += 1;
+                } else {
+                    result.moved += 1;
+                }
+                (COMMENT_STATE_OPEN, line)
+            }
+            None if was_outdated => continue,
+            None => {
+                result.outdated += 1;
+                (COMMENT_STATE_OUTDATED, line_number)
+            }
+        };
+
+        sqlx::query(
+            "UPDATE diff_comments SET state = ?, line_number = ? WHERE id = ? OR thread_id = ?",
+        )
+        .bind(state)
+        .bind(line_number)
+        .bind(&root.id)
+        .bind(&root.id)
+        .execute(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    }
+
+    Ok(result)
+}
+
+/// Compose the prompt asking the agent to address review threads, quoting the code
+/// around each commented line
+fn build_review_prompt(threads: &[CommentThread], worktree_path: &Path) -> String {
+    let mut prompt = String::from(
+        "Please address the following review comments on your changes. \
+         Line numbers refer to the current version of each file.\n",
+    );
+
+    for thread in threads {
+        let file_path = thread.file_path.as_deref().unwrap_or_default();
+        let side = thread
+            .comments
+            .first()
+            .and_then(|c| c.location.as_deref())
+            .and_then(|location| serde_json::from_str::<CommentAnchor>(location).ok())
+            .map(|anchor| anchor.side)
+            .unwrap_or_else(|| "new".to_string());
+
+        match thread.line_number {
+            Some(line) if side == "new" => {
+                prompt.push_str(&format!("\n## {}:{}\n", file_path, line));
+                if let Some(lines) = read_lines(worktree_path, file_path) {
+                    prompt.push_str(&code_excerpt(&lines, line));
+                }
+            }
+            Some(line) => {
+                prompt.push_str(&format!("\n## {} (removed line {})\n", file_path, line));
+            }
+            None => prompt.push_str(&format!("\n## {}\n", file_path)),
+        }
+
+        for comment in &thread.comments {
+            prompt.push_str(&format!(
+                "- {}: {}\n",
+                comment.author.as_deref().unwrap_or("reviewer"),
+                comment.body.as_deref().unwrap_or_default().trim()
+            ));
+        }
+    }
+
+    prompt
+}
+
+/// Quote the lines around a 1-based line, marking it with `>`
+fn code_excerpt(lines: &[String], line_number: i64) -> String {
+    let Some(index) = usize::try_from(line_number)
+        .ok()
+        .and_then(|n| n.checked_sub(1))
+        .filter(|index| *index < lines.len())
+    else {
+        return String::new();
+    };
+    let start = index.saturating_sub(REVIEW_CONTEXT_LINES);
+    let end = (index + 1 + REVIEW_CONTEXT_LINES).min(lines.len());
+
+    let mut excerpt = String::from("```\n");
+    for (i, line) in lines.iter().enumerate().take(end).skip(start) {
+        let marker = if i == index { ">" } else { " " };
+        excerpt.push_str(&format!("{} {:>4} | {}\n", marker, i + 1, line));
+    }
+    excerpt.push_str("```\n");
+    excerpt
+}
+
+/// Append the comments worth remembering to the repository's memory file
+fn append_memory_notes(path: &Path, notes: &[&DiffComment]) -> Result<(), String> {
+    let mut section = format!(
+        "\n## Review notes ({})\n\n",
+        chrono::Local::now().format("%Y-%m-%d")
+    );
+    for note in notes {
+        section.push_str(&format!(
+            "- {}\n",
+            note.body.as_deref().unwrap_or_default().trim()
+        ));
+    }
+
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(section.as_bytes()))
+        .map_err(|e| format!("Failed to update memory file {}: {}", path.display(), e))
+}
+
+/// Anchor a comment to a line of the workspace's version of a file
+pub(crate) fn anchor_for_line(
+    worktree_path: &Path,
+    file_path: &str,
+    line_number: i64,
+    side: &str,
+) -> CommentAnchor {
+    // Removed lines only exist in the base version, so there is nothing to read them from
+    if side == "new" {
+        read_lines(worktree_path, file_path).and_then(|lines| build_anchor(&lines, line_number))
+    } else {
+        None
+    }
+    .unwrap_or(CommentAnchor {
+        side: side.to_string(),
+        line_content: String::new(),
+        context_before: Vec::new(),
+        context_after: Vec::new(),
+    })
+}
+
+/// Read a file of the worktree, as long as it stays inside of it
+fn read_lines(worktree_path: &Path, file_path: &str) -> Option<Vec<String>> {
+    let path = worktree_file(worktree_path, file_path).ok()?;
+    let content = std::fs::read_to_string(path).ok()?;
+    Some(content.lines().map(str::to_string).collect())
+}
+
+/// Remember the content around a 1-based line so it can be found again
+fn build_anchor(lines: &[String], line_number: i64) -> Option<CommentAnchor> {
+    let index = usize::try_from(line_number).ok()?.checked_sub(1)?;
+    let line_content = lines.get(index)?.clone();
+    let before_start = index.saturating_sub(ANCHOR_CONTEXT_LINES);
+    let after_end = (index + 1 + ANCHOR_CONTEXT_LINES).min(lines.len());
+
+    Some(CommentAnchor {
+        side: "new".to_string(),
+        line_content,
+        context_before: lines[before_start..index].to_vec(),
+        context_after: lines[index + 1..after_end].to_vec(),
+    })
+}
+
+/// Find the 1-based line an anchor now points to. Among the lines matching its content,
+/// the one with the most matching context wins, then the one closest to where it was.
+fn find_anchor(lines: &[String], anchor: &CommentAnchor, line_number: i64) -> Option<i64> {
+    let same = |a: &str, b: &str| a.trim() == b.trim();
+    let score = |index: usize| {
+        let before = anchor
+            .context_before
+            .iter()
+            .rev()
+            .zip(lines[..index].iter().rev())
+            .filter(|(a, b)| same(a, b))
+            .count();
+        let after = anchor
+            .context_after
+            .iter()
+            .zip(lines[index + 1..].iter())
+            .filter(|(a, b)| same(a, b))
+            .count();
+        before + after
+    };
+
+    lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| same(line, &anchor.line_content))
+        .map(|(index, _)| {
+            let line = index as i64 + 1;
+            (score(index), -(line - line_number).abs(), line)
+        })
+        .max()
+        .map(|(_, _, line)| line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_find_anchor_follows_moved_line() {
+        let original = lines("fn a() {\n    let x = 1;\n    x\n}\n");
+        let anchor = build_anchor(&original, 2).unwrap();
+        assert_eq!(anchor.line_content, "    let x = 1;");
+        assert_eq!(anchor.context_before, vec!["fn a() {"]);
+
+        let edited = lines("// header\n\nfn a() {\n    let x = 1;\n    x\n}\n");
+        assert_eq!(find_anchor(&edited, &anchor, 2), Some(4));
+
+        let removed = lines("fn a() {\n    1\n}\n");
+        assert_eq!(find_anchor(&removed, &anchor, 2), None);
+    }
+
+    #[test]
+    fn test_find_anchor_prefers_matching_context() {
+        let original = lines("a\nx = 1\nb\nc\nx = 1\nd\n");
+        let anchor = build_anchor(&original, 5).unwrap();
+        let edited = lines("new\na\nx = 1\nb\nc\nx = 1\nd\n");
+        assert_eq!(find_anchor(&edited, &anchor, 5), Some(6));
+    }
+
+    #[test]
+    fn test_code_excerpt() {
+        let file = lines("1\n2\n3\n4\n5\n6\n7\n8\n9\n");
+        let excerpt = code_excerpt(&file, 2);
+        assert_eq!(
+            excerpt,
+            "```\n     1 | 1\n>    2 | 2\n     3 | 3\n     4 | 4\n     5 | 5\n```\n"
+        );
+        assert_eq!(code_excerpt(&file, 20), "");
+    }
+}
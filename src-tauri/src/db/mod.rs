@@ -20,3 +20,15 @@ pub async fn init_pool(db_path: &Path) -> Result<DbPool, sqlx::Error> {
 
     Ok(pool)
 }
+
+/// An in-memory database with the full schema, for tests
+#[cfg(test)]
+pub async fn test_pool() -> DbPool {
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    schema::init_schema(&pool).await.unwrap();
+    pool
+}
@@ -30,6 +30,10 @@ pub struct DiffComment {
     pub thread_id: Option<String>,
     pub reply_to_comment_id: Option<String>,
     pub update_memory: Option<i64>,
+    /// Agent turn that was asked to address the comment
+    pub addressed_turn_id: Option<String>,
+    /// Id of the comment on the forge, for comments synced with a pull request
+    pub remote_comment_id: Option<i64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -87,6 +91,10 @@ pub struct Session {
     pub agent_type: Option<String>,
     pub title: Option<String>,
     pub context_used_percent: Option<f64>,
+    /// What the agent's turns in this session have cost, in US dollars
+    pub cost_usd: Option<f64>,
+    /// Set when the next turn should fork `claude_session_id` rather than continue it
+    pub fork_session: Option<i64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -122,6 +130,12 @@ pub struct Workspace {
     pub linked_workspace_ids: Option<String>,
     pub notes: Option<String>,
     pub intended_target_branch: Option<String>,
+    pub pr_number: Option<i64>,
+    pub pr_url: Option<String>,
+    /// Combined state of the pull request's checks: `pending`, `success` or `failure`
+    pub pr_checks: Option<String>,
+    /// Position in the repository's workspace list, once the user has reordered it
+    pub display_order: Option<i64>,
     #[sqlx(default)]
     pub git_insertions: Option<i64>,
     #[sqlx(default)]
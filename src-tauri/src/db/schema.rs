@@ -244,5 +244,41 @@ pub async fn init_schema(pool: &SqlitePool) -> Result<(), sqlx::Error> {
     .execute(pool)
     .await?;
 
+    // Columns added after the original schema, for databases created by older versions
+    add_column_if_missing(pool, "diff_comments", "addressed_turn_id", "TEXT").await?;
+    add_column_if_missing(pool, "diff_comments", "remote_comment_id", "INTEGER").await?;
+    add_column_if_missing(pool, "workspaces", "pr_number", "INTEGER").await?;
+    add_column_if_missing(pool, "workspaces", "pr_url", "TEXT").await?;
+    add_column_if_missing(pool, "workspaces", "pr_checks", "TEXT").await?;
+    add_column_if_missing(pool, "sessions", "cost_usd", "REAL").await?;
+    add_column_if_missing(pool, "workspaces", "display_order", "INTEGER").await?;
+    add_column_if_missing(pool, "sessions", "fork_session", "INTEGER").await?;
+
+    Ok(())
+}
+
+/// Add a column to an existing table unless it is already there
+async fn add_column_if_missing(
+    pool: &SqlitePool,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<(), sqlx::Error> {
+    let exists: bool =
+        sqlx::query_scalar("SELECT COUNT(*) > 0 FROM pragma_table_info(?) WHERE name = ?")
+            .bind(table)
+            .bind(column)
+            .fetch_one(pool)
+            .await?;
+
+    if !exists {
+        sqlx::query(&format!(
+            "ALTER TABLE {} ADD COLUMN {} {}",
+            table, column, definition
+        ))
+        .execute(pool)
+        .await?;
+    }
+
     Ok(())
 }
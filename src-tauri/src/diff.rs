@@ -0,0 +1,686 @@
+use crate::checkpoint::write_worktree_tree;
+use crate::commands::load_workspace_context;
+use crate::git::run_git;
+use crate::AppState;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tauri::State;
+
+/// How many bytes git looks at when deciding whether a file is binary
+const BINARY_SNIFF_LEN: usize = 8000;
+
+/// Lines of context around each change unless asked otherwise
+const DEFAULT_CONTEXT_LINES: u32 = 3;
+
+/// Line counts of one changed file
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileDiffStat {
+    pub path: String,
+    /// Previous path of a renamed or copied file
+    pub old_path: Option<String>,
+    pub insertions: i64,
+    pub deletions: i64,
+    /// Binary files have no line counts
+    pub binary: bool,
+}
+
+/// Changes of one kind (committed, uncommitted or untracked) with their totals
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DiffStatGroup {
+    pub insertions: i64,
+    pub deletions: i64,
+    pub binary_files: i64,
+    pub files: Vec<FileDiffStat>,
+}
+
+/// Changes a workspace made since it branched off its base branch
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkspaceDiffStats {
+    pub base_branch: String,
+    /// Commit where the workspace branched off `base_branch`
+    pub merge_base: String,
+    /// Commits on the workspace branch since the merge base
+    pub committed: DiffStatGroup,
+    /// Staged and unstaged changes to tracked files
+    pub uncommitted: DiffStatGroup,
+    /// New files not yet added to git
+    pub untracked: DiffStatGroup,
+    pub insertions: i64,
+    pub deletions: i64,
+    pub binary_files: i64,
+}
+
+impl DiffStatGroup {
+    fn from_files(files: Vec<FileDiffStat>) -> Self {
+        let mut group = DiffStatGroup::default();
+        for file in &files {
+            group.insertions += file.insertions;
+            group.deletions += file.deletions;
+            if file.binary {
+                group.binary_files += 1;
+            }
+        }
+        group.files = files;
+        group
+    }
+}
+
+/// Get a workspace's diff stats against the merge base with its target branch
+#[tauri::command]
+pub async fn get_workspace_diff_stats(
+    state: State<'_, AppState>,
+    workspace_id: String,
+) -> Result<WorkspaceDiffStats, String> {
+    let context = {
+        let db = state.db.lock().await;
+        let pool = db.as_ref().ok_or("Database not initialized")?;
+        load_workspace_context(pool, &workspace_id).await?
+    };
+    if !context.worktree_path.exists() {
+        return Err(format!(
+            "Workspace directory does not exist: {}",
+            context.worktree_path.display()
+        ));
+    }
+
+    compute_diff_stats(&context.worktree_path, &context.base_branch())
+}
+
+/// Split a worktree's changes since it branched off `base_branch` into committed,
+/// uncommitted and untracked files
+pub(crate) fn compute_diff_stats(
+    worktree_path: &Path,
+    base_branch: &str,
+) -> Result<WorkspaceDiffStats, String> {
+    let merge_base = find_merge_base(worktree_path, base_branch)?;
+
+    let committed = run_git(
+        worktree_path,
+        &["diff", "--numstat", "-z", "-M", &merge_base, "HEAD"],
+    )?;
+    let uncommitted = run_git(worktree_path, &["diff", "--numstat", "-z", "-M", "HEAD"])?;
+    let untracked = run_git(
+        worktree_path,
+        &["ls-files", "--others", "--exclude-standard", "-z"],
+    )?;
+
+    let committed = DiffStatGroup::from_files(parse_numstat(&committed));
+    let uncommitted = DiffStatGroup::from_files(parse_numstat(&uncommitted));
+    let untracked = DiffStatGroup::from_files(
+        untracked
+            .split('\0')
+            .filter(|path| !path.is_empty())
+            .map(|path| untracked_file_stat(worktree_path, path))
+            .collect(),
+    );
+
+    let groups = [&committed, &uncommitted, &untracked];
+    Ok(WorkspaceDiffStats {
+        base_branch: base_branch.to_string(),
+        merge_base,
+        insertions: groups.iter().map(|g| g.insertions).sum(),
+        deletions: groups.iter().map(|g| g.deletions).sum(),
+        binary_files: groups.iter().map(|g| g.binary_files).sum(),
+        committed,
+        uncommitted,
+        untracked,
+    })
+}
+
+/// Find the commit where HEAD branched off `base_branch`, preferring the local branch
+/// over a same-named ref elsewhere
+pub(crate) fn find_merge_base(worktree_path: &Path, base_branch: &str) -> Result<String, String> {
+    let local = format!("refs/heads/{}", base_branch);
+    let base = [local.as_str(), base_branch]
+        .into_iter()
+        .find(|candidate| {
+            run_git(
+                worktree_path,
+                &[
+                    "rev-parse",
+                    "--verify",
+                    "--quiet",
+                    &format!("{}^{{commit}}", candidate),
+                ],
+            )
+            .is_ok()
+        })
+        .ok_or_else(|| format!("Base branch not found: {}", base_branch))?;
+
+    Ok(run_git(worktree_path, &["merge-base", "HEAD", base])?
+        .trim()
+        .to_string())
+}
+
+/// Parse the output of `git diff --numstat -z`
+fn parse_numstat(output: &str) -> Vec<FileDiffStat> {
+    let mut files = Vec::new();
+    let mut fields = output.split('\0');
+
+    while let Some(record) = fields.next() {
+        let mut parts = record.splitn(3, '\t');
+        let (Some(added), Some(removed), Some(path)) = (parts.next(), parts.next(), parts.next())
+        else {
+            continue;
+        };
+
+        // Renames and copies leave the path empty and follow with the old and new paths
+        let (path, old_path) = if path.is_empty() {
+            let old_path = fields.next().unwrap_or_default();
+            let new_path = fields.next().unwrap_or_default();
+            (new_path.to_string(), Some(old_path.to_string()))
+        } else {
+            (path.to_string(), None)
+        };
+
+        let binary = added == "-" && removed == "-";
+        files.push(FileDiffStat {
+            path,
+            old_path,
+            insertions: added.parse().unwrap_or(0),
+            deletions: removed.parse().unwrap_or(0),
+            binary,
+        });
+    }
+
+    files
+}
+
+/// Count the lines of a file git doesn't know about yet
+fn untracked_file_stat(worktree_path: &Path, path: &str) -> FileDiffStat {
+    let full_path = worktree_path.join(path);
+    let mut stat = FileDiffStat {
+        path: path.to_string(),
+        old_path: None,
+        insertions: 0,
+        deletions: 0,
+        binary: false,
+    };
+
+    // Git records a symlink as a single line holding its target
+    if full_path.is_symlink() {
+        stat.insertions = 1;
+        return stat;
+    }
+    let Ok(content) = std::fs::read(&full_path) else {
+        return stat;
+    };
+    if content[..content.len().min(BINARY_SNIFF_LEN)].contains(&0) {
+        stat.binary = true;
+        return stat;
+    }
+
+    stat.insertions = count_lines(&content);
+    stat
+}
+
+fn count_lines(content: &[u8]) -> i64 {
+    let newlines = content.iter().filter(|b| **b == b'\n').count() as i64;
+    match content.last() {
+        Some(b'\n') | None => newlines,
+        Some(_) => newlines + 1,
+    }
+}
+
+/// Options for `get_workspace_diff`
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct DiffOptions {
+    /// Ignore changes in whitespace when comparing lines
+    #[serde(default)]
+    pub ignore_whitespace: bool,
+    /// Lines of context around each change
+    pub context_lines: Option<u32>,
+}
+
+/// A workspace's changes since it branched off its base branch, untracked files included
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkspaceDiff {
+    pub base_branch: String,
+    pub merge_base: String,
+    pub files: Vec<FileDiff>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileChangeKind {
+    Added,
+    Deleted,
+    Modified,
+    Renamed,
+    Copied,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileDiff {
+    /// Path after the change, or the removed path for deleted files
+    pub path: String,
+    /// Source of a renamed or copied file
+    pub old_path: Option<String>,
+    pub change: FileChangeKind,
+    /// How similar a renamed or copied file is to its source, in percent
+    pub similarity: Option<u32>,
+    pub binary: bool,
+    pub old_mode: Option<String>,
+    pub new_mode: Option<String>,
+    pub hunks: Vec<DiffHunk>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DiffHunk {
+    pub old_start: u32,
+    pub old_lines: u32,
+    pub new_start: u32,
+    pub new_lines: u32,
+    /// Text after the `@@` range, usually the enclosing function
+    pub header: String,
+    pub lines: Vec<DiffLine>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffLineKind {
+    Context,
+    Added,
+    Removed,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DiffLine {
+    pub kind: DiffLineKind,
+    pub content: String,
+    /// Line number in the old file, for context and removed lines
+    pub old_line: Option<u32>,
+    /// Line number in the new file, for context and added lines
+    pub new_line: Option<u32>,
+    /// Set on the last line of a file that doesn't end with a newline
+    pub no_newline: bool,
+}
+
+/// Get a workspace's diff against the merge base with `base`, or with its target branch
+#[tauri::command]
+pub async fn get_workspace_diff(
+    state: State<'_, AppState>,
+    workspace_id: String,
+    base: Option<String>,
+    options: Option<DiffOptions>,
+) -> Result<WorkspaceDiff, String> {
+    let context = {
+        let db = state.db.lock().await;
+        let pool = db.as_ref().ok_or("Database not initialized")?;
+        load_workspace_context(pool, &workspace_id).await?
+    };
+    if !context.worktree_path.exists() {
+        return Err(format!(
+            "Workspace directory does not exist: {}",
+            context.worktree_path.display()
+        ));
+    }
+
+    let base_branch = base.unwrap_or_else(|| context.base_branch());
+    compute_diff(
+        &context.worktree_path,
+        &base_branch,
+        &options.unwrap_or_default(),
+    )
+}
+
+/// Diff the worktree as it is on disk, untracked files included, against the merge
+/// base with `base_branch`
+pub(crate) fn compute_diff(
+    worktree_path: &Path,
+    base_branch: &str,
+    options: &DiffOptions,
+) -> Result<WorkspaceDiff, String> {
+    let merge_base = find_merge_base(worktree_path, base_branch)?;
+    let head = run_git(worktree_path, &["rev-parse", "HEAD"])?;
+    let tree = write_worktree_tree(worktree_path, head.trim())?;
+
+    Ok(WorkspaceDiff {
+        base_branch: base_branch.to_string(),
+        merge_base: merge_base.clone(),
+        files: diff_trees(worktree_path, &merge_base, &tree, options)?,
+    })
+}
+
+/// Diff two commits or trees of the repository `worktree_path` belongs to
+pub(crate) fn diff_trees(
+    worktree_path: &Path,
+    from: &str,
+    to: &str,
+    options: &DiffOptions,
+) -> Result<Vec<FileDiff>, String> {
+    let context = format!(
+        "-U{}",
+        options.context_lines.unwrap_or(DEFAULT_CONTEXT_LINES)
+    );
+    let mut args = vec![
+        "-c",
+        "core.quotePath=false",
+        "diff",
+        "--no-color",
+        "--no-ext-diff",
+        "-M",
+        "-C",
+        &context,
+    ];
+    if options.ignore_whitespace {
+        args.push("--ignore-all-space");
+    }
+    args.push(from);
+    args.push(to);
+
+    let output = run_git(worktree_path, &args)?;
+    Ok(parse_unified_diff(&output))
+}
+
+/// Parse the output of `git diff` into files and hunks
+pub(crate) fn parse_unified_diff(output: &str) -> Vec<FileDiff> {
+    let mut files = Vec::new();
+    let mut current: Option<FileDiff> = None;
+    let (mut old_line, mut new_line) = (0, 0);
+
+    for line in output.split('\n') {
+        if let Some(rest) = line.strip_prefix("diff --git ") {
+            files.extend(current.take());
+            current = Some(FileDiff {
+                path: path_from_git_header(rest),
+                old_path: None,
+                change: FileChangeKind::Modified,
+                similarity: None,
+                binary: false,
+                old_mode: None,
+                new_mode: None,
+                hunks: Vec::new(),
+            });
+            continue;
+        }
+        let Some(file) = current.as_mut() else {
+            continue;
+        };
+
+        if line.starts_with("@@") {
+            if let Some(hunk) = parse_hunk_header(line) {
+                old_line = hunk.old_start;
+                new_line = hunk.new_start;
+                file.hunks.push(hunk);
+            }
+            continue;
+        }
+
+        // Once the hunks start, every line belongs to them until the next file
+        if let Some(hunk) = file.hunks.last_mut() {
+            let (kind, content) = match line.chars().next() {
+                Some('+') => (DiffLineKind::Added, &line[1..]),
+                Some('-') => (DiffLineKind::Removed, &line[1..]),
+                Some(' ') => (DiffLineKind::Context, &line[1..]),
+                Some('\\') => {
+                    if let Some(last) = hunk.lines.last_mut() {
+                        last.no_newline = true;
+                    }
+                    continue;
+                }
+                // The trailing newline of the output
+                None => continue,
+                Some(_) => (DiffLineKind::Context, line),
+            };
+            let (old, new) = match kind {
+                DiffLineKind::Added => (None, Some(new_line)),
+                DiffLineKind::Removed => (Some(old_line), None),
+                DiffLineKind::Context => (Some(old_line), Some(new_line)),
+            };
+            if old.is_some() {
+                old_line += 1;
+            }
+            if new.is_some() {
+                new_line += 1;
+            }
+            hunk.lines.push(DiffLine {
+                kind,
+                content: content.to_string(),
+                old_line: old,
+                new_line: new,
+                no_newline: false,
+            });
+            continue;
+        }
+
+        if let Some(mode) = line.strip_prefix("new file mode ") {
+            file.change = FileChangeKind::Added;
+            file.new_mode = Some(mode.to_string());
+        } else if let Some(mode) = line.strip_prefix("deleted file mode ") {
+            file.change = FileChangeKind::Deleted;
+            file.old_mode = Some(mode.to_string());
+        } else if let Some(mode) = line.strip_prefix("old mode ") {
+            file.old_mode = Some(mode.to_string());
+        } else if let Some(mode) = line.strip_prefix("new mode ") {
+            file.new_mode = Some(mode.to_string());
+        } else if let Some(similarity) = line.strip_prefix("similarity index ") {
+            file.similarity = similarity.trim_end_matches('%').parse().ok();
+        } else if let Some(path) = line.strip_prefix("rename from ") {
+            file.change = FileChangeKind::Renamed;
+            file.old_path = Some(unquote_path(path));
+        } else if let Some(path) = line.strip_prefix("rename to ") {
+            file.path = unquote_path(path);
+        } else if let Some(path) = line.strip_prefix("copy from ") {
+            file.change = FileChangeKind::Copied;
+            file.old_path = Some(unquote_path(path));
+        } else if let Some(path) = line.strip_prefix("copy to ") {
+            file.path = unquote_path(path);
+        } else if line.starts_with("Binary files ") || line == "GIT binary patch" {
+            file.binary = true;
+        } else if let Some(path) = line.strip_prefix("+++ ") {
+            // Paths with spaces get a trailing tab
+            if let Some(path) = unquote_path(path.trim_end_matches('\t')).strip_prefix("b/") {
+                file.path = path.to_string();
+            }
+        } else if let Some(path) = line.strip_prefix("--- ") {
+            if file.change == FileChangeKind::Deleted {
+                if let Some(path) = unquote_path(path.trim_end_matches('\t')).strip_prefix("a/") {
+                    file.path = path.to_string();
+                }
+            }
+        }
+    }
+
+    files.extend(current);
+    files
+}
+
+/// Parse a hunk header like `@@ -10,7 +10,8 @@ fn main() {`
+pub(crate) fn parse_hunk_header(line: &str) -> Option<DiffHunk> {
+    let rest = line.strip_prefix("@@ -")?;
+    let (ranges, header) = rest.split_once(" @@")?;
+    let (old, new) = ranges.split_once(" +")?;
+    let (old_start, old_lines) = parse_hunk_range(old)?;
+    let (new_start, new_lines) = parse_hunk_range(new)?;
+
+    Some(DiffHunk {
+        old_start,
+        old_lines,
+        new_start,
+        new_lines,
+        header: header.trim().to_string(),
+        lines: Vec::new(),
+    })
+}
+
+/// Parse `start,count`, where a missing count means a single line
+fn parse_hunk_range(range: &str) -> Option<(u32, u32)> {
+    match range.split_once(',') {
+        Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+        None => Some((range.parse().ok()?, 1)),
+    }
+}
+
+/// Take the path out of `a/<path> b/<path>`. Renames and copies get their
+/// paths from the extended headers instead.
+fn path_from_git_header(rest: &str) -> String {
+    if let Some(quoted) = rest.strip_prefix('"') {
+        let end = quoted.find("\" ").map(|i| i + 2).unwrap_or(rest.len());
+        let path = unquote_path(&rest[..end]);
+        return path.strip_prefix("a/").unwrap_or(&path).to_string();
+    }
+    // Both sides are the same path, so it takes up half of what's left
+    if rest.len() >= 5 {
+        let len = (rest.len() - 5) / 2;
+        if let Some(path) = rest.get(2..2 + len) {
+            if rest == format!("a/{} b/{}", path, path) {
+                return path.to_string();
+            }
+        }
+    }
+    rest.rsplit_once(" b/")
+        .map(|(_, path)| path.to_string())
+        .unwrap_or_else(|| rest.to_string())
+}
+
+/// Undo git's C-style quoting of paths with unusual characters
+fn unquote_path(path: &str) -> String {
+    let Some(inner) = path
+        .strip_prefix('"')
+        .and_then(|path| path.strip_suffix('"'))
+    else {
+        return path.to_string();
+    };
+
+    let mut bytes = Vec::new();
+    let mut chars = inner.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buffer = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('n') => bytes.push(b'\n'),
+            Some('t') => bytes.push(b'\t'),
+            Some(digit @ '0'..='7') => {
+                // Octal escapes encode the raw bytes of non-ASCII characters
+                let mut value = digit.to_digit(8).unwrap_or(0);
+                for _ in 0..2 {
+                    if let Some(d) = chars.peek().and_then(|d| d.to_digit(8)) {
+                        value = value * 8 + d;
+                        chars.next();
+                    }
+                }
+                bytes.push(value as u8);
+            }
+            Some(other) => {
+                let mut buffer = [0; 4];
+                bytes.extend_from_slice(other.encode_utf8(&mut buffer).as_bytes());
+            }
+            None => bytes.push(b'\\'),
+        }
+    }
+    String::from_utf8_lossy(&bytes).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_numstat() {
+        let output = [
+            "3\t1\tsrc/main.rs",
+            "-\t-\tlogo.png",
+            "5\t0\t",
+            "old.rs",
+            "new.rs",
+            "",
+        ]
+        .join("\0");
+        let files = parse_numstat(&output);
+        assert_eq!(files.len(), 3);
+        assert_eq!(files[0].path, "src/main.rs");
+        assert_eq!((files[0].insertions, files[0].deletions), (3, 1));
+        assert!(files[1].binary);
+        assert_eq!(files[2].path, "new.rs");
+        assert_eq!(files[2].old_path.as_deref(), Some("old.rs"));
+        assert_eq!(files[2].insertions, 5);
+    }
+
+    #[test]
+    fn test_count_lines() {
+        assert_eq!(count_lines(b""), 0);
+        assert_eq!(count_lines(b"a\nb\n"), 2);
+        assert_eq!(count_lines(b"a\nb"), 2);
+    }
+
+    #[test]
+    fn test_parse_unified_diff() {
+        let output = "\
+diff --git a/src/lib.rs b/src/lib.rs
+index 1111111..2222222 100644
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@ mod a;
+ one
+-two
++TWO
+ three
+\\ No newline at end of file
+diff --git a/old name.txt b/new name.txt
+similarity index 90%
+rename from old name.txt
+rename to new name.txt
+--- a/old name.txt\t
++++ b/new name.txt\t
+@@ -1 +1 @@
+-x
++y
+diff --git a/run.sh b/run.sh
+old mode 100644
+new mode 100755
+diff --git a/logo.png b/logo.png
+new file mode 100644
+index 0000000..3333333
+Binary files /dev/null and b/logo.png differ
+diff --git a/gone.txt b/gone.txt
+deleted file mode 100644
+index 4444444..0000000
+--- a/gone.txt
++++ /dev/null
+@@ -1 +0,0 @@
+--- not a header
+";
+        let files = parse_unified_diff(output);
+        assert_eq!(files.len(), 5);
+
+        let lib = &files[0];
+        assert_eq!(lib.path, "src/lib.rs");
+        assert_eq!(lib.change, FileChangeKind::Modified);
+        let hunk = &lib.hunks[0];
+        assert_eq!((hunk.old_start, hunk.new_start, hunk.new_lines), (1, 1, 3));
+        assert_eq!(hunk.header, "mod a;");
+        assert_eq!(hunk.lines.len(), 4);
+        assert_eq!(hunk.lines[1].kind, DiffLineKind::Removed);
+        assert_eq!(hunk.lines[1].old_line, Some(2));
+        assert_eq!(hunk.lines[2].content, "TWO");
+        assert_eq!(hunk.lines[2].new_line, Some(2));
+        assert_eq!(hunk.lines[3].old_line, Some(3));
+        assert!(hunk.lines[3].no_newline);
+
+        assert_eq!(files[1].change, FileChangeKind::Renamed);
+        assert_eq!(files[1].path, "new name.txt");
+        assert_eq!(files[1].old_path.as_deref(), Some("old name.txt"));
+        assert_eq!(files[1].similarity, Some(90));
+
+        assert_eq!(files[2].old_mode.as_deref(), Some("100644"));
+        assert_eq!(files[2].new_mode.as_deref(), Some("100755"));
+
+        assert_eq!(files[3].change, FileChangeKind::Added);
+        assert!(files[3].binary);
+
+        assert_eq!(files[4].change, FileChangeKind::Deleted);
+        assert_eq!(files[4].path, "gone.txt");
+        assert_eq!(files[4].hunks[0].lines[0].content, "-- not a header");
+    }
+
+    #[test]
+    fn test_unquote_path() {
+        assert_eq!(unquote_path("plain.txt"), "plain.txt");
+        assert_eq!(unquote_path("\"a\\tb\\\"c\""), "a\tb\"c");
+        assert_eq!(unquote_path("\"caf\\303\\251\""), "café");
+    }
+}
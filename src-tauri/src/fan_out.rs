@@ -0,0 +1,381 @@
+use crate::commands::{
+    create_workspace_with, WorkspaceOptions, WorkspaceSource, WORKSPACE_STATE_ARCHIVED,
+};
+use crate::db::models::{Session, Workspace};
+use crate::links::link_group;
+use crate::session::{insert_session, start_turn, RunningTurns, STATUS_ERROR};
+use crate::setup::{WORKSPACE_STATE_SETTING_UP, WORKSPACE_STATE_SETUP_FAILED};
+use crate::{AppState, DbPool};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, State};
+
+/// How often a fan-out checks on its workspaces' setup scripts and agents
+const FAN_OUT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How one of the agents of a fan-out is run
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FanOutVariant {
+    pub model: Option<String>,
+    pub agent_type: Option<String>,
+    pub permission_mode: Option<String>,
+    /// Title of the variant's session
+    pub title: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VariantStatus {
+    /// Waiting for the workspace's setup script before starting the agent
+    SettingUp,
+    Running,
+    Finished,
+    Failed,
+}
+
+/// Where one variant of a fan-out is at
+#[derive(Debug, Clone, Serialize)]
+pub struct VariantProgress {
+    /// Position of the variant in the `variants` the fan-out was started with
+    pub index: usize,
+    /// Unset when creating the variant's workspace failed
+    pub workspace_id: Option<String>,
+    /// Unset when creating the variant's workspace or session failed
+    pub session_id: Option<String>,
+    pub status: VariantStatus,
+    pub error: Option<String>,
+}
+
+/// Event emitted as `fan-out-progress` whenever a variant of a fan-out moves on
+#[derive(Debug, Clone, Serialize)]
+pub struct FanOutProgressEvent {
+    pub fan_out_id: String,
+    pub variants: Vec<VariantProgress>,
+    pub finished: usize,
+    pub failed: usize,
+    /// Set on the last event, once every variant finished or failed
+    pub done: bool,
+}
+
+/// Result of `fan_out_task`
+#[derive(Debug, Clone, Serialize)]
+pub struct FanOutResult {
+    pub fan_out_id: String,
+    /// Every variant, including ones that couldn't be set up
+    pub variants: Vec<VariantProgress>,
+    pub workspaces: Vec<Workspace>,
+    pub sessions: Vec<Session>,
+    pub warnings: Vec<String>,
+}
+
+/// Give the same task to several agents: every variant gets its own workspace from
+/// the main branch and a session running its model, agent and permission mode. The
+/// workspaces are linked for comparison. Each agent starts once its workspace's setup
+/// script is done, and `fan-out-progress` events follow them until all have finished.
+/// A variant whose workspace or session can't be created is reported as failed while
+/// the others go ahead; the command only fails when none could be created.
+#[tauri::command]
+pub async fn fan_out_task(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    repository_id: String,
+    prompt: String,
+    variants: Vec<FanOutVariant>,
+) -> Result<FanOutResult, String> {
+    if prompt.trim().is_empty() {
+        return Err("The task is empty".to_string());
+    }
+    if variants.is_empty() {
+        return Err("Add at least one variant to run the task with".to_string());
+    }
+
+    // Creating worktrees takes a while, so work with a pool handle instead of the lock
+    let pool = state.pool().await?;
+
+    let mut workspaces = Vec::new();
+    let mut sessions = Vec::new();
+    let mut warnings = Vec::new();
+    let mut progress = Vec::new();
+    for (index, variant) in variants.iter().enumerate() {
+        let mut variant_progress = VariantProgress {
+            index,
+            workspace_id: None,
+            session_id: None,
+            status: VariantStatus::SettingUp,
+            error: None,
+        };
+        match create_workspace_with(
+            &app,
+            &pool,
+            &repository_id,
+            WorkspaceSource::MainBranch,
+            WorkspaceOptions::default(),
+        )
+        .await
+        {
+            Ok(created) => {
+                variant_progress.workspace_id = Some(created.workspace.id.clone());
+                match insert_session(
+                    &pool,
+                    &created.workspace.id,
+                    variant.model.as_deref(),
+                    variant.permission_mode.as_deref(),
+                    variant.agent_type.as_deref(),
+                    variant.title.as_deref(),
+                )
+                .await
+                {
+                    Ok(session) => {
+                        variant_progress.session_id = Some(session.id.clone());
+                        sessions.push(session);
+                    }
+                    Err(e) => fail(&mut variant_progress, e),
+                }
+                workspaces.push(created.workspace);
+                warnings.extend(created.warnings);
+            }
+            Err(e) => fail(&mut variant_progress, e),
+        }
+        progress.push(variant_progress);
+    }
+    if workspaces.is_empty() {
+        let errors: Vec<String> = progress.into_iter().filter_map(|v| v.error).collect();
+        return Err(errors.join("\n"));
+    }
+
+    if workspaces.len() > 1 {
+        let ids: Vec<String> = workspaces.iter().map(|w| w.id.clone()).collect();
+        link_group(&pool, &ids).await?;
+    }
+
+    let fan_out_id = uuid::Uuid::new_v4().to_string();
+    let turns = state.agent_turns.clone();
+    tauri::async_runtime::spawn(drive_fan_out(
+        app,
+        pool.clone(),
+        turns,
+        fan_out_id.clone(),
+        prompt,
+        progress.clone(),
+    ));
+
+    let mut linked = Vec::new();
+    for workspace in &workspaces {
+        linked.push(
+            sqlx::query_as("SELECT * FROM workspaces WHERE id = ?")
+                .bind(&workspace.id)
+                .fetch_one(&pool)
+                .await
+                .map_err(|e| e.to_string())?,
+        );
+    }
+
+    Ok(FanOutResult {
+        fan_out_id,
+        variants: progress,
+        workspaces: linked,
+        sessions,
+        warnings,
+    })
+}
+
+/// Start each variant's agent once its workspace is set up and report progress until
+/// every variant is done
+async fn drive_fan_out(
+    app: AppHandle,
+    pool: DbPool,
+    turns: RunningTurns,
+    fan_out_id: String,
+    prompt: String,
+    mut variants: Vec<VariantProgress>,
+) {
+    let emit = |variants: &[VariantProgress]| {
+        let count = |status| variants.iter().filter(|v| v.status == status).count();
+        let finished = count(VariantStatus::Finished);
+        let failed = count(VariantStatus::Failed);
+        let _ = app.emit(
+            "fan-out-progress",
+            FanOutProgressEvent {
+                fan_out_id: fan_out_id.clone(),
+                variants: variants.to_vec(),
+                finished,
+                failed,
+                done: finished + failed == variants.len(),
+            },
+        );
+    };
+    emit(&variants);
+
+    while !variants.iter().all(is_done) {
+        tokio::time::sleep(FAN_OUT_POLL_INTERVAL).await;
+
+        let mut changed = false;
+        for variant in variants.iter_mut() {
+            let before = variant.status;
+            match update_variant(&pool, &turns, variant).await {
+                Ok(Some(session_id)) => {
+                    match start_turn(
+                        app.clone(),
+                        pool.clone(),
+                        turns.clone(),
+                        &session_id,
+                        &prompt,
+                    )
+                    .await
+                    {
+                        Ok(_) => variant.status = VariantStatus::Running,
+                        Err(e) => fail(variant, e),
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => fail(variant, e),
+            }
+            changed |= variant.status != before;
+        }
+
+        if changed {
+            emit(&variants);
+        }
+    }
+}
+
+/// Move a variant on once its setup script or agent is done. Returns the session to
+/// start the agent in once the variant's workspace is ready.
+async fn update_variant(
+    pool: &DbPool,
+    turns: &RunningTurns,
+    variant: &mut VariantProgress,
+) -> Result<Option<String>, String> {
+    // Variants that couldn't be created start out failed
+    let (Some(workspace_id), Some(session_id)) = (&variant.workspace_id, &variant.session_id)
+    else {
+        return Ok(None);
+    };
+    match variant.status {
+        VariantStatus::SettingUp => {
+            let state: Option<String> =
+                sqlx::query_scalar("SELECT state FROM workspaces WHERE id = ?")
+                    .bind(workspace_id)
+                    .fetch_one(pool)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            match state.as_deref() {
+                Some(WORKSPACE_STATE_SETTING_UP) => Ok(None),
+                Some(WORKSPACE_STATE_SETUP_FAILED) => {
+                    Err("The workspace's setup script failed".to_string())
+                }
+                Some(WORKSPACE_STATE_ARCHIVED) => Err("The workspace was archived".to_string()),
+                _ => Ok(Some(session_id.clone())),
+            }
+        }
+        VariantStatus::Running => {
+            if turns.lock().await.contains_key(session_id) {
+                return Ok(None);
+            }
+            let status: Option<String> =
+                sqlx::query_scalar("SELECT status FROM sessions WHERE id = ?")
+                    .bind(session_id)
+                    .fetch_one(pool)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            if status.as_deref() == Some(STATUS_ERROR) {
+                return Err("The agent failed".to_string());
+            }
+            variant.status = VariantStatus::Finished;
+            Ok(None)
+        }
+        VariantStatus::Finished | VariantStatus::Failed => Ok(None),
+    }
+}
+
+fn fail(variant: &mut VariantProgress, error: String) {
+    variant.status = VariantStatus::Failed;
+    variant.error = Some(error);
+}
+
+fn is_done(variant: &VariantProgress) -> bool {
+    matches!(
+        variant.status,
+        VariantStatus::Finished | VariantStatus::Failed
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_pool;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+
+    fn variant(status: VariantStatus) -> VariantProgress {
+        VariantProgress {
+            index: 0,
+            workspace_id: Some("w1".to_string()),
+            session_id: Some("s1".to_string()),
+            status,
+            error: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_update_variant() {
+        let pool = test_pool().await;
+        let turns: RunningTurns = Arc::new(Mutex::new(HashMap::new()));
+        sqlx::query("INSERT INTO workspaces (id, state) VALUES ('w1', 'setting_up')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO sessions (id, workspace_id, status) VALUES ('s1', 'w1', 'idle')")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        // The agent waits for the setup script
+        let mut setting_up = variant(VariantStatus::SettingUp);
+        let start = update_variant(&pool, &turns, &mut setting_up)
+            .await
+            .unwrap();
+        assert_eq!(start, None);
+        sqlx::query("UPDATE workspaces SET state = 'active' WHERE id = 'w1'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let start = update_variant(&pool, &turns, &mut setting_up)
+            .await
+            .unwrap();
+        assert_eq!(start.as_deref(), Some("s1"));
+
+        // A variant that couldn't be created has nothing to start
+        let mut never_created = VariantProgress {
+            workspace_id: None,
+            session_id: None,
+            ..variant(VariantStatus::Failed)
+        };
+        let start = update_variant(&pool, &turns, &mut never_created)
+            .await
+            .unwrap();
+        assert_eq!(start, None);
+
+        sqlx::query("UPDATE workspaces SET state = 'setup_failed' WHERE id = 'w1'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let mut failed_setup = variant(VariantStatus::SettingUp);
+        assert!(update_variant(&pool, &turns, &mut failed_setup)
+            .await
+            .is_err());
+
+        // A turn that's no longer running finished, unless it left the session in error
+        let mut running = variant(VariantStatus::Running);
+        update_variant(&pool, &turns, &mut running).await.unwrap();
+        assert_eq!(running.status, VariantStatus::Finished);
+
+        sqlx::query("UPDATE sessions SET status = 'error' WHERE id = 's1'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let mut errored = variant(VariantStatus::Running);
+        assert!(update_variant(&pool, &turns, &mut errored).await.is_err());
+    }
+}
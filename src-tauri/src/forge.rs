@@ -0,0 +1,940 @@
+use crate::commands::{
+    get_setting, load_workspace_context, WorkspaceContext, WORKSPACE_STATE_ARCHIVED,
+};
+use crate::diff::find_merge_base;
+use crate::git_ops::{git, push_branch};
+use crate::review_sync::sync_open_pull_requests;
+use crate::session::{ask_agent, truncate_for_prompt};
+use crate::{AppState, DbPool};
+use async_trait::async_trait;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, State};
+
+/// Settings key for the forge API base URL, e.g. a GitHub Enterprise `https://host/api/v3`
+pub const FORGE_API_URL_SETTING: &str = "forge_api_url";
+/// Settings key for the forge API token
+pub const FORGE_TOKEN_SETTING: &str = "forge_token";
+const DEFAULT_GITHUB_API_URL: &str = "https://api.github.com";
+/// Largest page GitHub returns for list endpoints
+const GITHUB_PAGE_SIZE: usize = 100;
+
+/// `workspaces.state` while the workspace's pull request is open
+pub const WORKSPACE_STATE_PR_OPEN: &str = "pr_open";
+/// `workspaces.state` once the workspace's changes are merged
+pub const WORKSPACE_STATE_MERGED: &str = "merged";
+/// `workspaces.state` when the pull request was closed without merging
+pub const WORKSPACE_STATE_PR_CLOSED: &str = "pr_closed";
+
+const PR_STATUS_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Characters of session history and diff given to the agent when it writes a PR description
+const PR_DESCRIPTION_CONTEXT_LIMIT: usize = 20_000;
+
+const PR_DESCRIPTION_PROMPT: &str = "Write a pull request title and description for the \
+changes on this branch, using the conversation that produced them and the commits below. \
+Put the title on the first line, under 72 characters, then a blank line, then the description \
+in Markdown. Reply with the title and description only.";
+
+/// A repository on a forge, identified as `owner/name`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForgeRepo {
+    pub owner: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PullRequestState {
+    Open,
+    Merged,
+    Closed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChecksState {
+    Pending,
+    Success,
+    Failure,
+}
+
+impl ChecksState {
+    fn as_str(self) -> &'static str {
+        match self {
+            ChecksState::Pending => "pending",
+            ChecksState::Success => "success",
+            ChecksState::Failure => "failure",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PullRequest {
+    pub number: i64,
+    pub url: String,
+    pub state: PullRequestState,
+    /// Combined state of the checks on the head commit; `None` when there are none
+    pub checks: Option<ChecksState>,
+}
+
+/// A review comment on a pull request's diff
+#[derive(Debug, Clone, Serialize)]
+pub struct ReviewComment {
+    pub id: i64,
+    /// The comment this one replies to; replies always point at the thread's first comment
+    pub in_reply_to: Option<i64>,
+    pub path: String,
+    /// Line in the current diff, `None` once the line no longer exists
+    pub line: Option<i64>,
+    /// Line the comment was originally made on
+    pub original_line: Option<i64>,
+    /// `new` for lines of the branch's version of the file, `old` for removed lines
+    pub side: String,
+    pub body: String,
+    pub author: Option<String>,
+    pub url: String,
+    /// Milliseconds since the epoch
+    pub created_at: i64,
+}
+
+/// What to open a pull request with
+#[derive(Debug, Clone)]
+pub struct NewPullRequest {
+    pub title: String,
+    pub body: String,
+    pub head: String,
+    pub base: String,
+    pub draft: bool,
+}
+
+/// A code host that pull requests can be opened on
+#[async_trait]
+pub trait Forge: Send + Sync {
+    /// Open a pull request, or return the open one if the branch already has one
+    async fn create_pull_request(
+        &self,
+        repo: &ForgeRepo,
+        request: &NewPullRequest,
+    ) -> Result<PullRequest, String>;
+
+    /// Get a pull request's state and the state of its checks
+    async fn get_pull_request(&self, repo: &ForgeRepo, number: i64) -> Result<PullRequest, String>;
+
+    /// List all review comments on a pull request, oldest first
+    async fn list_review_comments(
+        &self,
+        repo: &ForgeRepo,
+        number: i64,
+    ) -> Result<Vec<ReviewComment>, String>;
+
+    /// Reply to the thread started by a review comment
+    async fn reply_to_review_comment(
+        &self,
+        repo: &ForgeRepo,
+        number: i64,
+        comment_id: i64,
+        body: &str,
+    ) -> Result<ReviewComment, String>;
+}
+
+/// A failed GitHub API request
+struct ApiError {
+    /// Unset when GitHub couldn't be reached at all
+    status: Option<reqwest::StatusCode>,
+    message: String,
+}
+
+impl From<ApiError> for String {
+    fn from(error: ApiError) -> Self {
+        error.message
+    }
+}
+
+/// The GitHub REST API
+pub struct GitHubForge {
+    client: reqwest::Client,
+    api_url: String,
+    token: Option<String>,
+}
+
+impl GitHubForge {
+    pub fn new(api_url: &str, token: Option<String>) -> Self {
+        GitHubForge {
+            client: reqwest::Client::new(),
+            api_url: api_url.trim_end_matches('/').to_string(),
+            token,
+        }
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let request = self
+            .client
+            .request(method, format!("{}{}", self.api_url, path))
+            .header("Accept", "application/vnd.github+json")
+            .header("User-Agent", "letsvibe");
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<Value, ApiError> {
+        let response = request.send().await.map_err(|e| ApiError {
+            status: None,
+            message: format!("Failed to reach GitHub: {}", e),
+        })?;
+        let status = response.status();
+        let body: Value = response.json().await.unwrap_or(Value::Null);
+        if status.is_success() {
+            Ok(body)
+        } else {
+            Err(ApiError {
+                status: Some(status),
+                message: format!(
+                    "GitHub API error ({}): {}",
+                    status,
+                    api_error_message(&body)
+                ),
+            })
+        }
+    }
+
+    async fn find_open_pull_request(
+        &self,
+        repo: &ForgeRepo,
+        head: &str,
+    ) -> Result<Option<PullRequest>, String> {
+        let path = format!("/repos/{}/{}/pulls", repo.owner, repo.name);
+        let head = format!("{}:{}", repo.owner, head);
+        let pulls = self
+            .send(
+                self.request(reqwest::Method::GET, &path)
+                    .query(&[("head", head.as_str()), ("state", "open")]),
+            )
+            .await?;
+        match pulls.as_array().and_then(|pulls| pulls.first()) {
+            Some(pull) => Ok(Some(self.with_checks(repo, pull).await?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn with_checks(&self, repo: &ForgeRepo, pull: &Value) -> Result<PullRequest, String> {
+        let number = pull["number"]
+            .as_i64()
+            .ok_or("GitHub returned a pull request without a number")?;
+        let checks = match pull["head"]["sha"].as_str() {
+            Some(sha) => {
+                let path = format!(
+                    "/repos/{}/{}/commits/{}/check-runs",
+                    repo.owner, repo.name, sha
+                );
+                let runs = self.send(self.request(reqwest::Method::GET, &path)).await?;
+                combine_check_runs(runs["check_runs"].as_array().map_or(&[], |r| r.as_slice()))
+            }
+            None => None,
+        };
+        Ok(PullRequest {
+            number,
+            url: pull["html_url"].as_str().unwrap_or_default().to_string(),
+            state: pull_request_state(pull),
+            checks,
+        })
+    }
+}
+
+#[async_trait]
+impl Forge for GitHubForge {
+    async fn create_pull_request(
+        &self,
+        repo: &ForgeRepo,
+        request: &NewPullRequest,
+    ) -> Result<PullRequest, String> {
+        let path = format!("/repos/{}/{}/pulls", repo.owner, repo.name);
+        let body = json!({
+            "title": request.title,
+            "body": request.body,
+            "head": request.head,
+            "base": request.base,
+            "draft": request.draft,
+        });
+        match self
+            .send(self.request(reqwest::Method::POST, &path).json(&body))
+            .await
+        {
+            Ok(pull) => self.with_checks(repo, &pull).await,
+            // GitHub rejects a second pull request for the same branch as invalid
+            Err(e) if e.status == Some(reqwest::StatusCode::UNPROCESSABLE_ENTITY) => self
+                .find_open_pull_request(repo, &request.head)
+                .await?
+                .ok_or(e.message),
+            Err(e) => Err(e.message),
+        }
+    }
+
+    async fn get_pull_request(&self, repo: &ForgeRepo, number: i64) -> Result<PullRequest, String> {
+        let path = format!("/repos/{}/{}/pulls/{}", repo.owner, repo.name, number);
+        let pull = self.send(self.request(reqwest::Method::GET, &path)).await?;
+        self.with_checks(repo, &pull).await
+    }
+
+    async fn list_review_comments(
+        &self,
+        repo: &ForgeRepo,
+        number: i64,
+    ) -> Result<Vec<ReviewComment>, String> {
+        let path = format!(
+            "/repos/{}/{}/pulls/{}/comments",
+            repo.owner, repo.name, number
+        );
+        let page_size = GITHUB_PAGE_SIZE.to_string();
+        let mut comments = Vec::new();
+        for page in 1.. {
+            let page = page.to_string();
+            let response = self
+                .send(
+                    self.request(reqwest::Method::GET, &path)
+                        .query(&[("per_page", page_size.as_str()), ("page", page.as_str())]),
+                )
+                .await?;
+            let items = response.as_array().cloned().unwrap_or_default();
+            let last_page = items.len() < GITHUB_PAGE_SIZE;
+            comments.extend(items.iter().filter_map(review_comment));
+            if last_page {
+                break;
+            }
+        }
+        comments.sort_by_key(|comment| (comment.created_at, comment.id));
+        Ok(comments)
+    }
+
+    async fn reply_to_review_comment(
+        &self,
+        repo: &ForgeRepo,
+        number: i64,
+        comment_id: i64,
+        body: &str,
+    ) -> Result<ReviewComment, String> {
+        let path = format!(
+            "/repos/{}/{}/pulls/{}/comments/{}/replies",
+            repo.owner, repo.name, number, comment_id
+        );
+        let reply = self
+            .send(
+                self.request(reqwest::Method::POST, &path)
+                    .json(&json!({ "body": body })),
+            )
+            .await?;
+        review_comment(&reply).ok_or_else(|| "GitHub returned an invalid comment".to_string())
+    }
+}
+
+/// Build the forge configured in settings
+pub(crate) async fn load_forge(pool: &DbPool) -> Result<Box<dyn Forge>, String> {
+    let api_url = get_setting(pool, FORGE_API_URL_SETTING)
+        .await?
+        .filter(|url| !url.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_GITHUB_API_URL.to_string());
+    let token = get_setting(pool, FORGE_TOKEN_SETTING)
+        .await?
+        .filter(|token| !token.trim().is_empty());
+    Ok(Box::new(GitHubForge::new(&api_url, token)))
+}
+
+/// Event emitted as `pull-request-status` when a workspace's pull request changes
+#[derive(Debug, Clone, Serialize)]
+pub struct PullRequestStatusEvent {
+    pub workspace_id: String,
+    #[serde(flatten)]
+    pub pull_request: PullRequest,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct TrackedPullRequest {
+    id: String,
+    pr_number: i64,
+    state: Option<String>,
+    pr_checks: Option<String>,
+}
+
+/// Push a workspace's branch and open a pull request into its target branch. Without a
+/// title or body, the agent writes them from the workspace's sessions and commits.
+#[tauri::command]
+pub async fn create_pull_request(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    workspace_id: String,
+    title: Option<String>,
+    body: Option<String>,
+    draft: Option<bool>,
+) -> Result<PullRequest, String> {
+    let pool = state.pool().await?;
+    let context = load_workspace_context(&pool, &workspace_id).await?;
+    if !context.worktree_path.exists() {
+        return Err(format!(
+            "Workspace directory does not exist: {}",
+            context.worktree_path.display()
+        ));
+    }
+
+    let pushed = push_branch(&context, false).map_err(|e| e.message)?;
+    let repo = forge_repo(&context)?;
+    let base = context.base_branch();
+
+    let title = title.filter(|t| !t.trim().is_empty());
+    let body = body.filter(|b| !b.trim().is_empty());
+    let (title, body) = match (title, body) {
+        (Some(title), Some(body)) => (title, body),
+        (title, body) => {
+            let (generated_title, generated_body) =
+                generate_description(&pool, &context, &base).await?;
+            (
+                title.unwrap_or(generated_title),
+                body.unwrap_or(generated_body),
+            )
+        }
+    };
+
+    let forge = load_forge(&pool).await?;
+    let pull_request = forge
+        .create_pull_request(
+            &repo,
+            &NewPullRequest {
+                title,
+                body,
+                head: pushed.branch,
+                base,
+                draft: draft.unwrap_or(false),
+            },
+        )
+        .await?;
+
+    sqlx::query(
+        "UPDATE workspaces SET pr_number = ?, pr_url = ?, updated_at = datetime('now') WHERE id = ?",
+    )
+    .bind(pull_request.number)
+    .bind(&pull_request.url)
+    .bind(&workspace_id)
+    .execute(&pool)
+    .await
+    .map_err(|e| format!("Failed to save pull request: {}", e))?;
+    store_status(&pool, &workspace_id, &pull_request).await?;
+
+    let _ = app.emit(
+        "pull-request-status",
+        PullRequestStatusEvent {
+            workspace_id,
+            pull_request: pull_request.clone(),
+        },
+    );
+    Ok(pull_request)
+}
+
+/// Fetch the current state of a workspace's pull request and store it on the workspace
+#[tauri::command]
+pub async fn refresh_pull_request(
+    state: State<'_, AppState>,
+    workspace_id: String,
+) -> Result<PullRequest, String> {
+    let pool = state.pool().await?;
+    let context = load_workspace_context(&pool, &workspace_id).await?;
+    let number = context
+        .workspace
+        .pr_number
+        .ok_or("Workspace has no pull request")?;
+
+    let forge = load_forge(&pool).await?;
+    let pull_request = forge
+        .get_pull_request(&forge_repo(&context)?, number)
+        .await?;
+    store_status(&pool, &workspace_id, &pull_request).await?;
+    Ok(pull_request)
+}
+
+/// Poll the open pull requests of all workspaces in the background, emitting
+/// `pull-request-status` when one changes and syncing their review comments
+pub(crate) fn spawn_pull_request_poller(app: AppHandle, pool: DbPool) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            tokio::time::sleep(PR_STATUS_POLL_INTERVAL).await;
+            if let Err(e) = poll_pull_requests(&pool, |event| {
+                let _ = app.emit("pull-request-status", event);
+            })
+            .await
+            {
+                eprintln!("Failed to poll pull requests: {}", e);
+            }
+            if let Err(e) = sync_open_pull_requests(&pool, |event| {
+                let _ = app.emit("review-comments-synced", event);
+            })
+            .await
+            {
+                eprintln!("Failed to sync review comments: {}", e);
+            }
+        }
+    });
+}
+
+async fn poll_pull_requests<F>(pool: &DbPool, emit: F) -> Result<(), String>
+where
+    F: Fn(PullRequestStatusEvent),
+{
+    let tracked: Vec<TrackedPullRequest> = sqlx::query_as(
+        "SELECT id, pr_number, state, pr_checks FROM workspaces
+         WHERE pr_number IS NOT NULL AND state = ?",
+    )
+    .bind(WORKSPACE_STATE_PR_OPEN)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Failed to load pull requests: {}", e))?;
+    if tracked.is_empty() {
+        return Ok(());
+    }
+
+    let forge = load_forge(pool).await?;
+    for workspace in tracked {
+        let pull_request = match load_workspace_context(pool, &workspace.id)
+            .await
+            .and_then(|context| forge_repo(&context))
+        {
+            Ok(repo) => forge.get_pull_request(&repo, workspace.pr_number).await,
+            Err(e) => Err(e),
+        };
+        // One broken workspace or unreachable repository shouldn't stop the others from updating
+        let pull_request = match pull_request {
+            Ok(pull_request) => pull_request,
+            Err(e) => {
+                eprintln!("Failed to poll pull request of {}: {}", workspace.id, e);
+                continue;
+            }
+        };
+
+        let new_state = workspace_state(pull_request.state);
+        let new_checks = pull_request.checks.map(ChecksState::as_str);
+        if workspace.state.as_deref() != Some(new_state)
+            || workspace.pr_checks.as_deref() != new_checks
+        {
+            if let Err(e) = store_status(pool, &workspace.id, &pull_request).await {
+                eprintln!("Failed to store pull request of {}: {}", workspace.id, e);
+                continue;
+            }
+            emit(PullRequestStatusEvent {
+                workspace_id: workspace.id,
+                pull_request,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Write a pull request's state into `workspaces.state`, leaving archived workspaces alone
+async fn store_status(
+    pool: &DbPool,
+    workspace_id: &str,
+    pull_request: &PullRequest,
+) -> Result<(), String> {
+    sqlx::query(
+        "UPDATE workspaces SET state = ?, pr_checks = ?, updated_at = datetime('now')
+         WHERE id = ? AND COALESCE(state, 'active') != ?",
+    )
+    .bind(workspace_state(pull_request.state))
+    .bind(pull_request.checks.map(ChecksState::as_str))
+    .bind(workspace_id)
+    .bind(WORKSPACE_STATE_ARCHIVED)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to update pull request status: {}", e))?;
+    Ok(())
+}
+
+fn workspace_state(state: PullRequestState) -> &'static str {
+    match state {
+        PullRequestState::Open => WORKSPACE_STATE_PR_OPEN,
+        PullRequestState::Merged => WORKSPACE_STATE_MERGED,
+        PullRequestState::Closed => WORKSPACE_STATE_PR_CLOSED,
+    }
+}
+
+/// The forge repository of a workspace, from the URL of the repository's remote
+pub(crate) fn forge_repo(context: &WorkspaceContext) -> Result<ForgeRepo, String> {
+    let remote = context.repo.remote.as_deref().unwrap_or("origin");
+    let url = git(&context.worktree_path, &["remote", "get-url", remote]).map_err(|e| e.message)?;
+    parse_remote_url(url.trim())
+        .ok_or_else(|| format!("Cannot tell the repository from {}", url.trim()))
+}
+
+/// Take `owner/name` from a remote URL such as `git@github.com:owner/name.git`
+/// or `https://github.com/owner/name`
+fn parse_remote_url(url: &str) -> Option<ForgeRepo> {
+    let path = url.trim_end_matches('/').trim_end_matches(".git");
+    let mut parts = path.rsplit(['/', ':']);
+    let name = parts.next().filter(|s| !s.is_empty())?;
+    let owner = parts.next().filter(|s| !s.is_empty())?;
+    Some(ForgeRepo {
+        owner: owner.to_string(),
+        name: name.to_string(),
+    })
+}
+
+/// Ask the agent for a title and body from the workspace's sessions and commits
+async fn generate_description(
+    pool: &DbPool,
+    context: &WorkspaceContext,
+    base: &str,
+) -> Result<(String, String), String> {
+    let prompts: Vec<(Option<String>, Option<String>)> = sqlx::query_as(
+        "SELECT s.title, m.content FROM session_messages m
+         JOIN sessions s ON s.id = m.session_id
+         WHERE s.workspace_id = ? AND m.role = 'user'
+           AND m.full_message IS NULL AND m.cancelled_at IS NULL
+         ORDER BY m.created_at",
+    )
+    .bind(&context.workspace.id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Failed to load session history: {}", e))?;
+
+    let mut history = String::new();
+    for (title, content) in prompts {
+        if let Some(content) = content {
+            history.push_str(&format!(
+                "[{}] {}\n",
+                title.unwrap_or_default(),
+                content.trim()
+            ));
+        }
+    }
+    truncate_for_prompt(&mut history, PR_DESCRIPTION_CONTEXT_LIMIT);
+
+    let worktree = context.worktree_path.as_path();
+    let merge_base = find_merge_base(worktree, base)?;
+    let range = format!("{}..HEAD", merge_base);
+    let mut commits =
+        git(worktree, &["log", "--format=- %s%n%b", &range]).map_err(|e| e.message)?;
+    truncate_for_prompt(&mut commits, PR_DESCRIPTION_CONTEXT_LIMIT);
+    let stat = git(worktree, &["diff", "--stat", &merge_base]).map_err(|e| e.message)?;
+
+    let prompt = format!(
+        "{}\n\nConversation:\n{}\nCommits:\n{}\nChanged files:\n{}",
+        PR_DESCRIPTION_PROMPT, history, commits, stat
+    );
+    let reply = ask_agent(pool, worktree, &prompt)
+        .await
+        .map_err(|e| format!("Failed to generate pull request description: {}", e))?;
+    split_title_body(&reply).ok_or_else(|| "The agent returned an empty description".to_string())
+}
+
+/// Split the agent's reply into a title line and the rest
+fn split_title_body(reply: &str) -> Option<(String, String)> {
+    let reply = reply.trim();
+    let (title, body) = reply.split_once('\n').unwrap_or((reply, ""));
+    let title = title.trim().trim_start_matches('#').trim();
+    let title = title.strip_prefix("Title:").unwrap_or(title).trim();
+    if title.is_empty() {
+        return None;
+    }
+    Some((title.to_string(), body.trim().to_string()))
+}
+
+fn review_comment(comment: &Value) -> Option<ReviewComment> {
+    Some(ReviewComment {
+        id: comment["id"].as_i64()?,
+        in_reply_to: comment["in_reply_to_id"].as_i64(),
+        path: comment["path"].as_str()?.to_string(),
+        line: comment["line"].as_i64(),
+        original_line: comment["original_line"].as_i64(),
+        side: if comment["side"].as_str() == Some("LEFT") {
+            "old"
+        } else {
+            "new"
+        }
+        .to_string(),
+        body: comment["body"].as_str().unwrap_or_default().to_string(),
+        author: comment["user"]["login"].as_str().map(str::to_string),
+        url: comment["html_url"].as_str().unwrap_or_default().to_string(),
+        created_at: comment["created_at"]
+            .as_str()
+            .and_then(|at| chrono::DateTime::parse_from_rfc3339(at).ok())
+            .map_or(0, |at| at.timestamp_millis()),
+    })
+}
+
+fn pull_request_state(pull: &Value) -> PullRequestState {
+    if pull["merged"].as_bool() == Some(true) || !pull["merged_at"].is_null() {
+        PullRequestState::Merged
+    } else if pull["state"].as_str() == Some("closed") {
+        PullRequestState::Closed
+    } else {
+        PullRequestState::Open
+    }
+}
+
+/// Combine check runs into one state: any failure fails, anything unfinished is pending
+fn combine_check_runs(runs: &[Value]) -> Option<ChecksState> {
+    if runs.is_empty() {
+        return None;
+    }
+    let failed = runs.iter().any(|run| {
+        matches!(
+            run["conclusion"].as_str(),
+            Some("failure" | "timed_out" | "cancelled" | "action_required" | "startup_failure")
+        )
+    });
+    if failed {
+        Some(ChecksState::Failure)
+    } else if runs
+        .iter()
+        .any(|run| run["status"].as_str() != Some("completed"))
+    {
+        Some(ChecksState::Pending)
+    } else {
+        Some(ChecksState::Success)
+    }
+}
+
+/// The most useful message in a GitHub error response
+fn api_error_message(body: &Value) -> String {
+    let message = body["message"].as_str().unwrap_or("Unknown error");
+    let details: Vec<&str> = body["errors"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|error| error["message"].as_str())
+        .collect();
+    if details.is_empty() {
+        message.to_string()
+    } else {
+        format!("{} ({})", message, details.join("; "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Serve canned JSON responses keyed by request line, recording the requests
+    async fn mock_server(
+        routes: Vec<(&'static str, u16, Value)>,
+    ) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let Ok((mut stream, _)) = listener.accept().await else {
+                    return;
+                };
+                let request = read_request(&mut stream).await;
+                let line = request.lines().next().unwrap_or_default().to_string();
+                recorded.lock().unwrap().push(request);
+
+                let (status, body) = routes
+                    .iter()
+                    .find(|(route, _, _)| line.starts_with(route))
+                    .map(|(_, status, body)| (*status, body.to_string()))
+                    .unwrap_or((404, json!({"message": "Not Found"}).to_string()));
+                let response = format!(
+                    "HTTP/1.1 {} X\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    async fn read_request(stream: &mut tokio::net::TcpStream) -> String {
+        let mut data = Vec::new();
+        let mut buf = [0; 4096];
+        loop {
+            let n = stream.read(&mut buf).await.unwrap();
+            data.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&data).to_string();
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length = head
+                    .lines()
+                    .find_map(|line| {
+                        line.to_lowercase()
+                            .strip_prefix("content-length:")
+                            .map(|l| l.trim().parse().unwrap_or(0))
+                    })
+                    .unwrap_or(0);
+                if body.len() >= length || n == 0 {
+                    return text;
+                }
+            }
+            if n == 0 {
+                return text;
+            }
+        }
+    }
+
+    fn repo() -> ForgeRepo {
+        ForgeRepo {
+            owner: "me".to_string(),
+            name: "app".to_string(),
+        }
+    }
+
+    fn new_pull_request() -> NewPullRequest {
+        NewPullRequest {
+            title: "Add login".to_string(),
+            body: "Adds a login page".to_string(),
+            head: "oslo".to_string(),
+            base: "main".to_string(),
+            draft: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_github_create_and_poll_pull_request() {
+        let pull = json!({
+            "number": 7,
+            "html_url": "https://github.com/me/app/pull/7",
+            "state": "open",
+            "merged": false,
+            "head": {"sha": "abc"},
+        });
+        let (url, requests) = mock_server(vec![
+            ("POST /repos/me/app/pulls ", 201, pull.clone()),
+            (
+                "GET /repos/me/app/commits/abc/check-runs",
+                200,
+                json!({"check_runs": [
+                    {"status": "completed", "conclusion": "success"},
+                    {"status": "in_progress", "conclusion": null},
+                ]}),
+            ),
+            (
+                "GET /repos/me/app/commits/def/check-runs",
+                200,
+                json!({"check_runs": []}),
+            ),
+            (
+                "GET /repos/me/app/pulls/7 ",
+                200,
+                json!({"number": 7, "html_url": "u", "state": "closed", "merged": true, "head": {"sha": "def"}}),
+            ),
+        ])
+        .await;
+        let forge = GitHubForge::new(&url, Some("secret".to_string()));
+
+        let created = forge
+            .create_pull_request(&repo(), &new_pull_request())
+            .await
+            .unwrap();
+        assert_eq!(created.number, 7);
+        assert_eq!(created.url, "https://github.com/me/app/pull/7");
+        assert_eq!(created.state, PullRequestState::Open);
+        assert_eq!(created.checks, Some(ChecksState::Pending));
+
+        let request = requests.lock().unwrap()[0].clone();
+        assert!(request
+            .to_lowercase()
+            .contains("authorization: bearer secret"));
+        assert!(request.contains(r#""head":"oslo""#) && request.contains(r#""base":"main""#));
+
+        let merged = forge.get_pull_request(&repo(), 7).await.unwrap();
+        assert_eq!(merged.state, PullRequestState::Merged);
+        assert_eq!(merged.checks, None);
+    }
+
+    #[tokio::test]
+    async fn test_github_existing_pull_request() {
+        let (url, _) = mock_server(vec![
+            (
+                "POST /repos/me/app/pulls ",
+                422,
+                json!({"message": "Validation Failed", "errors": [{"message": "A pull request already exists for me:oslo."}]}),
+            ),
+            (
+                "GET /repos/me/app/pulls?head=me%3Aoslo&state=open ",
+                200,
+                json!([{"number": 3, "html_url": "u3", "state": "open", "merged": false}]),
+            ),
+        ])
+        .await;
+        let forge = GitHubForge::new(&url, None);
+        let existing = forge
+            .create_pull_request(&repo(), &new_pull_request())
+            .await
+            .unwrap();
+        assert_eq!(existing.number, 3);
+        assert_eq!(existing.checks, None);
+    }
+
+    #[tokio::test]
+    async fn test_github_review_comments() {
+        let (url, requests) = mock_server(vec![
+            (
+                "GET /repos/me/app/pulls/7/comments?per_page=100&page=1 ",
+                200,
+                json!([
+                    {"id": 12, "in_reply_to_id": 11, "path": "a.rs", "line": 4, "side": "RIGHT",
+                     "body": "Agreed", "user": {"login": "bob"}, "html_url": "u12",
+                     "created_at": "2026-01-01T10:05:00Z"},
+                    {"id": 11, "path": "a.rs", "line": null, "original_line": 3, "side": "LEFT",
+                     "body": "Why?", "user": {"login": "amy"}, "html_url": "u11",
+                     "created_at": "2026-01-01T10:00:00Z"},
+                ]),
+            ),
+            (
+                "POST /repos/me/app/pulls/7/comments/11/replies ",
+                201,
+                json!({"id": 13, "in_reply_to_id": 11, "path": "a.rs", "body": "Fixed",
+                       "html_url": "u13", "created_at": "2026-01-01T11:00:00Z"}),
+            ),
+        ])
+        .await;
+        let forge = GitHubForge::new(&url, None);
+
+        let comments = forge.list_review_comments(&repo(), 7).await.unwrap();
+        assert_eq!(
+            comments.iter().map(|c| c.id).collect::<Vec<_>>(),
+            vec![11, 12]
+        );
+        assert_eq!(comments[0].side, "old");
+        assert_eq!(comments[0].line, None);
+        assert_eq!(comments[1].in_reply_to, Some(11));
+        assert_eq!(comments[1].author.as_deref(), Some("bob"));
+
+        let reply = forge
+            .reply_to_review_comment(&repo(), 7, 11, "Fixed")
+            .await
+            .unwrap();
+        assert_eq!(reply.id, 13);
+        assert!(requests.lock().unwrap()[1].contains(r#"{"body":"Fixed"}"#));
+    }
+
+    #[test]
+    fn test_parse_remote_url() {
+        for url in [
+            "git@github.com:me/app.git",
+            "https://github.com/me/app",
+            "ssh://git@github.com/me/app.git/",
+        ] {
+            assert_eq!(parse_remote_url(url), Some(repo()), "{}", url);
+        }
+        assert_eq!(parse_remote_url("app"), None);
+    }
+
+    #[test]
+    fn test_split_title_body() {
+        assert_eq!(
+            split_title_body("# Add login\n\nAdds a page.\n"),
+            Some(("Add login".to_string(), "Adds a page.".to_string()))
+        );
+        assert_eq!(
+            split_title_body("Title: Fix crash"),
+            Some(("Fix crash".to_string(), String::new()))
+        );
+        assert_eq!(split_title_body("  "), None);
+    }
+}
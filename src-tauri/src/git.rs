@@ -0,0 +1,33 @@
+use std::path::Path;
+use std::process::Command;
+
+/// Run a git command in `dir` and return its stdout
+pub(crate) fn run_git(dir: &Path, args: &[&str]) -> Result<String, String> {
+    run_git_with_env(dir, args, &[])
+}
+
+/// Run a git command in `dir` with extra environment variables and return its stdout
+pub(crate) fn run_git_with_env(
+    dir: &Path,
+    args: &[&str],
+    envs: &[(&str, &str)],
+) -> Result<String, String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .envs(envs.iter().copied())
+        .output()
+        .map_err(|e| format!("Failed to execute git command: {}", e))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!(
+            "Git {} failed: {}",
+            args.first().unwrap_or(&""),
+            stderr.trim()
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
@@ -0,0 +1,1119 @@
+use crate::commands::{load_workspace_context, WorkspaceContext};
+use crate::diff::{parse_hunk_header, parse_unified_diff, FileDiff};
+use crate::forge::WORKSPACE_STATE_MERGED;
+use crate::session::{ask_agent, start_turn, truncate_for_prompt, RunningTurns};
+use crate::{AppState, DbPool};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use tauri::{AppHandle, State};
+
+/// Characters of staged diff given to the agent when it writes a commit message
+const COMMIT_MESSAGE_DIFF_LIMIT: usize = 20_000;
+
+const COMMIT_MESSAGE_PROMPT: &str = "Write a git commit message for the staged changes below. \
+Start with a short summary line in the imperative mood, under 72 characters. \
+Add a blank line and a brief body only if the change needs explaining. \
+Reply with the commit message only.";
+
+/// What went wrong in a git command, so the UI can react instead of showing stderr
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GitErrorKind {
+    NothingToCommit,
+    Conflict,
+    /// The remote has commits the local branch doesn't
+    Rejected,
+    Authentication,
+    RemoteNotFound,
+    NoUpstream,
+    /// Another git process holds the index lock
+    Locked,
+    /// Local changes are in the way of the operation
+    DirtyWorktree,
+    Other,
+}
+
+/// Error returned by the git commands
+#[derive(Debug, Clone, Serialize)]
+pub struct GitError {
+    pub kind: GitErrorKind,
+    pub message: String,
+    /// Files left with conflicts, for `conflict` errors
+    pub conflicted_files: Vec<String>,
+    /// Git's own output, for showing details
+    pub details: String,
+}
+
+impl From<String> for GitError {
+    fn from(message: String) -> Self {
+        GitError {
+            kind: GitErrorKind::Other,
+            message,
+            conflicted_files: Vec::new(),
+            details: String::new(),
+        }
+    }
+}
+
+/// Staged and unstaged changes of a workspace
+#[derive(Debug, Clone, Serialize)]
+pub struct StagingStatus {
+    pub staged: Vec<FileDiff>,
+    pub unstaged: Vec<FileDiff>,
+    pub untracked: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CommitResult {
+    pub commit: String,
+    pub message: String,
+    pub amended: bool,
+    /// Whether the message was written by the agent
+    pub generated_message: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PushResult {
+    pub remote: String,
+    pub branch: String,
+    /// Remote branch the workspace branch now tracks, e.g. `origin/oslo`
+    pub upstream: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncResult {
+    /// Branch the workspace was brought up to date with
+    pub base: String,
+    /// `rebase` or `merge`
+    pub strategy: String,
+    pub previous_head: String,
+    pub head: String,
+    pub updated: bool,
+}
+
+/// How `merge_workspace` lands a workspace's commits on its target branch
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeStrategy {
+    /// A merge commit joining the workspace branch
+    Merge,
+    /// The workspace's changes as one new commit
+    Squash,
+    /// The workspace's commits replayed on top of the target branch
+    Rebase,
+}
+
+/// A conflicting part of a file, between git's conflict markers
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConflictHunk {
+    /// Line of the `<<<<<<<` marker
+    pub start_line: usize,
+    /// Line of the `>>>>>>>` marker
+    pub end_line: usize,
+    pub ours: Vec<String>,
+    /// The common ancestor's lines, when the conflict style includes them
+    pub base: Option<Vec<String>>,
+    pub theirs: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConflictFile {
+    pub path: String,
+    pub hunks: Vec<ConflictHunk>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MergeResult {
+    pub strategy: MergeStrategy,
+    pub target_branch: String,
+    pub merged: bool,
+    /// Commit the target branch points to after the merge
+    pub commit: Option<String>,
+    /// Files that conflicted, when the merge was stopped
+    pub conflicts: Vec<ConflictFile>,
+    /// Agent turn resolving the conflicts in the workspace
+    pub resolve_turn_id: Option<String>,
+}
+
+/// Get a workspace's staged, unstaged and untracked changes
+#[tauri::command]
+pub async fn get_staging_status(
+    state: State<'_, AppState>,
+    workspace_id: String,
+) -> Result<StagingStatus, GitError> {
+    let context = load_context(&state, &workspace_id).await?;
+    staging_status(&context.worktree_path)
+}
+
+/// Stage files, or every change when no paths are given
+#[tauri::command]
+pub async fn stage_files(
+    state: State<'_, AppState>,
+    workspace_id: String,
+    paths: Option<Vec<String>>,
+) -> Result<StagingStatus, GitError> {
+    let context = load_context(&state, &workspace_id).await?;
+    let mut args = vec!["add", "-A", "--"];
+    args.extend(paths.iter().flatten().map(String::as_str));
+    git(&context.worktree_path, &args)?;
+    staging_status(&context.worktree_path)
+}
+
+/// Unstage files, or everything when no paths are given
+#[tauri::command]
+pub async fn unstage_files(
+    state: State<'_, AppState>,
+    workspace_id: String,
+    paths: Option<Vec<String>>,
+) -> Result<StagingStatus, GitError> {
+    let context = load_context(&state, &workspace_id).await?;
+    let mut args = vec!["reset", "--quiet", "--"];
+    args.extend(paths.iter().flatten().map(String::as_str));
+    git(&context.worktree_path, &args)?;
+    staging_status(&context.worktree_path)
+}
+
+/// Stage one hunk of a file's unstaged changes, identified by where it starts
+#[tauri::command]
+pub async fn stage_hunk(
+    state: State<'_, AppState>,
+    workspace_id: String,
+    file_path: String,
+    old_start: u32,
+    new_start: u32,
+) -> Result<StagingStatus, GitError> {
+    let context = load_context(&state, &workspace_id).await?;
+    apply_hunk(
+        &context.worktree_path,
+        &file_path,
+        old_start,
+        new_start,
+        false,
+    )?;
+    staging_status(&context.worktree_path)
+}
+
+/// Unstage one hunk of a file's staged changes, identified by where it starts
+#[tauri::command]
+pub async fn unstage_hunk(
+    state: State<'_, AppState>,
+    workspace_id: String,
+    file_path: String,
+    old_start: u32,
+    new_start: u32,
+) -> Result<StagingStatus, GitError> {
+    let context = load_context(&state, &workspace_id).await?;
+    apply_hunk(
+        &context.worktree_path,
+        &file_path,
+        old_start,
+        new_start,
+        true,
+    )?;
+    staging_status(&context.worktree_path)
+}
+
+/// Commit the staged changes. Without a message the agent writes one from the diff;
+/// `amend` rewrites the last commit, keeping its message unless a new one is given.
+#[tauri::command]
+pub async fn commit_workspace(
+    state: State<'_, AppState>,
+    workspace_id: String,
+    message: Option<String>,
+    amend: Option<bool>,
+    stage_all: Option<bool>,
+) -> Result<CommitResult, GitError> {
+    let pool = state.pool().await?;
+    let context = load_workspace_context(&pool, &workspace_id).await?;
+    let worktree = context.worktree_path.as_path();
+    let amend = amend.unwrap_or(false);
+
+    if stage_all.unwrap_or(false) {
+        git(worktree, &["add", "-A"])?;
+    }
+
+    let message = message.filter(|m| !m.trim().is_empty());
+    let generated_message = message.is_none() && !amend;
+    let message = match message {
+        Some(message) => Some(message),
+        None if amend => None,
+        None => Some(generate_commit_message(&pool, worktree).await?),
+    };
+
+    let mut args = vec!["commit"];
+    if amend {
+        args.push("--amend");
+    }
+    match &message {
+        Some(message) => args.extend(["-m", message.as_str()]),
+        None => args.push("--no-edit"),
+    }
+    git(worktree, &args)?;
+
+    Ok(CommitResult {
+        commit: git(worktree, &["rev-parse", "HEAD"])?.trim().to_string(),
+        message: git(worktree, &["log", "-1", "--format=%B"])?
+            .trim()
+            .to_string(),
+        amended: amend,
+        generated_message,
+    })
+}
+
+/// Push the workspace branch to the repository's remote and track it
+#[tauri::command]
+pub async fn push_workspace(
+    state: State<'_, AppState>,
+    workspace_id: String,
+    force: Option<bool>,
+) -> Result<PushResult, GitError> {
+    let context = load_context(&state, &workspace_id).await?;
+    push_branch(&context, force.unwrap_or(false))
+}
+
+/// Bring a workspace up to date with the branch it was created from, rebasing onto it
+/// by default or merging it in. Conflicts abort the operation and are reported.
+#[tauri::command]
+pub async fn sync_with_parent_branch(
+    state: State<'_, AppState>,
+    workspace_id: String,
+    merge: Option<bool>,
+) -> Result<SyncResult, GitError> {
+    let context = load_context(&state, &workspace_id).await?;
+    let worktree = context.worktree_path.as_path();
+    let parent = context
+        .workspace
+        .initialization_parent_branch
+        .clone()
+        .unwrap_or_else(|| context.base_branch());
+    let remote = context.repo.remote.as_deref().unwrap_or("origin");
+
+    // Prefer the remote's copy of the parent branch, which is usually ahead of the local one
+    let remote_branch = format!("{}/{}", remote, parent);
+    let base = if git(worktree, &["fetch", remote, &parent]).is_ok()
+        && git(
+            worktree,
+            &[
+                "rev-parse",
+                "--verify",
+                "--quiet",
+                &format!("refs/remotes/{}", remote_branch),
+            ],
+        )
+        .is_ok()
+    {
+        remote_branch
+    } else {
+        parent
+    };
+
+    let previous_head = git(worktree, &["rev-parse", "HEAD"])?.trim().to_string();
+    let (strategy, args, abort) = if merge.unwrap_or(false) {
+        (
+            "merge",
+            vec!["merge", "--no-edit", "--autostash", base.as_str()],
+            ["merge", "--abort"],
+        )
+    } else {
+        (
+            "rebase",
+            vec!["rebase", "--autostash", base.as_str()],
+            ["rebase", "--abort"],
+        )
+    };
+
+    if let Err(mut e) = git(worktree, &args) {
+        if e.kind == GitErrorKind::Conflict {
+            e.conflicted_files = conflicted_files(worktree);
+            // Leave the workspace as it was rather than half-way through
+            let _ = git(worktree, &abort);
+        }
+        return Err(e);
+    }
+
+    let head = git(worktree, &["rev-parse", "HEAD"])?.trim().to_string();
+    Ok(SyncResult {
+        base,
+        strategy: strategy.to_string(),
+        updated: head != previous_head,
+        previous_head,
+        head,
+    })
+}
+
+/// Land a workspace's commits on its target branch in the repository's own checkout.
+/// The checkout is switched to the target branch for the merge and back to the branch
+/// it was on afterwards, whatever the outcome. Conflicts stop the merge and leave the
+/// repository as it was; with `resolve_with_agent` the workspace is brought up to date
+/// with the target branch instead, and the agent is asked to resolve the conflicts there.
+#[tauri::command]
+pub async fn merge_workspace(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    workspace_id: String,
+    strategy: MergeStrategy,
+    message: Option<String>,
+    resolve_with_agent: Option<bool>,
+) -> Result<MergeResult, GitError> {
+    let pool = state.pool().await?;
+    let context = load_workspace_context(&pool, &workspace_id).await?;
+    let worktree = context.worktree_path.as_path();
+    let root = Path::new(
+        context
+            .repo
+            .root_path
+            .as_deref()
+            .ok_or("Repository has no root path".to_string())?,
+    );
+    let target = context.base_branch();
+
+    let message = message.filter(|m| !m.trim().is_empty());
+    let conflicts = match land_branch(root, worktree, &target, strategy, message.as_deref())? {
+        Landing::Merged(commit) => {
+            sqlx::query(
+                "UPDATE workspaces SET state = ?, updated_at = datetime('now') WHERE id = ?",
+            )
+            .bind(WORKSPACE_STATE_MERGED)
+            .bind(&workspace_id)
+            .execute(&pool)
+            .await
+            .map_err(|e| format!("Failed to update workspace: {}", e))?;
+
+            return Ok(MergeResult {
+                strategy,
+                target_branch: target,
+                merged: true,
+                commit: Some(commit),
+                conflicts: Vec::new(),
+                resolve_turn_id: None,
+            });
+        }
+        Landing::Conflicted(conflicts) => conflicts,
+    };
+
+    let resolve_turn_id = if resolve_with_agent.unwrap_or(false) {
+        hand_conflicts_to_agent(
+            app,
+            &pool,
+            state.agent_turns.clone(),
+            &context,
+            strategy,
+            &target,
+        )
+        .await?
+    } else {
+        None
+    };
+    Ok(MergeResult {
+        strategy,
+        target_branch: target,
+        merged: false,
+        commit: None,
+        conflicts,
+        resolve_turn_id,
+    })
+}
+
+/// Outcome of `land_branch`
+#[derive(Debug)]
+enum Landing {
+    /// The target branch's new head
+    Merged(String),
+    /// The conflicts that stopped the merge, which was aborted
+    Conflicted(Vec<ConflictFile>),
+}
+
+/// Merge the branch checked out in `worktree` into `target` in the checkout at `root`,
+/// switching the checkout back to where it was before returning
+fn land_branch(
+    root: &Path,
+    worktree: &Path,
+    target: &str,
+    strategy: MergeStrategy,
+    message: Option<&str>,
+) -> Result<Landing, GitError> {
+    let branch = current_branch(worktree)?;
+    if has_uncommitted_changes(worktree)? {
+        return Err(dirty_worktree_error(
+            "The workspace has uncommitted changes; commit them before merging",
+        ));
+    }
+    if has_uncommitted_changes(root)? {
+        return Err(dirty_worktree_error(&format!(
+            "The repository at {} has uncommitted changes",
+            root.display()
+        )));
+    }
+
+    // A detached checkout is restored to the commit it was on
+    let original = match current_branch(root) {
+        Ok(original) => original,
+        Err(_) => git(root, &["rev-parse", "HEAD"])?.trim().to_string(),
+    };
+    if original == target {
+        return merge_into_checkout(root, worktree, &branch, target, strategy, message);
+    }
+
+    git(root, &["checkout", "--quiet", target])?;
+    let landing = merge_into_checkout(root, worktree, &branch, target, strategy, message);
+    match git(root, &["checkout", "--quiet", &original]) {
+        Ok(_) => landing,
+        // Don't hide a merge that went through behind the failed switch back
+        Err(mut e) if landing.is_ok() => {
+            e.message = format!(
+                "Merged into {}, but switching {} back to {} failed: {}",
+                target,
+                root.display(),
+                original,
+                e.message
+            );
+            Err(e)
+        }
+        Err(_) => landing,
+    }
+}
+
+/// Merge `branch` into the target branch checked out at `root`. Conflicts are read and
+/// the merge aborted.
+fn merge_into_checkout(
+    root: &Path,
+    worktree: &Path,
+    branch: &str,
+    target: &str,
+    strategy: MergeStrategy,
+    message: Option<&str>,
+) -> Result<Landing, GitError> {
+    let outcome = match strategy {
+        MergeStrategy::Merge => {
+            let mut args = vec!["merge", "--no-ff"];
+            match message {
+                Some(message) => args.extend(["-m", message]),
+                None => args.push("--no-edit"),
+            }
+            args.push(branch);
+            git(root, &args)
+        }
+        MergeStrategy::Squash => git(root, &["merge", "--squash", branch]).and_then(|_| {
+            let message = match message {
+                Some(message) => message.to_string(),
+                None => squash_message(root, target, branch)?,
+            };
+            git(root, &["commit", "-m", &message])
+        }),
+        // The workspace branch is rebased where it's checked out, then fast-forwarded into
+        MergeStrategy::Rebase => git(worktree, &["rebase", target])
+            .and_then(|_| git(root, &["merge", "--ff-only", branch])),
+    };
+
+    match outcome {
+        Ok(_) => Ok(Landing::Merged(
+            git(root, &["rev-parse", "HEAD"])?.trim().to_string(),
+        )),
+        Err(e) if e.kind == GitErrorKind::Conflict => {
+            let conflicted_in = if strategy == MergeStrategy::Rebase {
+                worktree
+            } else {
+                root
+            };
+            let conflicts = read_conflicts(conflicted_in);
+            match strategy {
+                MergeStrategy::Merge => git(root, &["merge", "--abort"])?,
+                // A squash merge records no merge to abort
+                MergeStrategy::Squash => git(root, &["reset", "--merge"])?,
+                MergeStrategy::Rebase => git(worktree, &["rebase", "--abort"])?,
+            };
+            Ok(Landing::Conflicted(conflicts))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Bring the conflicts into the workspace by merging (or for `rebase`, rebasing onto)
+/// the target branch there, and start an agent turn to resolve them. Returns `None`
+/// when the workspace took the target branch without conflicts.
+async fn hand_conflicts_to_agent(
+    app: AppHandle,
+    pool: &DbPool,
+    turns: RunningTurns,
+    context: &WorkspaceContext,
+    strategy: MergeStrategy,
+    target: &str,
+) -> Result<Option<String>, GitError> {
+    let worktree = context.worktree_path.as_path();
+    let session_id = context
+        .workspace
+        .active_session_id
+        .clone()
+        .ok_or("Workspace has no active session".to_string())?;
+
+    let (args, abort, finish) = if strategy == MergeStrategy::Rebase {
+        (
+            vec!["rebase", target],
+            ["rebase", "--abort"],
+            "stage them and run `git rebase --continue`, repeating until the rebase is done",
+        )
+    } else {
+        (
+            vec!["merge", "--no-edit", target],
+            ["merge", "--abort"],
+            "stage them and commit the merge",
+        )
+    };
+    match git(worktree, &args) {
+        Ok(_) => return Ok(None),
+        Err(e) if e.kind == GitErrorKind::Conflict => {}
+        Err(e) => return Err(e),
+    }
+
+    let files: String = conflicted_files(worktree)
+        .iter()
+        .map(|path| format!("- {}\n", path))
+        .collect();
+    let prompt = format!(
+        "Bringing this branch up to date with `{}` stopped on conflicts in:\n{}\n\
+         Resolve the conflicts, keeping the intent of both sides, then {}. \
+         Don't change anything else.",
+        target, files, finish
+    );
+    match start_turn(app, pool.clone(), turns, &session_id, &prompt).await {
+        Ok(turn_id) => Ok(Some(turn_id)),
+        Err(e) => {
+            let _ = git(worktree, &abort);
+            Err(e.into())
+        }
+    }
+}
+
+async fn load_context(state: &AppState, workspace_id: &str) -> Result<WorkspaceContext, GitError> {
+    let pool = state.pool().await?;
+    let context = load_workspace_context(&pool, workspace_id).await?;
+    if !context.worktree_path.exists() {
+        return Err(format!(
+            "Workspace directory does not exist: {}",
+            context.worktree_path.display()
+        )
+        .into());
+    }
+    Ok(context)
+}
+
+/// Push the workspace branch to the repository's remote, setting it as upstream
+pub(crate) fn push_branch(context: &WorkspaceContext, force: bool) -> Result<PushResult, GitError> {
+    let worktree = context.worktree_path.as_path();
+    let remote = context
+        .repo
+        .remote
+        .clone()
+        .unwrap_or_else(|| "origin".to_string());
+    let branch = git(worktree, &["rev-parse", "--abbrev-ref", "HEAD"])?
+        .trim()
+        .to_string();
+    if branch == "HEAD" {
+        return Err("The workspace is not on a branch".to_string().into());
+    }
+
+    let mut args = vec!["push", "--set-upstream"];
+    if force {
+        args.push("--force-with-lease");
+    }
+    args.extend([remote.as_str(), branch.as_str()]);
+    git(worktree, &args)?;
+
+    Ok(PushResult {
+        upstream: format!("{}/{}", remote, branch),
+        remote,
+        branch,
+    })
+}
+
+fn staging_status(worktree: &Path) -> Result<StagingStatus, GitError> {
+    let diff_args = [
+        "-c",
+        "core.quotePath=false",
+        "diff",
+        "--no-color",
+        "--no-ext-diff",
+    ];
+    let unstaged = git(worktree, &diff_args)?;
+    let staged = git(worktree, &[&diff_args[..], &["--cached", "-M"]].concat())?;
+    let untracked = git(
+        worktree,
+        &["ls-files", "--others", "--exclude-standard", "-z"],
+    )?;
+
+    Ok(StagingStatus {
+        staged: parse_unified_diff(&staged),
+        unstaged: parse_unified_diff(&unstaged),
+        untracked: untracked
+            .split('\0')
+            .filter(|path| !path.is_empty())
+            .map(str::to_string)
+            .collect(),
+    })
+}
+
+/// Stage (or with `unstage`, unstage) the hunk of a file starting at the given lines
+fn apply_hunk(
+    worktree: &Path,
+    file_path: &str,
+    old_start: u32,
+    new_start: u32,
+    unstage: bool,
+) -> Result<(), GitError> {
+    let mut diff_args = vec!["diff", "--no-color", "--no-ext-diff"];
+    if unstage {
+        diff_args.push("--cached");
+    }
+    diff_args.extend(["--", file_path]);
+    let diff = git(worktree, &diff_args)?;
+    let patch = hunk_patch(&diff, old_start, new_start)
+        .ok_or_else(|| format!("Hunk not found in {}", file_path))?;
+
+    let patch_path =
+        std::env::temp_dir().join(format!("letsvibe-hunk-{}.patch", uuid::Uuid::new_v4()));
+    std::fs::write(&patch_path, patch).map_err(|e| format!("Failed to write patch: {}", e))?;
+    let patch_file = patch_path.to_string_lossy().to_string();
+
+    let mut apply_args = vec!["apply", "--cached"];
+    if unstage {
+        apply_args.push("--reverse");
+    }
+    apply_args.push(&patch_file);
+    let result = git(worktree, &apply_args).map(|_| ());
+
+    let _ = std::fs::remove_file(&patch_path);
+    result
+}
+
+/// Cut a single-file diff down to its header and the hunk starting at the given lines
+fn hunk_patch(diff: &str, old_start: u32, new_start: u32) -> Option<String> {
+    let mut header = String::new();
+    let mut hunks: Vec<String> = Vec::new();
+    for line in diff.split_inclusive('\n') {
+        if line.starts_with("@@") {
+            hunks.push(line.to_string());
+        } else if let Some(hunk) = hunks.last_mut() {
+            hunk.push_str(line);
+        } else {
+            header.push_str(line);
+        }
+    }
+
+    hunks
+        .into_iter()
+        .find(|hunk| {
+            parse_hunk_header(hunk.lines().next().unwrap_or_default())
+                .is_some_and(|h| h.old_start == old_start && h.new_start == new_start)
+        })
+        .map(|hunk| header + &hunk)
+}
+
+/// Ask the agent to summarize the staged changes as a commit message
+async fn generate_commit_message(pool: &DbPool, worktree: &Path) -> Result<String, GitError> {
+    let stat = git(worktree, &["diff", "--cached", "--stat"])?;
+    if stat.trim().is_empty() {
+        return Err(GitError {
+            kind: GitErrorKind::NothingToCommit,
+            message: "There are no staged changes to commit".to_string(),
+            conflicted_files: Vec::new(),
+            details: String::new(),
+        });
+    }
+    let mut diff = git(
+        worktree,
+        &["diff", "--cached", "--no-color", "--no-ext-diff"],
+    )?;
+    truncate_for_prompt(&mut diff, COMMIT_MESSAGE_DIFF_LIMIT);
+
+    let prompt = format!("{}\n\n{}\n{}", COMMIT_MESSAGE_PROMPT, stat, diff);
+    let message = ask_agent(pool, worktree, &prompt)
+        .await
+        .map_err(|e| format!("Failed to generate commit message: {}", e))?;
+    if message.is_empty() {
+        return Err("The agent returned an empty commit message"
+            .to_string()
+            .into());
+    }
+    Ok(message)
+}
+
+fn current_branch(dir: &Path) -> Result<String, GitError> {
+    let branch = git(dir, &["rev-parse", "--abbrev-ref", "HEAD"])?
+        .trim()
+        .to_string();
+    if branch == "HEAD" {
+        return Err(format!("{} is not on a branch", dir.display()).into());
+    }
+    Ok(branch)
+}
+
+/// Whether tracked files have changes, staged or not
+fn has_uncommitted_changes(dir: &Path) -> Result<bool, GitError> {
+    let status = git(dir, &["status", "--porcelain", "--untracked-files=no"])?;
+    Ok(!status.trim().is_empty())
+}
+
+fn dirty_worktree_error(message: &str) -> GitError {
+    GitError {
+        kind: GitErrorKind::DirtyWorktree,
+        message: message.to_string(),
+        conflicted_files: Vec::new(),
+        details: String::new(),
+    }
+}
+
+/// Message for a squash merge, listing the commits it squashes
+fn squash_message(root: &Path, target: &str, branch: &str) -> Result<String, GitError> {
+    let range = format!("{}..{}", target, branch);
+    let subjects = git(root, &["log", "--reverse", "--format=- %s", &range])?;
+    Ok(format!("Merge {}\n\n{}", branch, subjects.trim()))
+}
+
+/// The conflicted files in `dir` with their conflict hunks
+fn read_conflicts(dir: &Path) -> Vec<ConflictFile> {
+    conflicted_files(dir)
+        .into_iter()
+        .map(|path| {
+            let hunks = std::fs::read_to_string(dir.join(&path))
+                .map(|content| parse_conflict_hunks(&content))
+                .unwrap_or_default();
+            ConflictFile { path, hunks }
+        })
+        .collect()
+}
+
+/// Find the regions between conflict markers, with or without the common ancestor section
+fn parse_conflict_hunks(content: &str) -> Vec<ConflictHunk> {
+    #[derive(PartialEq)]
+    enum Section {
+        Ours,
+        Base,
+        Theirs,
+    }
+
+    let mut hunks = Vec::new();
+    let mut current: Option<(ConflictHunk, Section)> = None;
+    for (index, line) in content.lines().enumerate() {
+        let line_number = index + 1;
+        if line.starts_with("<<<<<<<") {
+            let hunk = ConflictHunk {
+                start_line: line_number,
+                end_line: line_number,
+                ours: Vec::new(),
+                base: None,
+                theirs: Vec::new(),
+            };
+            current = Some((hunk, Section::Ours));
+            continue;
+        }
+        let Some((hunk, section)) = current.as_mut() else {
+            continue;
+        };
+        if line.starts_with("|||||||") && *section == Section::Ours {
+            hunk.base = Some(Vec::new());
+            *section = Section::Base;
+        } else if line == "=======" && *section != Section::Theirs {
+            *section = Section::Theirs;
+        } else if line.starts_with(">>>>>>>") && *section == Section::Theirs {
+            hunk.end_line = line_number;
+            hunks.extend(current.take().map(|(hunk, _)| hunk));
+        } else {
+            let lines = match section {
+                Section::Ours => &mut hunk.ours,
+                Section::Base => hunk.base.get_or_insert_with(Vec::new),
+                Section::Theirs => &mut hunk.theirs,
+            };
+            lines.push(line.to_string());
+        }
+    }
+    hunks
+}
+
+fn conflicted_files(worktree: &Path) -> Vec<String> {
+    git(worktree, &["diff", "--name-only", "--diff-filter=U"])
+        .map(|output| output.lines().map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+/// Run a git command in `dir`, turning a failure into a `GitError`
+pub(crate) fn git(dir: &Path, args: &[&str]) -> Result<String, GitError> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        // Fail instead of waiting for credentials nobody can type in
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("Failed to execute git command: {}", e))?;
+
+    if output.status.success() {
+        return Ok(String::from_utf8_lossy(&output.stdout).to_string());
+    }
+
+    // Some failures, like "nothing to commit", are only reported on stdout
+    let mut details = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if !stdout.trim().is_empty() {
+        details = format!("{}\n{}", stdout.trim(), details).trim().to_string();
+    }
+    let (kind, message) = parse_git_error(&details);
+    Err(GitError {
+        kind,
+        message: message.unwrap_or_else(|| {
+            format!(
+                "Git {} failed: {}",
+                args.iter()
+                    .find(|a| !a.starts_with('-') && !a.contains('='))
+                    .unwrap_or(&""),
+                first_error_line(&details)
+            )
+        }),
+        conflicted_files: Vec::new(),
+        details,
+    })
+}
+
+/// Classify git's output, with a friendlier message for the failures people run into most
+fn parse_git_error(output: &str) -> (GitErrorKind, Option<String>) {
+    let has = |needles: &[&str]| needles.iter().any(|needle| output.contains(needle));
+
+    if has(&["nothing to commit", "no changes added to commit"]) {
+        (
+            GitErrorKind::NothingToCommit,
+            Some("There are no changes to commit".to_string()),
+        )
+    } else if has(&[
+        "CONFLICT",
+        "could not apply",
+        "Merge conflict",
+        "needs merge",
+    ]) {
+        (
+            GitErrorKind::Conflict,
+            Some("The changes conflict with the branch".to_string()),
+        )
+    } else if has(&["[rejected]", "non-fast-forward", "Updates were rejected"]) {
+        (
+            GitErrorKind::Rejected,
+            Some("The remote branch has commits that aren't in the workspace".to_string()),
+        )
+    } else if has(&[
+        "Authentication failed",
+        "could not read Username",
+        "terminal prompts disabled",
+        "Permission denied (publickey)",
+    ]) {
+        (
+            GitErrorKind::Authentication,
+            Some("Authentication with the remote failed".to_string()),
+        )
+    } else if has(&[
+        "does not appear to be a git repository",
+        "No such remote",
+        "Repository not found",
+    ]) {
+        (
+            GitErrorKind::RemoteNotFound,
+            Some("The remote repository could not be found".to_string()),
+        )
+    } else if has(&["has no upstream branch", "no tracking information"]) {
+        (
+            GitErrorKind::NoUpstream,
+            Some("The branch has no upstream branch".to_string()),
+        )
+    } else if has(&["index.lock", "Another git process"]) {
+        (
+            GitErrorKind::Locked,
+            Some("Another git process is running in this workspace".to_string()),
+        )
+    } else if has(&[
+        "would be overwritten",
+        "Please commit your changes or stash them",
+        "You have unstaged changes",
+    ]) {
+        (
+            GitErrorKind::DirtyWorktree,
+            Some("Local changes are in the way; commit or stash them first".to_string()),
+        )
+    } else {
+        (GitErrorKind::Other, None)
+    }
+}
+
+/// The line of git's output that says what went wrong, without its `fatal:` prefix
+fn first_error_line(output: &str) -> &str {
+    output
+        .lines()
+        .find_map(|line| {
+            line.strip_prefix("fatal: ")
+                .or_else(|| line.strip_prefix("error: "))
+        })
+        .or_else(|| output.lines().next())
+        .unwrap_or_default()
+        .trim()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_parse_git_error() {
+        let rejected = " ! [rejected]        oslo -> oslo (fetch first)\n\
+                        error: failed to push some refs to 'github.com:me/repo.git'";
+        assert_eq!(parse_git_error(rejected).0, GitErrorKind::Rejected);
+        assert_eq!(
+            parse_git_error("CONFLICT (content): Merge conflict in src/lib.rs").0,
+            GitErrorKind::Conflict
+        );
+        assert_eq!(
+            parse_git_error("fatal: could not read Username for 'https://github.com'").0,
+            GitErrorKind::Authentication
+        );
+        assert_eq!(
+            parse_git_error("On branch oslo\nnothing to commit, working tree clean").0,
+            GitErrorKind::NothingToCommit
+        );
+        assert_eq!(
+            parse_git_error("fatal: bad revision 'nope'"),
+            (GitErrorKind::Other, None)
+        );
+        assert_eq!(
+            first_error_line("hint: x\nfatal: bad revision 'nope'"),
+            "bad revision 'nope'"
+        );
+    }
+
+    #[test]
+    fn test_parse_conflict_hunks() {
+        let content = "a\n<<<<<<< HEAD\nmine\n=======\ntheirs\n>>>>>>> oslo\nb\n\
+                       <<<<<<< HEAD\nx\n||||||| base\no\n=======\ny\nz\n>>>>>>> oslo\n";
+        let hunks = parse_conflict_hunks(content);
+        assert_eq!(
+            hunks,
+            vec![
+                ConflictHunk {
+                    start_line: 2,
+                    end_line: 6,
+                    ours: vec!["mine".to_string()],
+                    base: None,
+                    theirs: vec!["theirs".to_string()],
+                },
+                ConflictHunk {
+                    start_line: 8,
+                    end_line: 15,
+                    ours: vec!["x".to_string()],
+                    base: Some(vec!["o".to_string()]),
+                    theirs: vec!["y".to_string(), "z".to_string()],
+                },
+            ]
+        );
+        assert!(parse_conflict_hunks("plain\n=======\n").is_empty());
+    }
+
+    #[test]
+    fn test_hunk_patch() {
+        let diff = "diff --git a/f b/f\n--- a/f\n+++ b/f\n\
+                    @@ -1,2 +1,2 @@\n-a\n+A\n b\n\
+                    @@ -10,2 +10,3 @@\n x\n+y\n z\n";
+        assert_eq!(
+            hunk_patch(diff, 10, 10).unwrap(),
+            "diff --git a/f b/f\n--- a/f\n+++ b/f\n@@ -10,2 +10,3 @@\n x\n+y\n z\n"
+        );
+        assert!(hunk_patch(diff, 5, 5).is_none());
+    }
+
+    /// A repository checked out on `other`, with `main` and a worktree on `feature`
+    /// whose commit adds a line to `shared.txt`
+    fn merge_fixture() -> (PathBuf, PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("letsvibe-merge-{}", uuid::Uuid::new_v4()));
+        let root = dir.join("repo");
+        let worktree = dir.join("feature");
+        std::fs::create_dir_all(&root).unwrap();
+        let run = |dir: &Path, args: &[&str]| git(dir, args).unwrap();
+        run(&root, &["init", "-q", "-b", "main"]);
+        run(&root, &["config", "user.name", "Test"]);
+        run(&root, &["config", "user.email", "test@example.com"]);
+        std::fs::write(root.join("shared.txt"), "one\n").unwrap();
+        run(&root, &["add", "."]);
+        run(&root, &["commit", "-qm", "initial"]);
+        run(
+            &root,
+            &[
+                "worktree",
+                "add",
+                "-q",
+                "-b",
+                "feature",
+                &worktree.to_string_lossy(),
+            ],
+        );
+        std::fs::write(worktree.join("shared.txt"), "one\nfeature\n").unwrap();
+        run(&worktree, &["commit", "-qam", "feature work"]);
+        run(&root, &["checkout", "-q", "-b", "other"]);
+        (dir, root, worktree)
+    }
+
+    #[test]
+    fn test_land_branch_strategies() {
+        for strategy in [
+            MergeStrategy::Merge,
+            MergeStrategy::Squash,
+            MergeStrategy::Rebase,
+        ] {
+            let (dir, root, worktree) = merge_fixture();
+
+            let landing = land_branch(&root, &worktree, "main", strategy, None).unwrap();
+            let Landing::Merged(commit) = landing else {
+                panic!("{:?} didn't merge: {:?}", strategy, landing);
+            };
+            assert_eq!(git(&root, &["rev-parse", "main"]).unwrap().trim(), commit);
+            assert_eq!(
+                git(&root, &["show", "main:shared.txt"]).unwrap(),
+                "one\nfeature\n"
+            );
+            let parents = git(&root, &["rev-list", "--parents", "-n1", "main"]).unwrap();
+            let merge_commit = parents.split_whitespace().count() == 3;
+            assert_eq!(merge_commit, strategy == MergeStrategy::Merge);
+            // The user's checkout is back where it was
+            assert_eq!(current_branch(&root).unwrap(), "other");
+
+            let _ = std::fs::remove_dir_all(&dir);
+        }
+    }
+
+    #[test]
+    fn test_land_branch_conflict() {
+        for strategy in [
+            MergeStrategy::Merge,
+            MergeStrategy::Squash,
+            MergeStrategy::Rebase,
+        ] {
+            let (dir, root, worktree) = merge_fixture();
+            git(&root, &["checkout", "-q", "main"]).unwrap();
+            std::fs::write(root.join("shared.txt"), "one\nmain\n").unwrap();
+            git(&root, &["commit", "-qam", "main work"]).unwrap();
+            git(&root, &["checkout", "-q", "other"]).unwrap();
+            let main_head = git(&root, &["rev-parse", "main"]).unwrap();
+
+            let landing = land_branch(&root, &worktree, "main", strategy, None).unwrap();
+            let Landing::Conflicted(conflicts) = landing else {
+                panic!("{:?} didn't conflict: {:?}", strategy, landing);
+            };
+            assert_eq!(conflicts.len(), 1);
+            assert_eq!(conflicts[0].path, "shared.txt");
+
+            // Nothing landed and both checkouts are clean and back where they were
+            assert_eq!(git(&root, &["rev-parse", "main"]).unwrap(), main_head);
+            assert_eq!(current_branch(&root).unwrap(), "other");
+            assert_eq!(current_branch(&worktree).unwrap(), "feature");
+            assert!(!has_uncommitted_changes(&root).unwrap());
+            assert!(!has_uncommitted_changes(&worktree).unwrap());
+
+            let _ = std::fs::remove_dir_all(&dir);
+        }
+    }
+}
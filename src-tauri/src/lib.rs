@@ -1,17 +1,53 @@
+mod checkpoint;
+mod clone;
 mod commands;
+mod comments;
 mod db;
+mod diff;
+mod fan_out;
+mod forge;
+mod git;
+mod git_ops;
+mod links;
 mod place_names;
+mod review_sync;
+mod run_script;
+mod scripts;
+mod session;
+mod setup;
+mod terminal;
 
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
 use tauri::Manager;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Notify};
 
 pub use db::models;
 pub use db::DbPool;
 
 pub struct AppState {
     pub db: Arc<Mutex<Option<DbPool>>>,
+    /// In-progress clones keyed by URL, used to cancel them
+    pub clones: Arc<Mutex<HashMap<String, Arc<Notify>>>>,
+    /// Agent turns currently running, keyed by session id
+    pub agent_turns: session::RunningTurns,
+    /// Run scripts currently running, keyed by workspace id
+    pub run_scripts: run_script::RunningScripts,
+    /// Setup scripts currently running, keyed by workspace id
+    pub setup_scripts: run_script::RunningScripts,
+    /// Open terminals, keyed by terminal id
+    pub terminals: terminal::Terminals,
+}
+
+impl AppState {
+    /// Get a handle to the connection pool without holding the lock,
+    /// for commands that run long operations
+    pub async fn pool(&self) -> Result<DbPool, String> {
+        let db = self.db.lock().await;
+        db.clone()
+            .ok_or_else(|| "Database not initialized".to_string())
+    }
 }
 
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
@@ -38,6 +74,11 @@ pub fn run() {
             let app_handle = app.handle().clone();
             let state = AppState {
                 db: Arc::new(Mutex::new(None)),
+                clones: Arc::new(Mutex::new(HashMap::new())),
+                agent_turns: Arc::new(Mutex::new(HashMap::new())),
+                run_scripts: Arc::new(Mutex::new(HashMap::new())),
+                setup_scripts: Arc::new(Mutex::new(HashMap::new())),
+                terminals: Arc::new(Mutex::new(HashMap::new())),
             };
             app.manage(state);
 
@@ -47,6 +88,17 @@ pub fn run() {
                 let db_path = get_db_path(&app_handle);
                 match db::init_pool(&db_path).await {
                     Ok(pool) => {
+                        // Nothing can be running yet, so any `running` session was cut off by the last quit
+                        match session::recover_interrupted_sessions(&pool).await {
+                            Ok(ids) if !ids.is_empty() => {
+                                println!("Marked {} interrupted session(s)", ids.len());
+                            }
+                            Ok(_) => {}
+                            Err(e) => eprintln!("Failed to recover interrupted sessions: {}", e),
+                        }
+
+                        forge::spawn_pull_request_poller(app_handle.clone(), pool.clone());
+
                         let mut db = db_arc.lock().await;
                         *db = Some(pool);
                         println!("Database initialized at: {:?}", db_path);
@@ -63,11 +115,70 @@ pub fn run() {
             greet,
             commands::get_repositories,
             commands::create_repo,
+            commands::open_project,
             commands::create_workspace,
+            commands::fork_workspace,
             commands::delete_repo,
+            commands::move_repo,
             commands::delete_workspace,
+            commands::archive_workspace,
+            commands::restore_workspace,
+            commands::pin_workspace,
+            commands::unpin_workspace,
+            commands::move_workspace,
             commands::get_workspace_files,
             commands::read_file_content,
+            clone::clone_repository,
+            clone::cancel_clone,
+            session::create_session,
+            session::get_sessions,
+            session::get_session_messages,
+            session::send_session_message,
+            session::cancel_session_turn,
+            session::resume_session,
+            checkpoint::list_checkpoints,
+            checkpoint::restore_checkpoint,
+            setup::get_setup_log,
+            setup::get_initialization_files,
+            setup::set_initialization_files,
+            run_script::start_run_script,
+            run_script::stop_run_script,
+            run_script::get_running_scripts,
+            terminal::open_terminal,
+            terminal::write_terminal,
+            terminal::resize_terminal,
+            terminal::close_terminal,
+            terminal::list_terminals,
+            terminal::attach_terminal,
+            diff::get_workspace_diff_stats,
+            diff::get_workspace_diff,
+            comments::create_diff_comment,
+            comments::reply_to_diff_comment,
+            comments::edit_diff_comment,
+            comments::resolve_diff_thread,
+            comments::reopen_diff_thread,
+            comments::delete_diff_comment,
+            comments::list_diff_comments,
+            comments::reanchor_diff_comments,
+            comments::address_review_comments,
+            git_ops::get_staging_status,
+            git_ops::stage_files,
+            git_ops::unstage_files,
+            git_ops::stage_hunk,
+            git_ops::unstage_hunk,
+            git_ops::commit_workspace,
+            git_ops::push_workspace,
+            git_ops::sync_with_parent_branch,
+            git_ops::merge_workspace,
+            forge::create_pull_request,
+            forge::refresh_pull_request,
+            review_sync::sync_review_comments,
+            links::link_workspaces,
+            links::unlink_workspace,
+            links::get_linked_workspaces,
+            links::diff_linked_workspaces,
+            links::promote_workspace,
+            fan_out::fan_out_task,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,430 @@
+use crate::checkpoint::write_worktree_tree;
+use crate::commands::{
+    archive_workspace_with, ensure_no_running_agent, load_workspace_context,
+    ArchiveWorkspaceResult, WORKSPACE_STATE_ARCHIVED,
+};
+use crate::db::models::Workspace;
+use crate::diff::{compute_diff_stats, diff_trees, DiffOptions, FileDiff, WorkspaceDiffStats};
+use crate::git::run_git;
+use crate::{AppState, DbPool};
+use serde::Serialize;
+use sqlx::{Executor, Sqlite, Transaction};
+use std::collections::BTreeSet;
+use std::path::Path;
+use tauri::State;
+
+/// One workspace of a linked group, for comparing it with the others
+#[derive(Debug, Clone, Serialize)]
+pub struct LinkedWorkspaceSummary {
+    pub workspace: Workspace,
+    /// Changes since the workspace branched off its base branch, when its worktree is on disk
+    pub diff_stats: Option<WorkspaceDiffStats>,
+    /// Every file the workspace changed, committed or not
+    pub files_touched: Vec<String>,
+    /// Combined state of its pull request's checks, once it has one
+    pub checks: Option<String>,
+    /// What the agents in its sessions have cost, in US dollars
+    pub cost_usd: f64,
+}
+
+/// Differences between the worktrees of two linked workspaces, uncommitted and
+/// untracked files included
+#[derive(Debug, Clone, Serialize)]
+pub struct CrossWorkspaceDiff {
+    pub from_workspace_id: String,
+    pub to_workspace_id: String,
+    pub files: Vec<FileDiff>,
+}
+
+/// Result of `promote_workspace`
+#[derive(Debug, Clone, Serialize)]
+pub struct PromoteWorkspaceResult {
+    pub workspace: Workspace,
+    pub archived: Vec<ArchiveWorkspaceResult>,
+    /// Members that were left alone, and why
+    pub warnings: Vec<String>,
+}
+
+/// Link workspaces of one repository into a group, to compare them. Workspaces that
+/// are already linked bring their group along. Returns the ids in the group.
+#[tauri::command]
+pub async fn link_workspaces(
+    state: State<'_, AppState>,
+    workspace_ids: Vec<String>,
+) -> Result<Vec<String>, String> {
+    let db = state.db.lock().await;
+    let pool = db.as_ref().ok_or("Database not initialized")?;
+
+    if workspace_ids.len() < 2 {
+        return Err("Select at least two workspaces to link".to_string());
+    }
+    let mut repository_ids = BTreeSet::new();
+    for id in &workspace_ids {
+        let context = load_workspace_context(pool, id).await?;
+        repository_ids.insert(context.repo.id);
+    }
+    if repository_ids.len() > 1 {
+        return Err("Only workspaces of the same repository can be linked".to_string());
+    }
+
+    link_group(pool, &workspace_ids).await
+}
+
+/// Take a workspace out of its group; the rest of the group stays linked
+#[tauri::command]
+pub async fn unlink_workspace(
+    state: State<'_, AppState>,
+    workspace_id: String,
+) -> Result<(), String> {
+    let db = state.db.lock().await;
+    let pool = db.as_ref().ok_or("Database not initialized")?;
+    unlink(pool, &workspace_id).await
+}
+
+/// Summarize a workspace and the workspaces linked to it, side by side
+#[tauri::command]
+pub async fn get_linked_workspaces(
+    state: State<'_, AppState>,
+    workspace_id: String,
+) -> Result<Vec<LinkedWorkspaceSummary>, String> {
+    // Diff stats run git in every worktree, so don't hold the lock meanwhile
+    let pool = state.pool().await?;
+
+    let mut ids = vec![workspace_id.clone()];
+    ids.extend(linked_workspace_ids(&pool, &workspace_id).await?);
+
+    let mut summaries = Vec::new();
+    for id in ids {
+        let context = load_workspace_context(&pool, &id).await?;
+        let diff_stats = if context.worktree_path.exists() {
+            compute_diff_stats(&context.worktree_path, &context.base_branch()).ok()
+        } else {
+            None
+        };
+        let files_touched = diff_stats
+            .iter()
+            .flat_map(|stats| [&stats.committed, &stats.uncommitted, &stats.untracked])
+            .flat_map(|group| group.files.iter().map(|file| file.path.clone()))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let cost_usd: f64 = sqlx::query_scalar(
+            "SELECT COALESCE(SUM(cost_usd), 0.0) FROM sessions WHERE workspace_id = ?",
+        )
+        .bind(&id)
+        .fetch_one(&pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        summaries.push(LinkedWorkspaceSummary {
+            checks: context.workspace.pr_checks.clone(),
+            workspace: context.workspace,
+            diff_stats,
+            files_touched,
+            cost_usd,
+        });
+    }
+
+    Ok(summaries)
+}
+
+/// Diff the files of two linked workspaces as they are on disk
+#[tauri::command]
+pub async fn diff_linked_workspaces(
+    state: State<'_, AppState>,
+    from_workspace_id: String,
+    to_workspace_id: String,
+    options: Option<DiffOptions>,
+) -> Result<CrossWorkspaceDiff, String> {
+    let (from, to) = {
+        let db = state.db.lock().await;
+        let pool = db.as_ref().ok_or("Database not initialized")?;
+        if !linked_workspace_ids(pool, &from_workspace_id)
+            .await?
+            .contains(&to_workspace_id)
+        {
+            return Err("The workspaces are not linked".to_string());
+        }
+        (
+            load_workspace_context(pool, &from_workspace_id).await?,
+            load_workspace_context(pool, &to_workspace_id).await?,
+        )
+    };
+    for context in [&from, &to] {
+        if !context.worktree_path.exists() {
+            return Err(format!(
+                "Workspace directory does not exist: {}",
+                context.worktree_path.display()
+            ));
+        }
+    }
+
+    Ok(CrossWorkspaceDiff {
+        files: diff_worktrees(
+            &from.worktree_path,
+            &to.worktree_path,
+            &options.unwrap_or_default(),
+        )?,
+        from_workspace_id,
+        to_workspace_id,
+    })
+}
+
+/// Keep one workspace of a group and archive the others. The group is dissolved.
+/// Members whose worktree is already gone, or that fail to archive, are left as they
+/// are and reported in the warnings.
+#[tauri::command]
+pub async fn promote_workspace(
+    state: State<'_, AppState>,
+    workspace_id: String,
+) -> Result<PromoteWorkspaceResult, String> {
+    // Archiving runs each member's archive script, so don't hold the lock meanwhile
+    let pool = state.pool().await?;
+
+    let others = linked_workspace_ids(&pool, &workspace_id).await?;
+    if others.is_empty() {
+        return Err("Workspace is not linked to any other workspace".to_string());
+    }
+
+    // Check them all up front rather than stopping halfway through the group
+    let mut to_archive = Vec::new();
+    let mut warnings = Vec::new();
+    for id in &others {
+        let context = load_workspace_context(&pool, id).await?;
+        if context.workspace.state.as_deref() == Some(WORKSPACE_STATE_ARCHIVED) {
+            continue;
+        }
+        ensure_no_running_agent(&state, &pool, id).await?;
+        if context.worktree_path.exists() {
+            to_archive.push(id.clone());
+        } else {
+            warnings.push(format!(
+                "Skipped workspace {}: its directory {} no longer exists",
+                context.workspace.directory_name.as_deref().unwrap_or(id),
+                context.worktree_path.display()
+            ));
+        }
+    }
+
+    let mut archived = Vec::new();
+    for id in &to_archive {
+        match archive_workspace_with(&state, &pool, id).await {
+            Ok(result) => archived.push(result),
+            Err(e) => warnings.push(format!("Failed to archive workspace {}: {}", id, e)),
+        }
+    }
+
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+    for id in std::iter::once(&workspace_id).chain(&others) {
+        store_links(&mut tx, id, &[]).await?;
+    }
+    tx.commit().await.map_err(|e| e.to_string())?;
+
+    let workspace: Workspace = sqlx::query_as("SELECT * FROM workspaces WHERE id = ?")
+        .bind(&workspace_id)
+        .fetch_one(&pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(PromoteWorkspaceResult {
+        workspace,
+        archived,
+        warnings,
+    })
+}
+
+/// Parse `workspaces.linked_workspace_ids`, a JSON array of workspace ids
+pub(crate) fn parse_linked_ids(value: Option<&str>) -> Vec<String> {
+    value
+        .and_then(|v| serde_json::from_str(v).ok())
+        .unwrap_or_default()
+}
+
+/// Ids of the workspaces linked to a workspace
+pub(crate) async fn linked_workspace_ids(
+    pool: &DbPool,
+    workspace_id: &str,
+) -> Result<Vec<String>, String> {
+    read_links(pool, workspace_id).await
+}
+
+/// Link workspaces into one group. Workspaces that are already linked bring their group
+/// along, and every member of the group ends up linked to every other member.
+pub(crate) async fn link_group(
+    pool: &DbPool,
+    workspace_ids: &[String],
+) -> Result<Vec<String>, String> {
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+
+    let mut group: Vec<String> = Vec::new();
+    for id in workspace_ids {
+        let links = read_links(&mut *tx, id).await?;
+        for member in std::iter::once(id.clone()).chain(links) {
+            if !group.contains(&member) {
+                group.push(member);
+            }
+        }
+    }
+
+    for member in &group {
+        let others: Vec<&String> = group.iter().filter(|id| *id != member).collect();
+        store_links(&mut tx, member, &others).await?;
+    }
+    tx.commit().await.map_err(|e| e.to_string())?;
+
+    Ok(group)
+}
+
+/// Remove a workspace from its group, on its side and on every other member's
+async fn unlink(pool: &DbPool, workspace_id: &str) -> Result<(), String> {
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+
+    for member in read_links(&mut *tx, workspace_id).await? {
+        let remaining = read_links(&mut *tx, &member).await?;
+        let remaining: Vec<&String> = remaining.iter().filter(|id| *id != workspace_id).collect();
+        store_links(&mut tx, &member, &remaining).await?;
+    }
+    store_links(&mut tx, workspace_id, &[]).await?;
+
+    tx.commit().await.map_err(|e| e.to_string())
+}
+
+async fn read_links<'e, E>(executor: E, workspace_id: &str) -> Result<Vec<String>, String>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let value: Option<Option<String>> =
+        sqlx::query_scalar("SELECT linked_workspace_ids FROM workspaces WHERE id = ?")
+            .bind(workspace_id)
+            .fetch_optional(executor)
+            .await
+            .map_err(|e| e.to_string())?;
+    match value {
+        Some(value) => Ok(parse_linked_ids(value.as_deref())),
+        None => Err(format!("Workspace not found: {}", workspace_id)),
+    }
+}
+
+async fn store_links(
+    tx: &mut Transaction<'_, Sqlite>,
+    workspace_id: &str,
+    linked_ids: &[&String],
+) -> Result<(), String> {
+    let value = if linked_ids.is_empty() {
+        None
+    } else {
+        Some(serde_json::to_string(linked_ids).map_err(|e| e.to_string())?)
+    };
+    sqlx::query(
+        "UPDATE workspaces SET linked_workspace_ids = ?, updated_at = datetime('now') WHERE id = ?",
+    )
+    .bind(value)
+    .bind(workspace_id)
+    .execute(&mut **tx)
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Diff two worktrees of the same repository. Both are snapshotted into trees, which
+/// land in the object store the worktrees share, so one worktree can diff them.
+fn diff_worktrees(from: &Path, to: &Path, options: &DiffOptions) -> Result<Vec<FileDiff>, String> {
+    let snapshot = |worktree: &Path| -> Result<String, String> {
+        let head = run_git(worktree, &["rev-parse", "HEAD"])?;
+        write_worktree_tree(worktree, head.trim())
+    };
+    diff_trees(from, &snapshot(from)?, &snapshot(to)?, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_pool;
+
+    async fn links(pool: &DbPool, workspace_id: &str) -> Vec<String> {
+        linked_workspace_ids(pool, workspace_id).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_link_group_merges_groups() {
+        let pool = test_pool().await;
+        sqlx::query("INSERT INTO workspaces (id) VALUES ('a'), ('b'), ('c'), ('d')")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let ids = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+        link_group(&pool, &ids(&["a", "b"])).await.unwrap();
+        assert_eq!(links(&pool, "a").await, ids(&["b"]));
+        assert_eq!(links(&pool, "b").await, ids(&["a"]));
+
+        // Linking to any member of a group joins the whole group
+        let group = link_group(&pool, &ids(&["c", "b"])).await.unwrap();
+        assert_eq!(group, ids(&["c", "b", "a"]));
+        assert_eq!(links(&pool, "a").await, ids(&["c", "b"]));
+        assert!(links(&pool, "d").await.is_empty());
+
+        assert!(link_group(&pool, &ids(&["a", "missing"])).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_unlink_keeps_the_rest_of_the_group() {
+        let pool = test_pool().await;
+        sqlx::query("INSERT INTO workspaces (id) VALUES ('a'), ('b'), ('c')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let ids = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+        link_group(&pool, &ids(&["a", "b", "c"])).await.unwrap();
+
+        unlink(&pool, "b").await.unwrap();
+        assert!(links(&pool, "b").await.is_empty());
+        assert_eq!(links(&pool, "a").await, ids(&["c"]));
+        assert_eq!(links(&pool, "c").await, ids(&["a"]));
+
+        // The last link of a group goes away along with it
+        unlink(&pool, "a").await.unwrap();
+        let value: Option<String> =
+            sqlx::query_scalar("SELECT linked_workspace_ids FROM workspaces WHERE id = 'c'")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(value, None);
+    }
+
+    #[test]
+    fn test_diff_worktrees() {
+        let root = std::env::temp_dir().join(format!("letsvibe-links-{}", uuid::Uuid::new_v4()));
+        let repo = root.join("repo");
+        let other = root.join("other");
+        std::fs::create_dir_all(&repo).unwrap();
+        let git = |dir: &Path, args: &[&str]| run_git(dir, args).unwrap();
+        git(&repo, &["init", "-q"]);
+        git(&repo, &["config", "user.email", "test@example.com"]);
+        git(&repo, &["config", "user.name", "Test"]);
+        std::fs::write(repo.join("shared.txt"), "one\n").unwrap();
+        git(&repo, &["add", "."]);
+        git(&repo, &["commit", "-qm", "initial"]);
+        git(
+            &repo,
+            &[
+                "worktree",
+                "add",
+                "-q",
+                "-b",
+                "other",
+                &other.to_string_lossy(),
+            ],
+        );
+
+        // Uncommitted and untracked changes on both sides are compared
+        std::fs::write(repo.join("shared.txt"), "one\ntwo\n").unwrap();
+        std::fs::write(other.join("new.txt"), "new\n").unwrap();
+
+        let files = diff_worktrees(&repo, &other, &DiffOptions::default()).unwrap();
+        let paths: Vec<_> = files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, ["new.txt", "shared.txt"]);
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
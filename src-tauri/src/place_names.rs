@@ -73,6 +73,19 @@ pub fn select_available_name(used_names: &[String]) -> Option<String> {
     available.choose(&mut rng).map(|s| s.to_string())
 }
 
+/// First port handed out to workspaces through `LETSVIBE_PORT`
+const WORKSPACE_PORT_BASE: u16 = 55000;
+
+/// Ports reserved for each workspace, starting at its `LETSVIBE_PORT`
+const WORKSPACE_PORT_RANGE: u16 = 10;
+
+/// Port assigned to the workspace with the given place name, so every
+/// workspace of a repository gets its own block of ports
+pub fn workspace_port(name: &str) -> Option<u16> {
+    let index = PLACE_NAMES.iter().position(|place| *place == name)?;
+    Some(WORKSPACE_PORT_BASE + index as u16 * WORKSPACE_PORT_RANGE)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -102,4 +115,11 @@ mod tests {
         let result = select_available_name(&used);
         assert!(result.is_none());
     }
+
+    #[test]
+    fn test_workspace_port() {
+        assert_eq!(workspace_port("andorra"), Some(55000));
+        assert_eq!(workspace_port("barcelona"), Some(55010));
+        assert_eq!(workspace_port("atlantis"), None);
+    }
 }
@@ -0,0 +1,531 @@
+use crate::commands::load_workspace_context;
+use crate::comments::{
+    anchor_for_line, get_comment, CommentAnchor, COMMENT_STATE_OPEN, COMMENT_STATE_OUTDATED,
+};
+use crate::db::models::DiffComment;
+use crate::forge::{
+    forge_repo, load_forge, Forge, ForgeRepo, ReviewComment, WORKSPACE_STATE_PR_OPEN,
+};
+use crate::{AppState, DbPool};
+use serde::Serialize;
+use sqlx::{Sqlite, Transaction};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use tauri::State;
+
+/// Outcome of syncing a workspace's comments with its pull request
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReviewSyncResult {
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+    /// Local replies posted to the pull request
+    pub pushed: usize,
+}
+
+impl ReviewSyncResult {
+    fn changed(&self) -> bool {
+        self.added + self.updated + self.removed + self.pushed > 0
+    }
+}
+
+/// Event emitted as `review-comments-synced` when the background sync changed a
+/// workspace's comments
+#[derive(Debug, Clone, Serialize)]
+pub struct ReviewSyncEvent {
+    pub workspace_id: String,
+    #[serde(flatten)]
+    pub result: ReviewSyncResult,
+}
+
+/// The pull request a workspace's comments are synced with
+struct SyncTarget<'a> {
+    workspace_id: &'a str,
+    worktree_path: &'a Path,
+    repo: ForgeRepo,
+    number: i64,
+}
+
+/// Mirror the review comments of a workspace's pull request into its diff comments.
+/// With `push_replies`, replies written in the app to threads from the pull request are
+/// posted there first. Comments started in the app aren't posted, since they have no
+/// place in the pull request's diff.
+#[tauri::command]
+pub async fn sync_review_comments(
+    state: State<'_, AppState>,
+    workspace_id: String,
+    push_replies: Option<bool>,
+) -> Result<ReviewSyncResult, String> {
+    let pool = state.pool().await?;
+    let context = load_workspace_context(&pool, &workspace_id).await?;
+    let number = context
+        .workspace
+        .pr_number
+        .ok_or("Workspace has no pull request")?;
+    let forge = load_forge(&pool).await?;
+
+    let target = SyncTarget {
+        workspace_id: &workspace_id,
+        worktree_path: &context.worktree_path,
+        repo: forge_repo(&context)?,
+        number,
+    };
+    sync_comments(&pool, forge.as_ref(), &target, push_replies.unwrap_or(true)).await
+}
+
+/// Pull the review comments of every open pull request, for the background poller
+pub(crate) async fn sync_open_pull_requests<F>(pool: &DbPool, emit: F) -> Result<(), String>
+where
+    F: Fn(ReviewSyncEvent),
+{
+    let workspaces: Vec<(String, i64)> = sqlx::query_as(
+        "SELECT id, pr_number FROM workspaces WHERE pr_number IS NOT NULL AND state = ?",
+    )
+    .bind(WORKSPACE_STATE_PR_OPEN)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Failed to load pull requests: {}", e))?;
+    if workspaces.is_empty() {
+        return Ok(());
+    }
+
+    let forge = load_forge(pool).await?;
+    for (workspace_id, number) in workspaces {
+        // A workspace that fails to load is skipped like one whose sync failed
+        let result = match load_workspace_context(pool, &workspace_id)
+            .await
+            .and_then(|context| Ok((forge_repo(&context)?, context)))
+        {
+            Ok((repo, context)) => {
+                let target = SyncTarget {
+                    workspace_id: &workspace_id,
+                    worktree_path: &context.worktree_path,
+                    repo,
+                    number,
+                };
+                sync_comments(pool, forge.as_ref(), &target, false).await
+            }
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(result) if result.changed() => emit(ReviewSyncEvent {
+                workspace_id,
+                result,
+            }),
+            Ok(_) => {}
+            Err(e) => eprintln!("Failed to sync review comments of {}: {}", workspace_id, e),
+        }
+    }
+    Ok(())
+}
+
+async fn sync_comments(
+    pool: &DbPool,
+    forge: &dyn Forge,
+    target: &SyncTarget<'_>,
+    push_replies: bool,
+) -> Result<ReviewSyncResult, String> {
+    let mut result = ReviewSyncResult::default();
+    if push_replies {
+        result.pushed = push_local_replies(pool, forge, target).await?;
+    }
+
+    let remote = forge
+        .list_review_comments(&target.repo, target.number)
+        .await?;
+    let synced: Vec<DiffComment> = sqlx::query_as(
+        "SELECT * FROM diff_comments WHERE workspace_id = ? AND remote_comment_id IS NOT NULL",
+    )
+    .bind(target.workspace_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+    let mut local: HashMap<i64, DiffComment> = synced
+        .into_iter()
+        .filter_map(|c| Some((c.remote_comment_id?, c)))
+        .collect();
+
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+    for comment in &remote {
+        if let Some(existing) = local.get(&comment.id) {
+            if existing.body.as_deref() != Some(comment.body.as_str()) {
+                sqlx::query("UPDATE diff_comments SET body = ? WHERE id = ?")
+                    .bind(&comment.body)
+                    .bind(&existing.id)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| format!("Failed to update comment: {}", e))?;
+                result.updated += 1;
+            }
+            continue;
+        }
+
+        // Replies take their position from the thread; a reply whose thread is gone starts one
+        let parent = comment.in_reply_to.and_then(|id| local.get(&id)).cloned();
+        let inserted = match parent {
+            Some(parent) => {
+                let root_id = parent.thread_id.clone().unwrap_or(parent.id.clone());
+                let root: DiffComment = sqlx::query_as("SELECT * FROM diff_comments WHERE id = ?")
+                    .bind(&root_id)
+                    .fetch_one(&mut *tx)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                new_comment(comment, target.workspace_id, Some((&root, &parent.id)))
+            }
+            None => {
+                let mut inserted = new_comment(comment, target.workspace_id, None);
+                let anchor = match comment.line {
+                    Some(line) => {
+                        anchor_for_line(target.worktree_path, &comment.path, line, &comment.side)
+                    }
+                    None => CommentAnchor {
+                        side: comment.side.clone(),
+                        line_content: String::new(),
+                        context_before: Vec::new(),
+                        context_after: Vec::new(),
+                    },
+                };
+                inserted.location =
+                    Some(serde_json::to_string(&anchor).map_err(|e| e.to_string())?);
+                inserted
+            }
+        };
+
+        sqlx::query(
+            r#"
+            INSERT INTO diff_comments (id, workspace_id, file_path, line_number, body, state,
+                                       location, created_at, remote_url, author, thread_id,
+                                       reply_to_comment_id, update_memory, remote_comment_id)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, 0, ?)
+            "#,
+        )
+        .bind(&inserted.id)
+        .bind(&inserted.workspace_id)
+        .bind(&inserted.file_path)
+        .bind(inserted.line_number)
+        .bind(&inserted.body)
+        .bind(&inserted.state)
+        .bind(&inserted.location)
+        .bind(inserted.created_at)
+        .bind(&inserted.remote_url)
+        .bind(&inserted.author)
+        .bind(&inserted.thread_id)
+        .bind(&inserted.reply_to_comment_id)
+        .bind(inserted.remote_comment_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| format!("Failed to save comment: {}", e))?;
+        local.insert(comment.id, inserted);
+        result.added += 1;
+    }
+
+    // Comments deleted on the forge; deleting a thread's first comment removes the thread
+    let remote_ids: HashSet<i64> = remote.iter().map(|c| c.id).collect();
+    for (_, gone) in local.iter().filter(|(id, _)| !remote_ids.contains(id)) {
+        result.removed += remove_remote_comment(&mut tx, gone).await?;
+    }
+    tx.commit().await.map_err(|e| e.to_string())?;
+
+    Ok(result)
+}
+
+/// Delete a comment that's gone from the forge, along with the synced rest of its thread
+/// when it started one. Replies written in the app and not posted yet are kept, as a
+/// thread of their own. Returns how many comments were deleted.
+async fn remove_remote_comment(
+    tx: &mut Transaction<'_, Sqlite>,
+    gone: &DiffComment,
+) -> Result<usize, String> {
+    let is_root = gone.thread_id.as_deref().is_none_or(|id| id == gone.id);
+    if !is_root {
+        sqlx::query(
+            "UPDATE diff_comments SET reply_to_comment_id = ? WHERE reply_to_comment_id = ?",
+        )
+        .bind(&gone.reply_to_comment_id)
+        .bind(&gone.id)
+        .execute(&mut **tx)
+        .await
+        .map_err(|e| e.to_string())?;
+        let deleted = sqlx::query("DELETE FROM diff_comments WHERE id = ?")
+            .bind(&gone.id)
+            .execute(&mut **tx)
+            .await
+            .map_err(|e| format!("Failed to delete comment: {}", e))?;
+        return Ok(deleted.rows_affected() as usize);
+    }
+
+    let deleted = sqlx::query(
+        "DELETE FROM diff_comments WHERE (id = ? OR thread_id = ?) AND remote_comment_id IS NOT NULL",
+    )
+    .bind(&gone.id)
+    .bind(&gone.id)
+    .execute(&mut **tx)
+    .await
+    .map_err(|e| format!("Failed to delete comment: {}", e))?;
+
+    // The oldest unposted reply takes over as the first comment of the thread
+    let new_root: Option<String> = sqlx::query_scalar(
+        "SELECT id FROM diff_comments WHERE thread_id = ? ORDER BY created_at, rowid LIMIT 1",
+    )
+    .bind(&gone.id)
+    .fetch_optional(&mut **tx)
+    .await
+    .map_err(|e| e.to_string())?;
+    if let Some(new_root) = new_root {
+        sqlx::query(
+            r#"
+            UPDATE diff_comments
+            SET thread_id = ?1,
+                reply_to_comment_id = CASE WHEN id = ?1 THEN NULL ELSE ?1 END
+            WHERE thread_id = ?2
+            "#,
+        )
+        .bind(&new_root)
+        .bind(&gone.id)
+        .execute(&mut **tx)
+        .await
+        .map_err(|e| e.to_string())?;
+    }
+
+    Ok(deleted.rows_affected() as usize)
+}
+
+/// Post the replies written in the app to threads that came from the pull request
+async fn push_local_replies(
+    pool: &DbPool,
+    forge: &dyn Forge,
+    target: &SyncTarget<'_>,
+) -> Result<usize, String> {
+    let replies: Vec<DiffComment> = sqlx::query_as(
+        r#"
+        SELECT reply.* FROM diff_comments reply
+        JOIN diff_comments root ON root.id = reply.thread_id
+        WHERE reply.workspace_id = ? AND reply.id != root.id
+          AND reply.remote_comment_id IS NULL AND root.remote_comment_id IS NOT NULL
+        ORDER BY reply.created_at, reply.rowid
+        "#,
+    )
+    .bind(target.workspace_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let mut pushed = 0;
+    for reply in replies {
+        let (Some(thread_id), Some(body)) = (&reply.thread_id, &reply.body) else {
+            continue;
+        };
+        let Some(root_remote_id) = get_comment(pool, thread_id).await?.remote_comment_id else {
+            continue;
+        };
+        let posted = forge
+            .reply_to_review_comment(&target.repo, target.number, root_remote_id, body)
+            .await?;
+        sqlx::query("UPDATE diff_comments SET remote_comment_id = ?, remote_url = ? WHERE id = ?")
+            .bind(posted.id)
+            .bind(&posted.url)
+            .bind(&reply.id)
+            .execute(pool)
+            .await
+            .map_err(|e| format!("Failed to save comment: {}", e))?;
+        pushed += 1;
+    }
+    Ok(pushed)
+}
+
+/// A diff comment for a review comment, either replying to a comment in `root`'s thread
+/// or starting its own
+fn new_comment(
+    comment: &ReviewComment,
+    workspace_id: &str,
+    thread: Option<(&DiffComment, &str)>,
+) -> DiffComment {
+    let id = uuid::Uuid::new_v4().to_string();
+    let (file_path, line_number, state, location, thread_id, reply_to) = match thread {
+        Some((root, reply_to)) => (
+            root.file_path.clone(),
+            root.line_number,
+            root.state.clone(),
+            root.location.clone(),
+            root.id.clone(),
+            Some(reply_to.to_string()),
+        ),
+        None => (
+            Some(comment.path.clone()),
+            comment.line.or(comment.original_line),
+            // The forge no longer shows comments whose line is gone
+            Some(
+                if comment.line.is_some() {
+                    COMMENT_STATE_OPEN
+                } else {
+                    COMMENT_STATE_OUTDATED
+                }
+                .to_string(),
+            ),
+            None,
+            id.clone(),
+            None,
+        ),
+    };
+    DiffComment {
+        id,
+        workspace_id: Some(workspace_id.to_string()),
+        file_path,
+        line_number,
+        body: Some(comment.body.clone()),
+        state,
+        location,
+        created_at: comment.created_at,
+        remote_url: Some(comment.url.clone()),
+        author: comment.author.clone(),
+        thread_id: Some(thread_id),
+        reply_to_comment_id: reply_to,
+        update_memory: Some(0),
+        addressed_turn_id: None,
+        remote_comment_id: Some(comment.id),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_pool;
+    use crate::forge::{NewPullRequest, PullRequest};
+    use async_trait::async_trait;
+    use std::sync::Mutex;
+
+    /// A forge holding review comments in memory
+    #[derive(Default)]
+    struct FakeForge {
+        comments: Mutex<Vec<ReviewComment>>,
+    }
+
+    #[async_trait]
+    impl Forge for FakeForge {
+        async fn create_pull_request(
+            &self,
+            _repo: &ForgeRepo,
+            _request: &NewPullRequest,
+        ) -> Result<PullRequest, String> {
+            Err("not supported by FakeForge".into())
+        }
+
+        async fn get_pull_request(
+            &self,
+            _repo: &ForgeRepo,
+            _number: i64,
+        ) -> Result<PullRequest, String> {
+            Err("not supported by FakeForge".into())
+        }
+
+        async fn list_review_comments(
+            &self,
+            _repo: &ForgeRepo,
+            _number: i64,
+        ) -> Result<Vec<ReviewComment>, String> {
+            Ok(self.comments.lock().unwrap().clone())
+        }
+
+        async fn reply_to_review_comment(
+            &self,
+            _repo: &ForgeRepo,
+            _number: i64,
+            comment_id: i64,
+            body: &str,
+        ) -> Result<ReviewComment, String> {
+            let mut comments = self.comments.lock().unwrap();
+            let reply = review_comment(100 + comments.len() as i64, Some(comment_id), body);
+            comments.push(reply.clone());
+            Ok(reply)
+        }
+    }
+
+    fn review_comment(id: i64, in_reply_to: Option<i64>, body: &str) -> ReviewComment {
+        ReviewComment {
+            id,
+            in_reply_to,
+            path: "src/main.rs".to_string(),
+            line: Some(2),
+            original_line: Some(2),
+            side: "new".to_string(),
+            body: body.to_string(),
+            author: Some("reviewer".to_string()),
+            url: format!("https://github.com/me/app/pull/7#discussion_r{}", id),
+            created_at: id,
+        }
+    }
+
+    async fn comments(pool: &DbPool) -> Vec<DiffComment> {
+        sqlx::query_as("SELECT * FROM diff_comments ORDER BY created_at, rowid")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_sync_comments() {
+        let pool = test_pool().await;
+        let worktree = std::env::temp_dir();
+        let target = SyncTarget {
+            workspace_id: "ws",
+            worktree_path: &worktree,
+            repo: ForgeRepo {
+                owner: "me".to_string(),
+                name: "app".to_string(),
+            },
+            number: 7,
+        };
+        let forge = FakeForge::default();
+        *forge.comments.lock().unwrap() = vec![
+            review_comment(1, None, "Rename this"),
+            review_comment(2, Some(1), "Agreed"),
+        ];
+
+        let result = sync_comments(&pool, &forge, &target, true).await.unwrap();
+        assert_eq!((result.added, result.pushed), (2, 0));
+        let synced = comments(&pool).await;
+        assert_eq!(synced[0].thread_id.as_ref(), Some(&synced[0].id));
+        assert_eq!(synced[1].thread_id.as_ref(), Some(&synced[0].id));
+        assert_eq!(synced[1].reply_to_comment_id.as_ref(), Some(&synced[0].id));
+        assert_eq!(synced[1].author.as_deref(), Some("reviewer"));
+
+        // A local reply is posted to the thread and not duplicated when pulled back
+        sqlx::query(
+            "INSERT INTO diff_comments (id, workspace_id, body, state, created_at, thread_id)
+             VALUES ('local', 'ws', 'Done', 'open', 50, ?)",
+        )
+        .bind(&synced[0].id)
+        .execute(&pool)
+        .await
+        .unwrap();
+        forge.comments.lock().unwrap()[0].body = "Rename this please".to_string();
+        let result = sync_comments(&pool, &forge, &target, true).await.unwrap();
+        assert_eq!((result.added, result.updated, result.pushed), (0, 1, 1));
+        assert_eq!(forge.comments.lock().unwrap()[2].in_reply_to, Some(1));
+        assert_eq!(
+            get_comment(&pool, "local").await.unwrap().remote_comment_id,
+            Some(102)
+        );
+
+        // A reply that hasn't been posted yet
+        sqlx::query(
+            "INSERT INTO diff_comments (id, workspace_id, body, state, created_at, thread_id, reply_to_comment_id)
+             VALUES ('unposted', 'ws', 'One more thing', 'open', 200, ?, 'local')",
+        )
+        .bind(&synced[0].id)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        // Deleting the first comment on the forge removes the synced thread, but the
+        // unposted reply is kept as a thread of its own
+        forge.comments.lock().unwrap().remove(0);
+        let result = sync_comments(&pool, &forge, &target, false).await.unwrap();
+        assert_eq!(result.removed, 3);
+        let kept = comments(&pool).await;
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].id, "unposted");
+        assert_eq!(kept[0].thread_id.as_deref(), Some("unposted"));
+        assert_eq!(kept[0].reply_to_comment_id, None);
+    }
+}
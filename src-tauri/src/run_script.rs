@@ -0,0 +1,321 @@
+use crate::commands::{load_workspace_context, workspace_script_env};
+use crate::scripts::{output_lines, shell_command, signal_process_group, StopSignal};
+use crate::AppState;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, State};
+use tokio::sync::Mutex;
+
+/// `repos.run_script_mode` letting run scripts of several workspaces run side by side
+pub const RUN_SCRIPT_MODE_CONCURRENT: &str = "concurrent";
+
+/// `repos.run_script_mode` allowing one run script per repository at a time,
+/// for dev servers bound to a fixed port
+pub const RUN_SCRIPT_MODE_NONCONCURRENT: &str = "nonconcurrent";
+
+/// How long a run script gets to shut down before it is killed
+const STOP_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// A run script currently running in a workspace
+#[derive(Debug, Clone, Serialize)]
+pub struct RunningScript {
+    pub workspace_id: String,
+    pub repository_id: String,
+    pub run_id: String,
+    pub pid: Option<u32>,
+    /// Set when the script is stopped on purpose rather than exiting by itself
+    #[serde(skip)]
+    pub stopped: Arc<AtomicBool>,
+}
+
+/// Running scripts keyed by workspace id
+pub type RunningScripts = Arc<Mutex<HashMap<String, RunningScript>>>;
+
+/// Event emitted as `run-script-event` while a run script runs
+#[derive(Debug, Clone, Serialize)]
+pub struct RunScriptEvent {
+    pub workspace_id: String,
+    pub run_id: String,
+    #[serde(flatten)]
+    pub kind: RunScriptEventKind,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RunScriptEventKind {
+    Started {
+        pid: Option<u32>,
+    },
+    /// One line of the script's stdout or stderr
+    Output {
+        line: String,
+    },
+    Exited {
+        exit_code: Option<i32>,
+        /// Whether the script was stopped rather than exiting by itself
+        stopped: bool,
+    },
+}
+
+/// Start the repository's run script in a workspace. In `nonconcurrent` mode the
+/// run scripts of the repository's other workspaces are stopped first.
+#[tauri::command]
+pub async fn start_run_script(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    workspace_id: String,
+) -> Result<RunningScript, String> {
+    let pool = state.pool().await?;
+    let context = load_workspace_context(&pool, &workspace_id).await?;
+    let script = context
+        .repo
+        .run_script
+        .clone()
+        .filter(|script| !script.trim().is_empty())
+        .ok_or("Repository has no run script")?;
+    if !context.worktree_path.exists() {
+        return Err(format!(
+            "Workspace directory does not exist: {}",
+            context.worktree_path.display()
+        ));
+    }
+
+    let scripts = state.run_scripts.clone();
+    let mode = context
+        .repo
+        .run_script_mode
+        .as_deref()
+        .unwrap_or(RUN_SCRIPT_MODE_CONCURRENT);
+    if mode == RUN_SCRIPT_MODE_NONCONCURRENT {
+        let others: Vec<String> = scripts
+            .lock()
+            .await
+            .values()
+            .filter(|s| s.repository_id == context.repo.id && s.workspace_id != workspace_id)
+            .map(|s| s.workspace_id.clone())
+            .collect();
+        for other in others {
+            stop_workspace_script(&scripts, &other).await?;
+        }
+    }
+
+    let emit = move |event: RunScriptEvent| {
+        let _ = app.emit("run-script-event", event);
+    };
+    launch_run_script(
+        scripts,
+        RunScriptSpec {
+            workspace_id,
+            repository_id: context.repo.id.clone(),
+            script,
+            cwd: context.worktree_path.clone(),
+            envs: workspace_script_env(&context),
+        },
+        emit,
+    )
+    .await
+}
+
+/// What to run for a workspace's run script
+struct RunScriptSpec {
+    workspace_id: String,
+    repository_id: String,
+    script: String,
+    cwd: PathBuf,
+    envs: Vec<(&'static str, String)>,
+}
+
+/// Spawn the run script, register it and stream its output in the background
+async fn launch_run_script<F>(
+    scripts: RunningScripts,
+    spec: RunScriptSpec,
+    emit: F,
+) -> Result<RunningScript, String>
+where
+    F: Fn(RunScriptEvent) + Send + Sync + 'static,
+{
+    // Hold the lock from the check to the insert so a double click can't start two servers
+    let mut running = scripts.lock().await;
+    if running.contains_key(&spec.workspace_id) {
+        return Err("The run script is already running in this workspace".to_string());
+    }
+
+    let mut command = shell_command(&spec.script, &spec.cwd);
+    command
+        .envs(spec.envs)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    // Run the script in its own process group so stopping it also stops what it spawned
+    #[cfg(unix)]
+    command.process_group(0);
+    let mut child = command
+        .spawn()
+        .map_err(|e| format!("Failed to start run script: {}", e))?;
+
+    let script = RunningScript {
+        workspace_id: spec.workspace_id.clone(),
+        repository_id: spec.repository_id,
+        run_id: uuid::Uuid::new_v4().to_string(),
+        pid: child.id(),
+        stopped: Arc::new(AtomicBool::new(false)),
+    };
+    running.insert(spec.workspace_id.clone(), script.clone());
+    drop(running);
+
+    let workspace_id = spec.workspace_id;
+    let run_id = script.run_id.clone();
+    let event = move |kind: RunScriptEventKind| RunScriptEvent {
+        workspace_id: workspace_id.clone(),
+        run_id: run_id.clone(),
+        kind,
+    };
+    emit(event(RunScriptEventKind::Started { pid: script.pid }));
+
+    let mut lines = output_lines(&mut child);
+    let running_script = script.clone();
+    tauri::async_runtime::spawn(async move {
+        while let Some(line) = lines.recv().await {
+            emit(event(RunScriptEventKind::Output { line }));
+        }
+        let exit_code = child.wait().await.ok().and_then(|status| status.code());
+
+        let mut running = scripts.lock().await;
+        if running.get(&running_script.workspace_id).map(|s| &s.run_id)
+            == Some(&running_script.run_id)
+        {
+            running.remove(&running_script.workspace_id);
+        }
+        drop(running);
+
+        emit(event(RunScriptEventKind::Exited {
+            exit_code,
+            stopped: running_script.stopped.load(Ordering::SeqCst),
+        }));
+    });
+
+    Ok(script)
+}
+
+/// Stop the run script of a workspace
+#[tauri::command]
+pub async fn stop_run_script(
+    state: State<'_, AppState>,
+    workspace_id: String,
+) -> Result<(), String> {
+    if !state.run_scripts.lock().await.contains_key(&workspace_id) {
+        return Err("No run script is running in this workspace".to_string());
+    }
+    stop_workspace_script(&state.run_scripts, &workspace_id).await
+}
+
+/// List the run scripts currently running, e.g. to restore the UI after a reload
+#[tauri::command]
+pub async fn get_running_scripts(state: State<'_, AppState>) -> Result<Vec<RunningScript>, String> {
+    Ok(state.run_scripts.lock().await.values().cloned().collect())
+}
+
+/// Stop a workspace's run script if one is running, killing it when it ignores the request
+pub(crate) async fn stop_workspace_script(
+    scripts: &RunningScripts,
+    workspace_id: &str,
+) -> Result<(), String> {
+    let Some(script) = scripts.lock().await.get(workspace_id).cloned() else {
+        return Ok(());
+    };
+    script.stopped.store(true, Ordering::SeqCst);
+
+    if let Some(pid) = script.pid {
+        signal_process_group(pid, StopSignal::Terminate);
+    }
+    if wait_for_exit(scripts, workspace_id, &script.run_id, STOP_GRACE_PERIOD).await {
+        return Ok(());
+    }
+
+    if let Some(pid) = script.pid {
+        signal_process_group(pid, StopSignal::Kill);
+    }
+    if wait_for_exit(scripts, workspace_id, &script.run_id, STOP_GRACE_PERIOD).await {
+        Ok(())
+    } else {
+        Err("Run script did not exit after being killed".to_string())
+    }
+}
+
+/// Wait until the given run is no longer registered. Returns false on timeout.
+async fn wait_for_exit(
+    scripts: &RunningScripts,
+    workspace_id: &str,
+    run_id: &str,
+    timeout: Duration,
+) -> bool {
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        match scripts.lock().await.get(workspace_id) {
+            Some(script) if script.run_id == run_id => {}
+            _ => return true,
+        }
+        if tokio::time::Instant::now() >= deadline {
+            return false;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex as StdMutex;
+
+    fn spec(workspace_id: &str, script: &str) -> RunScriptSpec {
+        RunScriptSpec {
+            workspace_id: workspace_id.to_string(),
+            repository_id: "repo".to_string(),
+            script: script.to_string(),
+            cwd: std::env::temp_dir(),
+            envs: vec![("LETSVIBE_PORT", "55000".to_string())],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_script_output_and_stop() {
+        let scripts: RunningScripts = Arc::new(Mutex::new(HashMap::new()));
+        let events = Arc::new(StdMutex::new(Vec::new()));
+        let recorded = events.clone();
+        let emit = move |event: RunScriptEvent| recorded.lock().unwrap().push(event.kind);
+
+        launch_run_script(
+            scripts.clone(),
+            spec("ws", "echo port $LETSVIBE_PORT; sleep 30"),
+            emit,
+        )
+        .await
+        .unwrap();
+        assert!(
+            launch_run_script(scripts.clone(), spec("ws", "true"), |_| {})
+                .await
+                .is_err()
+        );
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        stop_workspace_script(&scripts, "ws").await.unwrap();
+        assert!(scripts.lock().await.is_empty());
+
+        // The exit event is emitted right after the script is unregistered
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let events = events.lock().unwrap();
+        assert!(matches!(events[0], RunScriptEventKind::Started { .. }));
+        assert!(events
+            .iter()
+            .any(|e| matches!(e, RunScriptEventKind::Output { line } if line == "port 55000")));
+        assert!(matches!(
+            events.last(),
+            Some(RunScriptEventKind::Exited { stopped: true, .. })
+        ));
+    }
+}
@@ -0,0 +1,179 @@
+use serde::Serialize;
+use std::future::Future;
+use std::path::Path;
+use std::process::{ExitStatus, Stdio};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::mpsc;
+
+/// Result of running one of a repository's scripts
+#[derive(Debug, Clone, Serialize)]
+pub struct ScriptOutput {
+    pub success: bool,
+    pub exit_code: Option<i32>,
+    /// Combined stdout and stderr
+    pub output: String,
+}
+
+/// Build a shell command that runs `script` inside `cwd`
+pub(crate) fn shell_command(script: &str, cwd: &Path) -> Command {
+    #[cfg(windows)]
+    let mut command = {
+        let mut command = Command::new("cmd");
+        command.arg("/C").arg(script);
+        command
+    };
+    #[cfg(not(windows))]
+    let mut command = {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(script);
+        command
+    };
+    command.current_dir(cwd);
+    command
+}
+
+/// Run a repository script (e.g. `archive_script`) to completion and capture its output
+pub(crate) async fn run_script(
+    script: &str,
+    cwd: &Path,
+    envs: &[(&str, String)],
+) -> Result<ScriptOutput, String> {
+    let output = shell_command(script, cwd)
+        .envs(envs.iter().map(|(key, value)| (key, value)))
+        .output()
+        .await
+        .map_err(|e| format!("Failed to run script: {}", e))?;
+
+    let mut combined = String::from_utf8_lossy(&output.stdout).to_string();
+    combined.push_str(&String::from_utf8_lossy(&output.stderr));
+
+    Ok(ScriptOutput {
+        success: output.status.success(),
+        exit_code: output.status.code(),
+        output: combined,
+    })
+}
+
+/// Run a script to completion, appending each line of its stdout and stderr to `log_path`
+/// and handing it to `on_line` as it arrives. The script runs in its own process group,
+/// whose id is passed to `on_start` once it's spawned so it can be stopped.
+pub(crate) async fn run_script_logged<F, S, Fut>(
+    script: &str,
+    cwd: &Path,
+    envs: &[(&str, String)],
+    log_path: &Path,
+    mut on_line: F,
+    on_start: S,
+) -> Result<ExitStatus, String>
+where
+    F: FnMut(&str),
+    S: FnOnce(Option<u32>) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    if let Some(parent) = log_path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|e| format!("Failed to create log directory: {}", e))?;
+    }
+    let mut log = tokio::fs::File::create(log_path)
+        .await
+        .map_err(|e| format!("Failed to create log file: {}", e))?;
+
+    let mut command = shell_command(script, cwd);
+    command
+        .envs(envs.iter().map(|(key, value)| (key, value)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    #[cfg(unix)]
+    command.process_group(0);
+    let mut child = command
+        .spawn()
+        .map_err(|e| format!("Failed to run script: {}", e))?;
+    on_start(child.id()).await;
+
+    let mut lines = output_lines(&mut child);
+    while let Some(line) = lines.recv().await {
+        // A full disk shouldn't abort the script, the lines still reach `on_line`
+        let _ = log.write_all(format!("{}\n", line).as_bytes()).await;
+        on_line(&line);
+    }
+
+    let status = child
+        .wait()
+        .await
+        .map_err(|e| format!("Failed to wait for script: {}", e))?;
+    let exit = match status.code() {
+        Some(code) => format!("Exited with code {}", code),
+        None => "Terminated by signal".to_string(),
+    };
+    let _ = log.write_all(format!("\n{}\n", exit).as_bytes()).await;
+    let _ = log.flush().await;
+
+    Ok(status)
+}
+
+/// How to ask a process group to stop
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum StopSignal {
+    /// Like Ctrl-C, for processes that clean up after an interrupt
+    Interrupt,
+    Terminate,
+    /// Can't be ignored; on Windows the only one that's forced
+    Kill,
+}
+
+/// Send a signal to the process group led by `pid`, which was spawned as its own group
+pub(crate) fn signal_process_group(pid: u32, signal: StopSignal) {
+    #[cfg(unix)]
+    {
+        let signal = match signal {
+            StopSignal::Interrupt => libc::SIGINT,
+            StopSignal::Terminate => libc::SIGTERM,
+            StopSignal::Kill => libc::SIGKILL,
+        };
+        // SAFETY: kill takes no pointers and has no memory effects on this process; a
+        // negative pid addresses the group, which at worst no longer exists (ESRCH)
+        unsafe {
+            libc::kill(-(pid as i32), signal);
+        }
+    }
+
+    #[cfg(windows)]
+    {
+        let mut command = std::process::Command::new("taskkill");
+        command.arg("/PID").arg(pid.to_string()).arg("/T");
+        if signal == StopSignal::Kill {
+            command.arg("/F");
+        }
+        let _ = command.output();
+    }
+}
+
+/// Merge a child's stdout and stderr into a single stream of lines.
+/// The channel closes once both pipes are drained.
+pub(crate) fn output_lines(child: &mut Child) -> mpsc::UnboundedReceiver<String> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    if let Some(stdout) = child.stdout.take() {
+        forward_lines(stdout, tx.clone());
+    }
+    if let Some(stderr) = child.stderr.take() {
+        forward_lines(stderr, tx);
+    }
+    rx
+}
+
+fn forward_lines<R>(reader: R, tx: mpsc::UnboundedSender<String>)
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(reader).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+}
@@ -0,0 +1,1396 @@
+use crate::checkpoint;
+use crate::commands::{get_setting, load_workspace_context};
+use crate::db::models::{Session, SessionMessage};
+use crate::scripts::{signal_process_group, StopSignal};
+use crate::{AppState, DbPool};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tauri::{AppHandle, Emitter, State};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::Mutex;
+
+/// Settings key for the agent CLI binary; defaults to `claude` on PATH. Sessions with an
+/// agent type use `agent_binary_path.<agent type>` instead, when it's set.
+const AGENT_BINARY_SETTING: &str = "agent_binary_path";
+const DEFAULT_AGENT_BINARY: &str = "claude";
+
+pub const STATUS_IDLE: &str = "idle";
+pub const STATUS_RUNNING: &str = "running";
+pub const STATUS_ERROR: &str = "error";
+/// A turn was still running when the app last quit
+pub const STATUS_INTERRUPTED: &str = "interrupted";
+
+/// Prompt sent by `resume_session` when the user doesn't supply one
+const RESUME_PROMPT: &str = "Continue where you left off.";
+
+const ALREADY_RUNNING: &str = "The agent is already working on a message in this session";
+
+/// How long a cancelled agent gets to exit after SIGINT before it is killed
+const CANCEL_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// An agent subprocess currently working on a turn
+#[derive(Debug, Clone)]
+pub struct RunningTurn {
+    pub turn_id: String,
+    pub pid: Option<u32>,
+    /// Set when the user cancels the turn, so it is recorded as cancelled rather than failed
+    pub cancelled: Arc<AtomicBool>,
+}
+
+/// Running turns keyed by session id
+pub type RunningTurns = Arc<Mutex<HashMap<String, RunningTurn>>>;
+
+/// Event emitted as `session-event` while a turn runs
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionEvent {
+    pub session_id: String,
+    pub turn_id: String,
+    #[serde(flatten)]
+    pub kind: SessionEventKind,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SessionEventKind {
+    Status { status: String },
+    Delta { text: String },
+    Message { message: Box<SessionMessage> },
+    Error { message: String },
+    Cancelled,
+}
+
+/// Everything needed to launch the agent for one turn
+#[derive(Debug, Clone)]
+struct TurnSpec {
+    session_id: String,
+    turn_id: String,
+    prompt: String,
+    cwd: PathBuf,
+    binary: String,
+    model: Option<String>,
+    permission_mode: Option<String>,
+    resume: Option<String>,
+    resume_at: Option<String>,
+    /// Resume into a new agent session rather than continuing the one in `resume`
+    fork: bool,
+    started_at: SystemTime,
+}
+
+/// A line of the agent's `stream-json` output we care about
+#[derive(Debug, PartialEq)]
+enum AgentEvent {
+    Init {
+        agent_session_id: String,
+    },
+    Delta {
+        text: String,
+    },
+    Message {
+        role: String,
+        sdk_message_id: Option<String>,
+        model: Option<String>,
+        content: String,
+    },
+    Result {
+        is_error: bool,
+        text: Option<String>,
+        /// What the agent run cost, in US dollars
+        cost_usd: Option<f64>,
+    },
+}
+
+/// Create a new session in a workspace and make it the workspace's active session
+#[tauri::command]
+pub async fn create_session(
+    state: State<'_, AppState>,
+    workspace_id: String,
+    model: Option<String>,
+    permission_mode: Option<String>,
+    agent_type: Option<String>,
+    title: Option<String>,
+) -> Result<Session, String> {
+    let db = state.db.lock().await;
+    let pool = db.as_ref().ok_or("Database not initialized")?;
+
+    // Make sure the workspace exists before attaching a session to it
+    load_workspace_context(pool, &workspace_id).await?;
+
+    insert_session(
+        pool,
+        &workspace_id,
+        model.as_deref(),
+        permission_mode.as_deref(),
+        agent_type.as_deref(),
+        title.as_deref(),
+    )
+    .await
+}
+
+/// Record a new session and make it its workspace's active session
+pub(crate) async fn insert_session(
+    pool: &DbPool,
+    workspace_id: &str,
+    model: Option<&str>,
+    permission_mode: Option<&str>,
+    agent_type: Option<&str>,
+    title: Option<&str>,
+) -> Result<Session, String> {
+    let id = uuid::Uuid::new_v4().to_string();
+
+    sqlx::query(
+        r#"
+        INSERT INTO sessions (id, workspace_id, model, permission_mode, agent_type, title)
+        VALUES (?, ?, ?, COALESCE(?, 'default'), ?, COALESCE(?, 'Untitled'))
+        "#,
+    )
+    .bind(&id)
+    .bind(workspace_id)
+    .bind(model)
+    .bind(permission_mode)
+    .bind(agent_type)
+    .bind(title)
+    .execute(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    sqlx::query(
+        "UPDATE workspaces SET active_session_id = ?, updated_at = datetime('now') WHERE id = ?",
+    )
+    .bind(&id)
+    .bind(workspace_id)
+    .execute(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let session: Session = sqlx::query_as("SELECT * FROM sessions WHERE id = ?")
+        .bind(&id)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(session)
+}
+
+/// Copy a session and its messages into a new session of another workspace, and make
+/// the copy that workspace's active session. The copy's first turn resumes the agent's
+/// conversation as a fork, so the original session can carry on independently.
+pub(crate) async fn clone_session_history(
+    pool: &DbPool,
+    session_id: &str,
+    workspace_id: &str,
+) -> Result<Session, String> {
+    let id = uuid::Uuid::new_v4().to_string();
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO sessions
+            (id, workspace_id, model, permission_mode, thinking_level, agent_type, title,
+             last_user_message_at, claude_session_id, resume_session_at, fork_session)
+        SELECT ?, ?, model, permission_mode, thinking_level, agent_type, title,
+               last_user_message_at, claude_session_id, resume_session_at,
+               claude_session_id IS NOT NULL
+        FROM sessions WHERE id = ?
+        "#,
+    )
+    .bind(&id)
+    .bind(workspace_id)
+    .bind(session_id)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let messages: Vec<SessionMessage> =
+        sqlx::query_as("SELECT * FROM session_messages WHERE session_id = ? ORDER BY rowid")
+            .bind(session_id)
+            .fetch_all(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+    for message in messages {
+        sqlx::query(
+            r#"
+            INSERT INTO session_messages
+                (id, session_id, role, content, created_at, sent_at, full_message,
+                 cancelled_at, model, sdk_message_id, last_assistant_message_id, turn_id)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(uuid::Uuid::new_v4().to_string())
+        .bind(&id)
+        .bind(&message.role)
+        .bind(&message.content)
+        .bind(&message.created_at)
+        .bind(&message.sent_at)
+        .bind(&message.full_message)
+        .bind(&message.cancelled_at)
+        .bind(&message.model)
+        .bind(&message.sdk_message_id)
+        .bind(&message.last_assistant_message_id)
+        // The checkpoints of these turns belong to the original session
+        .bind(None::<String>)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+    }
+
+    sqlx::query(
+        "UPDATE workspaces SET active_session_id = ?, updated_at = datetime('now') WHERE id = ?",
+    )
+    .bind(&id)
+    .bind(workspace_id)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let session: Session = sqlx::query_as("SELECT * FROM sessions WHERE id = ?")
+        .bind(&id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+    tx.commit().await.map_err(|e| e.to_string())?;
+
+    Ok(session)
+}
+
+/// List the sessions of a workspace, oldest first
+#[tauri::command]
+pub async fn get_sessions(
+    state: State<'_, AppState>,
+    workspace_id: String,
+) -> Result<Vec<Session>, String> {
+    let db = state.db.lock().await;
+    let pool = db.as_ref().ok_or("Database not initialized")?;
+
+    let sessions: Vec<Session> =
+        sqlx::query_as("SELECT * FROM sessions WHERE workspace_id = ? ORDER BY created_at, rowid")
+            .bind(&workspace_id)
+            .fetch_all(pool)
+            .await
+            .map_err(|e| e.to_string())?;
+
+    Ok(sessions)
+}
+
+/// List the messages of a session in the order they were recorded
+#[tauri::command]
+pub async fn get_session_messages(
+    state: State<'_, AppState>,
+    session_id: String,
+) -> Result<Vec<SessionMessage>, String> {
+    let db = state.db.lock().await;
+    let pool = db.as_ref().ok_or("Database not initialized")?;
+
+    let messages: Vec<SessionMessage> =
+        sqlx::query_as("SELECT * FROM session_messages WHERE session_id = ? ORDER BY rowid")
+            .bind(&session_id)
+            .fetch_all(pool)
+            .await
+            .map_err(|e| e.to_string())?;
+
+    Ok(messages)
+}
+
+/// Send a user message to a session. The agent runs in the background and streams
+/// its output as `session-event`s; the new turn id is returned immediately.
+#[tauri::command]
+pub async fn send_session_message(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    session_id: String,
+    prompt: String,
+) -> Result<String, String> {
+    // The turn outlives this command, so work with a pool handle instead of the lock
+    let pool = state.pool().await?;
+    let turns = state.agent_turns.clone();
+
+    start_turn(app, pool, turns, &session_id, &prompt).await
+}
+
+/// Continue a session's agent conversation, e.g. after it was interrupted by the app
+/// quitting. The agent is relaunched with the stored agent session id and resume point.
+#[tauri::command]
+pub async fn resume_session(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    session_id: String,
+    prompt: Option<String>,
+) -> Result<String, String> {
+    let pool = state.pool().await?;
+    let turns = state.agent_turns.clone();
+
+    let claude_session_id: Option<String> =
+        sqlx::query_scalar("SELECT claude_session_id FROM sessions WHERE id = ?")
+            .bind(&session_id)
+            .fetch_one(&pool)
+            .await
+            .map_err(|e| format!("Session not found: {}", e))?;
+    if claude_session_id.is_none() {
+        return Err("Session has no agent conversation to resume".to_string());
+    }
+
+    let prompt = prompt.unwrap_or_else(|| RESUME_PROMPT.to_string());
+    start_turn(app, pool, turns, &session_id, &prompt).await
+}
+
+/// Cancel the running turn of a session. The agent is interrupted, killed if it doesn't
+/// exit within the grace period, and the turn's messages are marked as cancelled.
+#[tauri::command]
+pub async fn cancel_session_turn(
+    state: State<'_, AppState>,
+    session_id: String,
+) -> Result<(), String> {
+    let turns = state.agent_turns.clone();
+    cancel_turn(&turns, &session_id).await
+}
+
+async fn cancel_turn(turns: &RunningTurns, session_id: &str) -> Result<(), String> {
+    let turn = turns
+        .lock()
+        .await
+        .get(session_id)
+        .cloned()
+        .ok_or("No turn is running in this session")?;
+    turn.cancelled.store(true, Ordering::SeqCst);
+
+    // The agent leads its own process group, so this reaches the tools it spawned too
+    if let Some(pid) = turn.pid {
+        signal_process_group(pid, StopSignal::Interrupt);
+    }
+    if wait_for_turn_end(turns, session_id, &turn.turn_id, CANCEL_GRACE_PERIOD).await {
+        return Ok(());
+    }
+
+    if let Some(pid) = turn.pid {
+        signal_process_group(pid, StopSignal::Kill);
+    }
+    if wait_for_turn_end(turns, session_id, &turn.turn_id, CANCEL_GRACE_PERIOD).await {
+        Ok(())
+    } else {
+        Err("Agent did not exit after being killed".to_string())
+    }
+}
+
+/// Wait until the given turn is no longer registered. Returns false on timeout.
+async fn wait_for_turn_end(
+    turns: &RunningTurns,
+    session_id: &str,
+    turn_id: &str,
+    timeout: Duration,
+) -> bool {
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        match turns.lock().await.get(session_id) {
+            Some(turn) if turn.turn_id == turn_id => {}
+            _ => return true,
+        }
+        if tokio::time::Instant::now() >= deadline {
+            return false;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+/// Record the user's prompt, launch the agent and drive it to completion in the background
+pub(crate) async fn start_turn(
+    app: AppHandle,
+    pool: DbPool,
+    turns: RunningTurns,
+    session_id: &str,
+    prompt: &str,
+) -> Result<String, String> {
+    let session: Session = sqlx::query_as("SELECT * FROM sessions WHERE id = ?")
+        .bind(session_id)
+        .fetch_one(&pool)
+        .await
+        .map_err(|e| format!("Session not found: {}", e))?;
+
+    let workspace_id = session
+        .workspace_id
+        .as_ref()
+        .ok_or("Session has no workspace")?;
+    let context = load_workspace_context(&pool, workspace_id).await?;
+    if !context.worktree_path.exists() {
+        return Err(format!(
+            "Workspace directory does not exist: {}",
+            context.worktree_path.display()
+        ));
+    }
+
+    let binary = agent_binary(&pool, session.agent_type.as_deref()).await?;
+
+    let spec = TurnSpec {
+        session_id: session.id.clone(),
+        turn_id: uuid::Uuid::new_v4().to_string(),
+        prompt: prompt.to_string(),
+        cwd: context.worktree_path,
+        binary,
+        model: session.model.clone(),
+        permission_mode: session.permission_mode.clone(),
+        resume: session.claude_session_id.clone(),
+        resume_at: session.resume_session_at.clone(),
+        fork: session.fork_session.unwrap_or(0) != 0,
+        started_at: SystemTime::now(),
+    };
+
+    let emit = move |event: SessionEvent| {
+        let _ = app.emit("session-event", event);
+    };
+
+    launch_turn(pool, turns, spec, emit).await
+}
+
+/// The agent binary for a session: `agent_binary_path.<agent type>` when that's set,
+/// otherwise the default agent binary
+async fn agent_binary(pool: &DbPool, agent_type: Option<&str>) -> Result<String, String> {
+    if let Some(agent_type) = agent_type {
+        let key = format!("{}.{}", AGENT_BINARY_SETTING, agent_type);
+        if let Some(binary) = get_setting(pool, &key).await? {
+            return Ok(binary);
+        }
+    }
+    Ok(get_setting(pool, AGENT_BINARY_SETTING)
+        .await?
+        .unwrap_or_else(|| DEFAULT_AGENT_BINARY.to_string()))
+}
+
+/// Register a turn, record the prompt and start the agent; the turn is driven to
+/// completion in the background. Returns the turn id.
+async fn launch_turn<F>(
+    pool: DbPool,
+    turns: RunningTurns,
+    spec: TurnSpec,
+    emit: F,
+) -> Result<String, String>
+where
+    F: Fn(SessionEvent) + Send + Sync + 'static,
+{
+    if turns.lock().await.contains_key(&spec.session_id) {
+        return Err(ALREADY_RUNNING.to_string());
+    }
+
+    // Snapshot the worktree so this turn can be rolled back. On a large worktree this
+    // takes a while, so it runs off the async threads and outside the registry lock. A
+    // failed snapshot shouldn't stop the agent from running.
+    let (cwd, session_id, turn_id) = (
+        spec.cwd.clone(),
+        spec.session_id.clone(),
+        spec.turn_id.clone(),
+    );
+    let checkpoint = tokio::task::spawn_blocking(move || {
+        checkpoint::create_checkpoint(&cwd, &session_id, &turn_id)
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+    if let Err(e) = &checkpoint {
+        eprintln!("Failed to create checkpoint: {}", e);
+    }
+
+    // Hold the registry lock until the turn is registered so two sends can't race
+    let mut running = turns.lock().await;
+    if running.contains_key(&spec.session_id) {
+        // The other send got there first, so this turn's snapshot won't be used
+        if let Ok(Some(_)) = checkpoint {
+            let _ = checkpoint::delete_checkpoint(&spec.cwd, &spec.session_id, &spec.turn_id);
+        }
+        return Err(ALREADY_RUNNING.to_string());
+    }
+
+    let user_message = insert_message(
+        &pool,
+        &spec.session_id,
+        &spec.turn_id,
+        "user",
+        &spec.prompt,
+        None,
+        None,
+        None,
+    )
+    .await?;
+
+    let child = match spawn_agent(&spec) {
+        Ok(child) => child,
+        Err(e) => {
+            set_session_status(&pool, &spec.session_id, STATUS_ERROR).await?;
+            return Err(e);
+        }
+    };
+    let cancelled = Arc::new(AtomicBool::new(false));
+    running.insert(
+        spec.session_id.clone(),
+        RunningTurn {
+            turn_id: spec.turn_id.clone(),
+            pid: child.id(),
+            cancelled: cancelled.clone(),
+        },
+    );
+    drop(running);
+
+    set_session_status(&pool, &spec.session_id, STATUS_RUNNING).await?;
+    sqlx::query("UPDATE sessions SET last_user_message_at = datetime('now') WHERE id = ?")
+        .bind(&spec.session_id)
+        .execute(&pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    emit(SessionEvent {
+        session_id: spec.session_id.clone(),
+        turn_id: spec.turn_id.clone(),
+        kind: SessionEventKind::Message {
+            message: Box::new(user_message),
+        },
+    });
+    emit(SessionEvent {
+        session_id: spec.session_id.clone(),
+        turn_id: spec.turn_id.clone(),
+        kind: SessionEventKind::Status {
+            status: STATUS_RUNNING.to_string(),
+        },
+    });
+
+    let turn_id = spec.turn_id.clone();
+    tauri::async_runtime::spawn(async move {
+        let outcome = drive_turn(&pool, &spec, child, &emit).await;
+        // Finish before unregistering so a waiting cancel sees the final state
+        finish_turn(
+            &pool,
+            &spec,
+            outcome,
+            cancelled.load(Ordering::SeqCst),
+            &emit,
+        )
+        .await;
+        turns.lock().await.remove(&spec.session_id);
+    });
+
+    Ok(turn_id)
+}
+
+/// Launch the agent CLI for a turn with streaming JSON output
+fn spawn_agent(spec: &TurnSpec) -> Result<Child, String> {
+    let mut command = Command::new(&spec.binary);
+    command
+        .current_dir(&spec.cwd)
+        .arg("-p")
+        .arg(&spec.prompt)
+        .arg("--output-format")
+        .arg("stream-json")
+        .arg("--verbose")
+        .arg("--include-partial-messages");
+
+    if let Some(model) = &spec.model {
+        command.arg("--model").arg(model);
+    }
+    if let Some(permission_mode) = &spec.permission_mode {
+        command.arg("--permission-mode").arg(permission_mode);
+    }
+    if let Some(resume) = &spec.resume {
+        command.arg("--resume").arg(resume);
+        if let Some(resume_at) = &spec.resume_at {
+            command.arg("--resume-session-at").arg(resume_at);
+        }
+        if spec.fork {
+            command.arg("--fork-session");
+        }
+    }
+
+    // Run the agent in its own process group so cancelling also stops the tools it spawned
+    #[cfg(unix)]
+    command.process_group(0);
+
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("Failed to start agent '{}': {}", spec.binary, e))
+}
+
+/// Run the agent once on a prompt outside any session, for one-off text like commit
+/// messages. Returns its reply without a surrounding code fence.
+pub(crate) async fn ask_agent(pool: &DbPool, cwd: &Path, prompt: &str) -> Result<String, String> {
+    let binary = get_setting(pool, AGENT_BINARY_SETTING)
+        .await?
+        .unwrap_or_else(|| DEFAULT_AGENT_BINARY.to_string());
+    let output = Command::new(&binary)
+        .current_dir(cwd)
+        .arg("-p")
+        .arg(prompt)
+        .arg("--output-format")
+        .arg("text")
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| format!("Failed to start agent '{}': {}", binary, e))?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    Ok(strip_code_fence(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Cut text put into a prompt down to `limit` bytes, marking that it was cut
+pub(crate) fn truncate_for_prompt(text: &mut String, limit: usize) {
+    if text.len() <= limit {
+        return;
+    }
+    let mut end = limit;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text.truncate(end);
+    text.push_str("\n[truncated]\n");
+}
+
+/// Strip a code fence the agent may have wrapped its reply in
+fn strip_code_fence(output: &str) -> String {
+    let trimmed = output.trim();
+    let unfenced = trimmed
+        .strip_prefix("```")
+        .and_then(|rest| rest.strip_suffix("```"))
+        .map(|inner| inner.split_once('\n').map_or(inner, |(_, body)| body))
+        .unwrap_or(trimmed);
+    unfenced.trim().to_string()
+}
+
+/// Read the agent's output until it exits, persisting messages and emitting events.
+/// Returns an error message if the turn failed.
+async fn drive_turn<F>(
+    pool: &DbPool,
+    spec: &TurnSpec,
+    mut child: Child,
+    emit: &F,
+) -> Result<(), String>
+where
+    F: Fn(SessionEvent),
+{
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or("Failed to capture agent output")?;
+    let mut stderr = child
+        .stderr
+        .take()
+        .ok_or("Failed to capture agent errors")?;
+
+    // Drain stderr concurrently so a chatty agent can't block on a full pipe
+    let stderr_task = tokio::spawn(async move {
+        let mut output = String::new();
+        let _ = stderr.read_to_string(&mut output).await;
+        output
+    });
+
+    let mut lines = BufReader::new(stdout).lines();
+    let mut last_assistant_message_id: Option<String> = None;
+    let mut result: Option<(bool, Option<String>)> = None;
+
+    while let Some(line) = lines
+        .next_line()
+        .await
+        .map_err(|e| format!("Failed to read agent output: {}", e))?
+    {
+        let Some(event) = parse_agent_line(&line) else {
+            continue;
+        };
+
+        match event {
+            AgentEvent::Init { agent_session_id } => {
+                // A forked session has its own agent session from here on
+                sqlx::query(
+                    "UPDATE sessions SET claude_session_id = ?, fork_session = NULL WHERE id = ?",
+                )
+                .bind(&agent_session_id)
+                .bind(&spec.session_id)
+                .execute(pool)
+                .await
+                .map_err(|e| e.to_string())?;
+            }
+            AgentEvent::Delta { text } => {
+                emit(SessionEvent {
+                    session_id: spec.session_id.clone(),
+                    turn_id: spec.turn_id.clone(),
+                    kind: SessionEventKind::Delta { text },
+                });
+            }
+            AgentEvent::Message {
+                role,
+                sdk_message_id,
+                model,
+                content,
+            } => {
+                let message = insert_message(
+                    pool,
+                    &spec.session_id,
+                    &spec.turn_id,
+                    &role,
+                    &content,
+                    Some(&line),
+                    model.as_deref(),
+                    sdk_message_id.as_deref(),
+                )
+                .await?;
+
+                if role == "assistant" {
+                    last_assistant_message_id = sdk_message_id;
+                } else if let Some(assistant_id) = &last_assistant_message_id {
+                    sqlx::query(
+                        "UPDATE session_messages SET last_assistant_message_id = ? WHERE id = ?",
+                    )
+                    .bind(assistant_id)
+                    .bind(&message.id)
+                    .execute(pool)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                }
+
+                emit(SessionEvent {
+                    session_id: spec.session_id.clone(),
+                    turn_id: spec.turn_id.clone(),
+                    kind: SessionEventKind::Message {
+                        message: Box::new(message),
+                    },
+                });
+            }
+            AgentEvent::Result {
+                is_error,
+                text,
+                cost_usd,
+            } => {
+                if let Some(cost) = cost_usd {
+                    sqlx::query(
+                        "UPDATE sessions SET cost_usd = COALESCE(cost_usd, 0) + ? WHERE id = ?",
+                    )
+                    .bind(cost)
+                    .bind(&spec.session_id)
+                    .execute(pool)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                }
+                result = Some((is_error, text));
+            }
+        }
+    }
+
+    let status = child
+        .wait()
+        .await
+        .map_err(|e| format!("Failed to wait for agent: {}", e))?;
+    let stderr = stderr_task.await.unwrap_or_default();
+
+    match result {
+        Some((false, _)) if status.success() => Ok(()),
+        Some((true, text)) => Err(text.unwrap_or_else(|| "Agent reported an error".to_string())),
+        _ => {
+            let detail = stderr.trim();
+            if detail.is_empty() {
+                Err(format!("Agent exited with {}", status))
+            } else {
+                Err(detail.to_string())
+            }
+        }
+    }
+}
+
+/// Reset the session status once a turn ends and tell the frontend
+async fn finish_turn<F>(
+    pool: &DbPool,
+    spec: &TurnSpec,
+    outcome: Result<(), String>,
+    cancelled: bool,
+    emit: &F,
+) where
+    F: Fn(SessionEvent),
+{
+    let status = if cancelled {
+        if let Err(e) = record_cancellation(pool, spec).await {
+            eprintln!("Failed to record turn cancellation: {}", e);
+        }
+        emit(SessionEvent {
+            session_id: spec.session_id.clone(),
+            turn_id: spec.turn_id.clone(),
+            kind: SessionEventKind::Cancelled,
+        });
+        STATUS_IDLE
+    } else {
+        match &outcome {
+            Ok(()) => {
+                // The agent's transcript continues from this turn, so any rewind point is stale
+                if let Err(e) =
+                    sqlx::query("UPDATE sessions SET resume_session_at = NULL WHERE id = ?")
+                        .bind(&spec.session_id)
+                        .execute(pool)
+                        .await
+                {
+                    eprintln!("Failed to clear resume point: {}", e);
+                }
+                STATUS_IDLE
+            }
+            Err(message) => {
+                emit(SessionEvent {
+                    session_id: spec.session_id.clone(),
+                    turn_id: spec.turn_id.clone(),
+                    kind: SessionEventKind::Error {
+                        message: message.clone(),
+                    },
+                });
+                STATUS_ERROR
+            }
+        }
+    };
+
+    if let Err(e) = set_session_status(pool, &spec.session_id, status).await {
+        eprintln!("Failed to update session status: {}", e);
+    }
+
+    emit(SessionEvent {
+        session_id: spec.session_id.clone(),
+        turn_id: spec.turn_id.clone(),
+        kind: SessionEventKind::Status {
+            status: status.to_string(),
+        },
+    });
+}
+
+/// Mark a cancelled turn's messages, rewind the agent's resume point to the last
+/// completed assistant message and clear any git lock the agent left in the worktree
+async fn record_cancellation(pool: &DbPool, spec: &TurnSpec) -> Result<(), String> {
+    sqlx::query(
+        "UPDATE session_messages SET cancelled_at = datetime('now') WHERE turn_id = ? AND cancelled_at IS NULL",
+    )
+    .bind(&spec.turn_id)
+    .execute(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    rewind_resume_point(pool, &spec.session_id).await?;
+    remove_stale_index_lock(&spec.cwd, spec.started_at)
+}
+
+/// Point `resume_session_at` at the session's last assistant message that wasn't cancelled
+pub(crate) async fn rewind_resume_point(pool: &DbPool, session_id: &str) -> Result<(), String> {
+    sqlx::query(
+        r#"
+        UPDATE sessions SET resume_session_at = (
+            SELECT sdk_message_id FROM session_messages
+            WHERE session_id = ? AND role = 'assistant'
+              AND cancelled_at IS NULL AND sdk_message_id IS NOT NULL
+            ORDER BY rowid DESC LIMIT 1
+        )
+        WHERE id = ?
+        "#,
+    )
+    .bind(session_id)
+    .bind(session_id)
+    .execute(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+/// Mark sessions left `running` by a previous run of the app as interrupted, and set
+/// their resume point to the last message the agent completed. Called once at startup,
+/// before any turn can be running. Returns the ids of the interrupted sessions.
+pub async fn recover_interrupted_sessions(pool: &DbPool) -> Result<Vec<String>, String> {
+    let session_ids: Vec<String> = sqlx::query_scalar(
+        "UPDATE sessions SET status = ?, updated_at = datetime('now') WHERE status = ? RETURNING id",
+    )
+    .bind(STATUS_INTERRUPTED)
+    .bind(STATUS_RUNNING)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    for session_id in &session_ids {
+        rewind_resume_point(pool, session_id).await?;
+    }
+
+    Ok(session_ids)
+}
+
+/// Remove the worktree's `index.lock` left behind by a git command killed mid-write.
+/// Only a lock taken since `since`, while the killed agent was running, is removed; an
+/// older one belongs to some other git process and is left alone.
+fn remove_stale_index_lock(worktree_path: &Path, since: SystemTime) -> Result<(), String> {
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(worktree_path)
+        .arg("rev-parse")
+        .arg("--git-dir")
+        .output()
+        .map_err(|e| format!("Failed to execute git command: {}", e))?;
+
+    if !output.status.success() {
+        return Ok(());
+    }
+
+    let git_dir = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+    let lock = worktree_path.join(git_dir).join("index.lock");
+    let Ok(modified) = std::fs::metadata(&lock).and_then(|m| m.modified()) else {
+        return Ok(());
+    };
+    if modified < since {
+        return Err(format!(
+            "Kept {} because it was taken before the turn started",
+            lock.display()
+        ));
+    }
+    std::fs::remove_file(&lock).map_err(|e| format!("Failed to remove {}: {}", lock.display(), e))
+}
+
+async fn set_session_status(pool: &DbPool, session_id: &str, status: &str) -> Result<(), String> {
+    sqlx::query("UPDATE sessions SET status = ?, updated_at = datetime('now') WHERE id = ?")
+        .bind(status)
+        .bind(session_id)
+        .execute(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn insert_message(
+    pool: &DbPool,
+    session_id: &str,
+    turn_id: &str,
+    role: &str,
+    content: &str,
+    full_message: Option<&str>,
+    model: Option<&str>,
+    sdk_message_id: Option<&str>,
+) -> Result<SessionMessage, String> {
+    let id = uuid::Uuid::new_v4().to_string();
+
+    sqlx::query(
+        r#"
+        INSERT INTO session_messages
+            (id, session_id, role, content, sent_at, full_message, model, sdk_message_id, turn_id)
+        VALUES (?, ?, ?, ?, datetime('now'), ?, ?, ?, ?)
+        "#,
+    )
+    .bind(&id)
+    .bind(session_id)
+    .bind(role)
+    .bind(content)
+    .bind(full_message)
+    .bind(model)
+    .bind(sdk_message_id)
+    .bind(turn_id)
+    .execute(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let message: SessionMessage = sqlx::query_as("SELECT * FROM session_messages WHERE id = ?")
+        .bind(&id)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(message)
+}
+
+/// Parse one line of `--output-format stream-json` output
+fn parse_agent_line(line: &str) -> Option<AgentEvent> {
+    let value: Value = serde_json::from_str(line.trim()).ok()?;
+
+    match value.get("type")?.as_str()? {
+        "system" if value.get("subtype").and_then(Value::as_str) == Some("init") => {
+            Some(AgentEvent::Init {
+                agent_session_id: value.get("session_id")?.as_str()?.to_string(),
+            })
+        }
+        "stream_event" => {
+            let delta = value.get("event")?.get("delta")?;
+            if delta.get("type")?.as_str()? != "text_delta" {
+                return None;
+            }
+            Some(AgentEvent::Delta {
+                text: delta.get("text")?.as_str()?.to_string(),
+            })
+        }
+        role @ ("assistant" | "user") => {
+            let message = value.get("message")?;
+            // Tool results come back from the CLI as "user" messages
+            let role = if role == "user" && has_tool_result(message) {
+                "tool"
+            } else {
+                role
+            };
+            Some(AgentEvent::Message {
+                role: role.to_string(),
+                sdk_message_id: message
+                    .get("id")
+                    .and_then(Value::as_str)
+                    .map(str::to_string),
+                model: message
+                    .get("model")
+                    .and_then(Value::as_str)
+                    .map(str::to_string),
+                content: message_text(message.get("content")?),
+            })
+        }
+        "result" => Some(AgentEvent::Result {
+            is_error: value
+                .get("is_error")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+            text: value
+                .get("result")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+            cost_usd: value.get("total_cost_usd").and_then(Value::as_f64),
+        }),
+        _ => None,
+    }
+}
+
+fn has_tool_result(message: &Value) -> bool {
+    message
+        .get("content")
+        .and_then(Value::as_array)
+        .is_some_and(|blocks| {
+            blocks
+                .iter()
+                .any(|b| b.get("type").and_then(Value::as_str) == Some("tool_result"))
+        })
+}
+
+/// Flatten message content (a string or a list of blocks) into its readable text
+fn message_text(content: &Value) -> String {
+    match content {
+        Value::String(text) => text.clone(),
+        Value::Array(blocks) => blocks
+            .iter()
+            .filter_map(|block| match block.get("type").and_then(Value::as_str) {
+                Some("text") => block
+                    .get("text")
+                    .and_then(Value::as_str)
+                    .map(str::to_string),
+                Some("tool_result") => block.get("content").map(message_text),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_pool;
+
+    #[test]
+    fn test_parse_agent_line() {
+        assert_eq!(
+            parse_agent_line(r#"{"type":"system","subtype":"init","session_id":"abc"}"#),
+            Some(AgentEvent::Init {
+                agent_session_id: "abc".to_string()
+            })
+        );
+        assert_eq!(
+            parse_agent_line(
+                r#"{"type":"stream_event","event":{"type":"content_block_delta","delta":{"type":"text_delta","text":"Hi"}}}"#
+            ),
+            Some(AgentEvent::Delta {
+                text: "Hi".to_string()
+            })
+        );
+        assert_eq!(
+            parse_agent_line(
+                r#"{"type":"user","message":{"content":[{"type":"tool_result","content":"ok"}]}}"#
+            ),
+            Some(AgentEvent::Message {
+                role: "tool".to_string(),
+                sdk_message_id: None,
+                model: None,
+                content: "ok".to_string()
+            })
+        );
+        assert_eq!(
+            parse_agent_line(
+                r#"{"type":"result","subtype":"success","is_error":false,"result":"Done","total_cost_usd":0.25}"#
+            ),
+            Some(AgentEvent::Result {
+                is_error: false,
+                text: Some("Done".to_string()),
+                cost_usd: Some(0.25)
+            })
+        );
+        assert_eq!(parse_agent_line("not json"), None);
+    }
+
+    /// Write an executable fake agent script into a fresh temp directory and
+    /// return a turn spec that runs it
+    #[cfg(unix)]
+    fn fake_agent_spec(body: &str) -> TurnSpec {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("letsvibe-agent-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let script = dir.join("fake-agent.sh");
+        std::fs::write(&script, format!("#!/bin/sh\n{}", body)).unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        TurnSpec {
+            session_id: "s1".to_string(),
+            turn_id: "t1".to_string(),
+            prompt: "hi".to_string(),
+            cwd: dir,
+            binary: script.to_string_lossy().to_string(),
+            model: None,
+            permission_mode: None,
+            resume: None,
+            resume_at: None,
+            fork: false,
+            started_at: SystemTime::now(),
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_drive_turn_with_fake_agent() {
+        let pool = test_pool().await;
+        let spec = fake_agent_spec(
+            r#"
+echo '{"type":"system","subtype":"init","session_id":"agent-1"}'
+echo '{"type":"stream_event","event":{"type":"content_block_delta","delta":{"type":"text_delta","text":"Hel"}}}'
+echo '{"type":"assistant","message":{"id":"msg_1","model":"fake","content":[{"type":"text","text":"Hello"}]}}'
+echo '{"type":"user","message":{"content":[{"type":"tool_result","content":"done"}]}}'
+echo '{"type":"result","subtype":"success","is_error":false,"result":"Hello","total_cost_usd":0.5}'
+"#,
+        );
+
+        sqlx::query("INSERT INTO sessions (id) VALUES ('s1')")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let events = std::sync::Mutex::new(Vec::new());
+        let child = spawn_agent(&spec).unwrap();
+        let outcome = drive_turn(&pool, &spec, child, &|e: SessionEvent| {
+            events.lock().unwrap().push(e)
+        })
+        .await;
+        assert_eq!(outcome, Ok(()));
+
+        let messages: Vec<SessionMessage> =
+            sqlx::query_as("SELECT * FROM session_messages WHERE turn_id = 't1' ORDER BY rowid")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        let roles: Vec<_> = messages.iter().map(|m| m.role.clone().unwrap()).collect();
+        assert_eq!(roles, vec!["assistant", "tool"]);
+        assert_eq!(
+            messages[1].last_assistant_message_id.as_deref(),
+            Some("msg_1")
+        );
+
+        let agent_session_id: Option<String> =
+            sqlx::query_scalar("SELECT claude_session_id FROM sessions WHERE id = 's1'")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(agent_session_id.as_deref(), Some("agent-1"));
+        assert_eq!(events.lock().unwrap().len(), 3);
+
+        let cost: Option<f64> = sqlx::query_scalar("SELECT cost_usd FROM sessions WHERE id = 's1'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(cost, Some(0.5));
+
+        let _ = std::fs::remove_dir_all(&spec.cwd);
+    }
+
+    #[tokio::test]
+    async fn test_clone_session_history() {
+        let pool = test_pool().await;
+        sqlx::query(
+            "INSERT INTO sessions (id, claude_session_id, model, title) VALUES ('s1', 'agent-1', 'opus', 'Fix it')",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query("INSERT INTO workspaces (id) VALUES ('w2')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        insert_message(&pool, "s1", "t1", "user", "Do it", None, None, None)
+            .await
+            .unwrap();
+        insert_message(&pool, "s1", "t1", "assistant", "Done", None, None, None)
+            .await
+            .unwrap();
+
+        let session = clone_session_history(&pool, "s1", "w2").await.unwrap();
+        assert_ne!(session.id, "s1");
+        assert_eq!(session.workspace_id.as_deref(), Some("w2"));
+        assert_eq!(session.model.as_deref(), Some("opus"));
+        assert_eq!(session.title.as_deref(), Some("Fix it"));
+        // The first turn forks the agent's conversation instead of continuing it
+        assert_eq!(session.claude_session_id.as_deref(), Some("agent-1"));
+        assert_eq!(session.fork_session, Some(1));
+
+        let contents: Vec<String> = sqlx::query_scalar(
+            "SELECT content FROM session_messages WHERE session_id = ? ORDER BY rowid",
+        )
+        .bind(&session.id)
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(contents, ["Do it", "Done"]);
+        let turns: Vec<Option<String>> =
+            sqlx::query_scalar("SELECT turn_id FROM session_messages WHERE session_id = ?")
+                .bind(&session.id)
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(turns, [None, None]);
+
+        let active: Option<String> =
+            sqlx::query_scalar("SELECT active_session_id FROM workspaces WHERE id = 'w2'")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(active, Some(session.id));
+    }
+
+    #[tokio::test]
+    async fn test_recover_interrupted_sessions() {
+        let pool = test_pool().await;
+        sqlx::query(
+            "INSERT INTO sessions (id, status, claude_session_id) VALUES ('s1', 'running', 'agent-1'), ('s2', 'idle', NULL)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        insert_message(&pool, "s1", "t1", "user", "Do it", None, None, None)
+            .await
+            .unwrap();
+        insert_message(
+            &pool,
+            "s1",
+            "t1",
+            "assistant",
+            "On it",
+            None,
+            None,
+            Some("msg_1"),
+        )
+        .await
+        .unwrap();
+
+        let interrupted = recover_interrupted_sessions(&pool).await.unwrap();
+        assert_eq!(interrupted, vec!["s1".to_string()]);
+
+        let session: Session = sqlx::query_as("SELECT * FROM sessions WHERE id = 's1'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(session.status.as_deref(), Some(STATUS_INTERRUPTED));
+        assert_eq!(session.resume_session_at.as_deref(), Some("msg_1"));
+        assert_eq!(session.claude_session_id.as_deref(), Some("agent-1"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_cancel_turn_marks_messages_and_resets_status() {
+        let pool = test_pool().await;
+        let turns: RunningTurns = Arc::new(Mutex::new(HashMap::new()));
+
+        // A previous, completed turn the agent can be rewound to
+        sqlx::query("INSERT INTO sessions (id, claude_session_id) VALUES ('s1', 'agent-1')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        insert_message(
+            &pool,
+            "s1",
+            "t0",
+            "assistant",
+            "Earlier",
+            None,
+            None,
+            Some("msg_0"),
+        )
+        .await
+        .unwrap();
+
+        // Ignores SIGINT so cancellation has to escalate to a kill
+        let spec = fake_agent_spec(
+            r#"
+trap '' INT
+echo '{"type":"assistant","message":{"id":"msg_1","content":[{"type":"text","text":"Working"}]}}'
+sleep 30
+"#,
+        );
+        let cwd = spec.cwd.clone();
+
+        launch_turn(pool.clone(), turns.clone(), spec, |_| {})
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        cancel_turn(&turns, "s1").await.unwrap();
+        assert!(turns.lock().await.is_empty());
+
+        let uncancelled: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM session_messages WHERE turn_id = 't1' AND cancelled_at IS NULL",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(uncancelled, 0);
+
+        let session: Session = sqlx::query_as("SELECT * FROM sessions WHERE id = 's1'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(session.status.as_deref(), Some(STATUS_IDLE));
+        assert_eq!(session.resume_session_at.as_deref(), Some("msg_0"));
+
+        let _ = std::fs::remove_dir_all(&cwd);
+    }
+
+    #[test]
+    fn test_remove_stale_index_lock() {
+        let dir = std::env::temp_dir().join(format!("letsvibe-lock-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        crate::git::run_git(&dir, &["init", "-q"]).unwrap();
+        let lock = dir.join(".git").join("index.lock");
+
+        // A lock taken before the turn started isn't the agent's
+        std::fs::write(&lock, "").unwrap();
+        let later = SystemTime::now() + Duration::from_secs(3600);
+        assert!(remove_stale_index_lock(&dir, later).is_err());
+        assert!(lock.exists());
+
+        remove_stale_index_lock(&dir, SystemTime::UNIX_EPOCH).unwrap();
+        assert!(!lock.exists());
+        // Nothing to do without a lock
+        remove_stale_index_lock(&dir, SystemTime::UNIX_EPOCH).unwrap();
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_strip_code_fence() {
+        assert_eq!(
+            strip_code_fence("```text\nFix parser\n\nDetails\n```\n"),
+            "Fix parser\n\nDetails"
+        );
+        assert_eq!(strip_code_fence("  Fix parser\n"), "Fix parser");
+
+        let mut text = "héllo".to_string();
+        truncate_for_prompt(&mut text, 2);
+        assert_eq!(text, "h\n[truncated]\n");
+    }
+}
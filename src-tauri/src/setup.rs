@@ -0,0 +1,459 @@
+use crate::commands::{
+    get_setting, set_setting, workspace_script_env, WorkspaceContext, WORKSPACE_STATE_ACTIVE,
+};
+use crate::db::models::Workspace;
+use crate::run_script::{RunningScript, RunningScripts};
+use crate::scripts::run_script_logged;
+use crate::{AppState, DbPool};
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+use std::process::ExitStatus;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tauri::{AppHandle, Emitter, Manager, State};
+
+/// `workspaces.state` while the repository's setup script runs
+pub const WORKSPACE_STATE_SETTING_UP: &str = "setting_up";
+
+/// `workspaces.state` of a workspace whose setup script exited with an error
+pub const WORKSPACE_STATE_SETUP_FAILED: &str = "setup_failed";
+
+/// Prefix of the `settings` keys holding each repository's initialization files
+const INITIALIZATION_FILES_SETTING_PREFIX: &str = "initialization_files:";
+
+/// Gitignored files brought over from the repository root into new workspaces
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InitializationFiles {
+    /// Glob patterns relative to the repository root, e.g. `.env*` or `certs/*.pem`
+    pub patterns: Vec<String>,
+    /// Symlink the files instead of copying them, so every workspace shares one copy
+    #[serde(default)]
+    pub symlink: bool,
+}
+
+impl Default for InitializationFiles {
+    fn default() -> Self {
+        Self {
+            patterns: vec![".env".to_string(), ".env.*".to_string()],
+            symlink: false,
+        }
+    }
+}
+
+/// Progress of a workspace's setup script, emitted as `workspace-setup`
+#[derive(Debug, Clone, Serialize)]
+pub struct SetupEvent {
+    pub workspace_id: String,
+    #[serde(flatten)]
+    pub kind: SetupEventKind,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SetupEventKind {
+    Started {
+        log_path: String,
+    },
+    /// One line of the script's stdout or stderr
+    Output {
+        line: String,
+    },
+    Finished {
+        success: bool,
+        exit_code: Option<i32>,
+    },
+}
+
+/// Start the repository's setup script in a freshly created workspace.
+/// The script keeps running in the background; the returned workspace
+/// is `setting_up` until it exits.
+pub(crate) async fn start_setup_script(
+    app: &AppHandle,
+    pool: &DbPool,
+    context: WorkspaceContext,
+) -> Result<Workspace, String> {
+    let Some(script) = context
+        .repo
+        .setup_script
+        .clone()
+        .filter(|script| !script.trim().is_empty())
+    else {
+        return Ok(context.workspace);
+    };
+
+    let log_path = app
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to get app data directory: {}", e))?
+        .join("logs")
+        .join(&context.workspace.id)
+        .join("setup.log");
+    let log_path_str = log_path.to_string_lossy().to_string();
+
+    sqlx::query(
+        "UPDATE workspaces SET state = ?, setup_log_path = ?, updated_at = datetime('now') WHERE id = ?",
+    )
+    .bind(WORKSPACE_STATE_SETTING_UP)
+    .bind(&log_path_str)
+    .bind(&context.workspace.id)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to update workspace: {}", e))?;
+
+    let workspace: Workspace = sqlx::query_as("SELECT * FROM workspaces WHERE id = ?")
+        .bind(&context.workspace.id)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let _ = app.emit(
+        "workspace-setup",
+        SetupEvent {
+            workspace_id: workspace.id.clone(),
+            kind: SetupEventKind::Started {
+                log_path: log_path_str,
+            },
+        },
+    );
+
+    let app = app.clone();
+    let pool = pool.clone();
+    let scripts = app.state::<AppState>().setup_scripts.clone();
+    tauri::async_runtime::spawn(async move {
+        run_setup_script(&app, &pool, &scripts, &context, &script, log_path).await;
+    });
+
+    Ok(workspace)
+}
+
+/// Run the setup script to completion and record whether it succeeded
+async fn run_setup_script(
+    app: &AppHandle,
+    pool: &DbPool,
+    scripts: &RunningScripts,
+    context: &WorkspaceContext,
+    script: &str,
+    log_path: PathBuf,
+) {
+    let workspace_id = &context.workspace.id;
+    let emit = |kind: SetupEventKind| {
+        let _ = app.emit(
+            "workspace-setup",
+            SetupEvent {
+                workspace_id: workspace_id.clone(),
+                kind,
+            },
+        );
+    };
+
+    let envs = workspace_script_env(context);
+    let result = run_tracked_script(
+        scripts,
+        RunningScript {
+            workspace_id: workspace_id.clone(),
+            repository_id: context.repo.id.clone(),
+            run_id: uuid::Uuid::new_v4().to_string(),
+            pid: None,
+            stopped: Arc::new(AtomicBool::new(false)),
+        },
+        script,
+        &context.worktree_path,
+        &envs,
+        &log_path,
+        |line| {
+            emit(SetupEventKind::Output {
+                line: line.to_string(),
+            })
+        },
+    )
+    .await;
+
+    let (success, exit_code) = match result {
+        Ok(status) => (status.success(), status.code()),
+        Err(e) => {
+            emit(SetupEventKind::Output { line: e });
+            (false, None)
+        }
+    };
+
+    let new_state = if success {
+        WORKSPACE_STATE_ACTIVE
+    } else {
+        WORKSPACE_STATE_SETUP_FAILED
+    };
+    // Only move on from `setting_up`, the workspace may have been archived meanwhile
+    if let Err(e) = sqlx::query(
+        "UPDATE workspaces SET state = ?, updated_at = datetime('now') WHERE id = ? AND state = ?",
+    )
+    .bind(new_state)
+    .bind(workspace_id)
+    .bind(WORKSPACE_STATE_SETTING_UP)
+    .execute(pool)
+    .await
+    {
+        eprintln!("Failed to record setup result: {}", e);
+    }
+
+    emit(SetupEventKind::Finished { success, exit_code });
+}
+
+/// Run a script in a workspace, registered in `scripts` while it runs so deleting or
+/// archiving the workspace can stop it
+async fn run_tracked_script<F>(
+    scripts: &RunningScripts,
+    run: RunningScript,
+    script: &str,
+    cwd: &Path,
+    envs: &[(&str, String)],
+    log_path: &Path,
+    on_line: F,
+) -> Result<ExitStatus, String>
+where
+    F: FnMut(&str),
+{
+    let register = |pid| {
+        let started = RunningScript { pid, ..run.clone() };
+        async move {
+            scripts
+                .lock()
+                .await
+                .insert(started.workspace_id.clone(), started);
+        }
+    };
+    let result = run_script_logged(script, cwd, envs, log_path, on_line, register).await;
+
+    let mut running = scripts.lock().await;
+    if running.get(&run.workspace_id).map(|s| &s.run_id) == Some(&run.run_id) {
+        running.remove(&run.workspace_id);
+    }
+    result
+}
+
+/// Read the output of a workspace's setup script
+#[tauri::command]
+pub async fn get_setup_log(
+    state: State<'_, AppState>,
+    workspace_id: String,
+) -> Result<Option<String>, String> {
+    let db = state.db.lock().await;
+    let pool = db.as_ref().ok_or("Database not initialized")?;
+
+    let log_path: Option<String> =
+        sqlx::query_scalar("SELECT setup_log_path FROM workspaces WHERE id = ?")
+            .bind(&workspace_id)
+            .fetch_one(pool)
+            .await
+            .map_err(|e| format!("Workspace not found: {}", e))?;
+
+    match log_path {
+        Some(path) => std::fs::read_to_string(&path)
+            .map(Some)
+            .map_err(|e| format!("Failed to read setup log: {}", e)),
+        None => Ok(None),
+    }
+}
+
+/// Load a repository's initialization files, falling back to the defaults
+pub(crate) async fn load_initialization_files(
+    pool: &DbPool,
+    repository_id: &str,
+) -> Result<InitializationFiles, String> {
+    let key = format!("{}{}", INITIALIZATION_FILES_SETTING_PREFIX, repository_id);
+    match get_setting(pool, &key).await? {
+        Some(value) => serde_json::from_str(&value)
+            .map_err(|e| format!("Invalid initialization files setting: {}", e)),
+        None => Ok(InitializationFiles::default()),
+    }
+}
+
+/// Get the files copied into new workspaces of a repository
+#[tauri::command]
+pub async fn get_initialization_files(
+    state: State<'_, AppState>,
+    repository_id: String,
+) -> Result<InitializationFiles, String> {
+    let db = state.db.lock().await;
+    let pool = db.as_ref().ok_or("Database not initialized")?;
+
+    load_initialization_files(pool, &repository_id).await
+}
+
+/// Set the files copied into new workspaces of a repository
+#[tauri::command]
+pub async fn set_initialization_files(
+    state: State<'_, AppState>,
+    repository_id: String,
+    files: InitializationFiles,
+) -> Result<(), String> {
+    let db = state.db.lock().await;
+    let pool = db.as_ref().ok_or("Database not initialized")?;
+
+    for pattern in &files.patterns {
+        validate_pattern(pattern)?;
+    }
+
+    let key = format!("{}{}", INITIALIZATION_FILES_SETTING_PREFIX, repository_id);
+    let value = serde_json::to_string(&files).map_err(|e| e.to_string())?;
+    set_setting(pool, &key, &value).await
+}
+
+/// Patterns must stay inside the repository root
+fn validate_pattern(pattern: &str) -> Result<(), String> {
+    let path = Path::new(pattern);
+    if pattern.trim().is_empty()
+        || path.is_absolute()
+        || path.components().any(|c| c == Component::ParentDir)
+    {
+        return Err(format!("Invalid pattern: {}", pattern));
+    }
+    glob::Pattern::new(pattern).map_err(|e| format!("Invalid pattern {}: {}", pattern, e))?;
+    Ok(())
+}
+
+/// Copy (or symlink) the files matching `files.patterns` from the repository root into a
+/// new worktree. Files already present in the worktree, such as tracked ones, are left alone.
+/// Returns the copied paths relative to the root, and a warning for each one that failed.
+pub(crate) fn copy_initialization_files(
+    repo_root: &Path,
+    worktree_path: &Path,
+    files: &InitializationFiles,
+) -> (Vec<String>, Vec<String>) {
+    let mut copied = Vec::new();
+    let mut warnings = Vec::new();
+
+    for pattern in &files.patterns {
+        if let Err(e) = validate_pattern(pattern) {
+            warnings.push(e);
+            continue;
+        }
+        let full_pattern = repo_root.join(pattern);
+        let matches = match glob::glob(&full_pattern.to_string_lossy()) {
+            Ok(matches) => matches,
+            Err(e) => {
+                warnings.push(format!("Invalid pattern {}: {}", pattern, e));
+                continue;
+            }
+        };
+
+        for source in matches.flatten() {
+            let Ok(relative) = source.strip_prefix(repo_root) else {
+                continue;
+            };
+            if !source.is_file() || relative.starts_with(".git") {
+                continue;
+            }
+            let relative_str = relative.to_string_lossy().to_string();
+            let target = worktree_path.join(relative);
+            if target.exists() || copied.contains(&relative_str) {
+                continue;
+            }
+
+            match copy_file(&source, &target, files.symlink) {
+                Ok(()) => copied.push(relative_str),
+                Err(e) => warnings.push(format!("Failed to copy {}: {}", relative_str, e)),
+            }
+        }
+    }
+
+    (copied, warnings)
+}
+
+fn copy_file(source: &Path, target: &Path, symlink: bool) -> std::io::Result<()> {
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if !symlink {
+        return std::fs::copy(source, target).map(|_| ());
+    }
+    #[cfg(unix)]
+    return std::os::unix::fs::symlink(source, target);
+    #[cfg(windows)]
+    return std::os::windows::fs::symlink_file(source, target);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run_script::stop_workspace_script;
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+    use tokio::sync::Mutex;
+
+    #[tokio::test]
+    async fn test_run_tracked_script_can_be_stopped() {
+        let scripts: RunningScripts = Arc::new(Mutex::new(HashMap::new()));
+        let dir = std::env::temp_dir().join(format!("letsvibe-setup-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let log_path = dir.join("setup.log");
+        let run = RunningScript {
+            workspace_id: "w1".to_string(),
+            repository_id: "r1".to_string(),
+            run_id: "run".to_string(),
+            pid: None,
+            stopped: Arc::new(AtomicBool::new(false)),
+        };
+
+        let started = Instant::now();
+        let running = run_tracked_script(&scripts, run, "sleep 30", &dir, &[], &log_path, |_| {});
+        let stop = async {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            assert!(scripts.lock().await.get("w1").unwrap().pid.is_some());
+            stop_workspace_script(&scripts, "w1").await
+        };
+        let (status, stopped) = tokio::join!(running, stop);
+
+        stopped.unwrap();
+        assert!(!status.unwrap().success());
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(scripts.lock().await.is_empty());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_validate_pattern() {
+        assert!(validate_pattern(".env*").is_ok());
+        assert!(validate_pattern("certs/*.pem").is_ok());
+        assert!(validate_pattern("../secrets").is_err());
+        assert!(validate_pattern("/etc/passwd").is_err());
+        assert!(validate_pattern("").is_err());
+    }
+
+    #[test]
+    fn test_copy_initialization_files() {
+        let dir = std::env::temp_dir().join(format!("letsvibe-init-{}", uuid::Uuid::new_v4()));
+        let root = dir.join("root");
+        let worktree = dir.join("worktree");
+        std::fs::create_dir_all(root.join("certs")).unwrap();
+        std::fs::create_dir_all(&worktree).unwrap();
+        std::fs::write(root.join(".env"), "A=1").unwrap();
+        std::fs::write(root.join(".env.local"), "B=2").unwrap();
+        std::fs::write(root.join("certs/dev.pem"), "pem").unwrap();
+        std::fs::write(root.join("README.md"), "root").unwrap();
+        std::fs::write(worktree.join("README.md"), "tracked").unwrap();
+
+        let files = InitializationFiles {
+            patterns: vec![
+                ".env*".to_string(),
+                "certs/*.pem".to_string(),
+                "README.md".to_string(),
+            ],
+            symlink: false,
+        };
+        let (mut copied, warnings) = copy_initialization_files(&root, &worktree, &files);
+        copied.sort();
+
+        assert!(warnings.is_empty());
+        assert_eq!(copied, vec![".env", ".env.local", "certs/dev.pem"]);
+        assert_eq!(
+            std::fs::read_to_string(worktree.join(".env")).unwrap(),
+            "A=1"
+        );
+        assert_eq!(
+            std::fs::read_to_string(worktree.join("README.md")).unwrap(),
+            "tracked"
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,404 @@
+use crate::commands::{load_workspace_context, workspace_script_env};
+use crate::AppState;
+use portable_pty::{native_pty_system, ChildKiller, CommandBuilder, MasterPty, PtySize};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex as StdMutex};
+use tauri::{AppHandle, Emitter, State};
+use tokio::sync::Mutex;
+
+/// Bytes of output kept per terminal for reattaching after a reload
+const SCROLLBACK_LIMIT: usize = 1024 * 1024;
+
+const DEFAULT_COLS: u16 = 80;
+const DEFAULT_ROWS: u16 = 24;
+
+/// A shell running in a pseudo terminal inside a workspace
+pub struct Terminal {
+    pub id: String,
+    pub workspace_id: String,
+    pub created_at: String,
+    master: StdMutex<Box<dyn MasterPty + Send>>,
+    writer: StdMutex<Box<dyn Write + Send>>,
+    killer: StdMutex<Box<dyn ChildKiller + Send + Sync>>,
+    state: StdMutex<TerminalState>,
+}
+
+/// The parts of a terminal that change while it runs
+#[derive(Default)]
+struct TerminalState {
+    scrollback: String,
+    cols: u16,
+    rows: u16,
+    /// Set once the shell has exited
+    exit_code: Option<u32>,
+    exited: bool,
+}
+
+/// Open terminals keyed by terminal id
+pub type Terminals = Arc<Mutex<HashMap<String, Arc<Terminal>>>>;
+
+/// What the frontend needs to show a terminal
+#[derive(Debug, Clone, Serialize)]
+pub struct TerminalInfo {
+    pub id: String,
+    pub workspace_id: String,
+    pub created_at: String,
+    pub cols: u16,
+    pub rows: u16,
+    pub exited: bool,
+    pub exit_code: Option<u32>,
+}
+
+/// A terminal along with its recent output, returned when reattaching
+#[derive(Debug, Clone, Serialize)]
+pub struct TerminalSnapshot {
+    #[serde(flatten)]
+    pub info: TerminalInfo,
+    pub scrollback: String,
+}
+
+/// Event emitted as `terminal-event` while a terminal runs
+#[derive(Debug, Clone, Serialize)]
+pub struct TerminalEvent {
+    pub terminal_id: String,
+    pub workspace_id: String,
+    #[serde(flatten)]
+    pub kind: TerminalEventKind,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TerminalEventKind {
+    Output { data: String },
+    Exited { exit_code: Option<u32> },
+}
+
+impl Terminal {
+    fn info(&self) -> TerminalInfo {
+        let state = self.state.lock().unwrap();
+        TerminalInfo {
+            id: self.id.clone(),
+            workspace_id: self.workspace_id.clone(),
+            created_at: self.created_at.clone(),
+            cols: state.cols,
+            rows: state.rows,
+            exited: state.exited,
+            exit_code: state.exit_code,
+        }
+    }
+
+    fn snapshot(&self) -> TerminalSnapshot {
+        TerminalSnapshot {
+            info: self.info(),
+            scrollback: self.state.lock().unwrap().scrollback.clone(),
+        }
+    }
+
+    fn write(&self, data: &[u8]) -> Result<(), String> {
+        let mut writer = self.writer.lock().unwrap();
+        writer
+            .write_all(data)
+            .and_then(|_| writer.flush())
+            .map_err(|e| format!("Failed to write to terminal: {}", e))
+    }
+
+    fn resize(&self, cols: u16, rows: u16) -> Result<(), String> {
+        self.master
+            .lock()
+            .unwrap()
+            .resize(PtySize {
+                rows,
+                cols,
+                pixel_width: 0,
+                pixel_height: 0,
+            })
+            .map_err(|e| format!("Failed to resize terminal: {}", e))?;
+        let mut state = self.state.lock().unwrap();
+        state.cols = cols;
+        state.rows = rows;
+        Ok(())
+    }
+
+    fn kill(&self) {
+        if !self.state.lock().unwrap().exited {
+            let _ = self.killer.lock().unwrap().kill();
+        }
+    }
+}
+
+/// Where and how to start a terminal's shell
+struct TerminalSpec {
+    workspace_id: String,
+    cwd: PathBuf,
+    envs: Vec<(&'static str, String)>,
+    cols: u16,
+    rows: u16,
+}
+
+/// Open a shell in the workspace's worktree
+#[tauri::command]
+pub async fn open_terminal(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    workspace_id: String,
+    cols: Option<u16>,
+    rows: Option<u16>,
+) -> Result<TerminalInfo, String> {
+    let context = {
+        let db = state.db.lock().await;
+        let pool = db.as_ref().ok_or("Database not initialized")?;
+        load_workspace_context(pool, &workspace_id).await?
+    };
+    if !context.worktree_path.exists() {
+        return Err(format!(
+            "Workspace directory does not exist: {}",
+            context.worktree_path.display()
+        ));
+    }
+
+    let spec = TerminalSpec {
+        workspace_id,
+        envs: workspace_script_env(&context),
+        cwd: context.worktree_path,
+        cols: cols.unwrap_or(DEFAULT_COLS),
+        rows: rows.unwrap_or(DEFAULT_ROWS),
+    };
+    let terminal = spawn_terminal(spec, move |event| {
+        let _ = app.emit("terminal-event", event);
+    })?;
+
+    let info = terminal.info();
+    state
+        .terminals
+        .lock()
+        .await
+        .insert(terminal.id.clone(), terminal);
+    Ok(info)
+}
+
+/// Send keyboard input to a terminal
+#[tauri::command]
+pub async fn write_terminal(
+    state: State<'_, AppState>,
+    terminal_id: String,
+    data: String,
+) -> Result<(), String> {
+    let terminal = get_terminal(&state.terminals, &terminal_id).await?;
+    tauri::async_runtime::spawn_blocking(move || terminal.write(data.as_bytes()))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// Resize a terminal to the given number of columns and rows
+#[tauri::command]
+pub async fn resize_terminal(
+    state: State<'_, AppState>,
+    terminal_id: String,
+    cols: u16,
+    rows: u16,
+) -> Result<(), String> {
+    get_terminal(&state.terminals, &terminal_id)
+        .await?
+        .resize(cols, rows)
+}
+
+/// Kill a terminal's shell and forget it
+#[tauri::command]
+pub async fn close_terminal(state: State<'_, AppState>, terminal_id: String) -> Result<(), String> {
+    let terminal = state
+        .terminals
+        .lock()
+        .await
+        .remove(&terminal_id)
+        .ok_or("Terminal not found")?;
+    terminal.kill();
+    Ok(())
+}
+
+/// List the terminals of a workspace, or all of them
+#[tauri::command]
+pub async fn list_terminals(
+    state: State<'_, AppState>,
+    workspace_id: Option<String>,
+) -> Result<Vec<TerminalInfo>, String> {
+    let terminals = state.terminals.lock().await;
+    let mut infos: Vec<TerminalInfo> = terminals
+        .values()
+        .filter(|t| workspace_id.as_ref().is_none_or(|id| &t.workspace_id == id))
+        .map(|t| t.info())
+        .collect();
+    infos.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+    Ok(infos)
+}
+
+/// Reattach to a terminal after the webview reloaded, returning its recent output.
+/// Later output keeps arriving as `terminal-event`s.
+#[tauri::command]
+pub async fn attach_terminal(
+    state: State<'_, AppState>,
+    terminal_id: String,
+) -> Result<TerminalSnapshot, String> {
+    Ok(get_terminal(&state.terminals, &terminal_id)
+        .await?
+        .snapshot())
+}
+
+/// Kill and forget every terminal of a workspace, e.g. when it is deleted or archived
+pub(crate) async fn close_workspace_terminals(terminals: &Terminals, workspace_id: &str) {
+    let mut terminals = terminals.lock().await;
+    terminals.retain(|_, terminal| {
+        if terminal.workspace_id == workspace_id {
+            terminal.kill();
+            false
+        } else {
+            true
+        }
+    });
+}
+
+async fn get_terminal(terminals: &Terminals, terminal_id: &str) -> Result<Arc<Terminal>, String> {
+    terminals
+        .lock()
+        .await
+        .get(terminal_id)
+        .cloned()
+        .ok_or_else(|| "Terminal not found".to_string())
+}
+
+/// Start the user's shell in a new pseudo terminal and pump its output on a background thread
+fn spawn_terminal<F>(spec: TerminalSpec, emit: F) -> Result<Arc<Terminal>, String>
+where
+    F: Fn(TerminalEvent) + Send + 'static,
+{
+    let pair = native_pty_system()
+        .openpty(PtySize {
+            rows: spec.rows,
+            cols: spec.cols,
+            pixel_width: 0,
+            pixel_height: 0,
+        })
+        .map_err(|e| format!("Failed to open terminal: {}", e))?;
+
+    let mut command = CommandBuilder::new_default_prog();
+    command.cwd(&spec.cwd);
+    command.env("TERM", "xterm-256color");
+    for (key, value) in &spec.envs {
+        command.env(key, value);
+    }
+    let mut child = pair
+        .slave
+        .spawn_command(command)
+        .map_err(|e| format!("Failed to start shell: {}", e))?;
+    // The child holds its own handle to the slave side
+    drop(pair.slave);
+
+    let mut reader = pair
+        .master
+        .try_clone_reader()
+        .map_err(|e| format!("Failed to read from terminal: {}", e))?;
+    let writer = pair
+        .master
+        .take_writer()
+        .map_err(|e| format!("Failed to write to terminal: {}", e))?;
+
+    let terminal = Arc::new(Terminal {
+        id: uuid::Uuid::new_v4().to_string(),
+        workspace_id: spec.workspace_id,
+        created_at: chrono::Utc::now().to_rfc3339(),
+        killer: StdMutex::new(child.clone_killer()),
+        master: StdMutex::new(pair.master),
+        writer: StdMutex::new(writer),
+        state: StdMutex::new(TerminalState {
+            cols: spec.cols,
+            rows: spec.rows,
+            ..Default::default()
+        }),
+    });
+
+    let pump = terminal.clone();
+    std::thread::spawn(move || {
+        let event = |kind| TerminalEvent {
+            terminal_id: pump.id.clone(),
+            workspace_id: pump.workspace_id.clone(),
+            kind,
+        };
+        let mut buffer = [0u8; 8192];
+        let mut pending = Vec::new();
+        loop {
+            let n = match reader.read(&mut buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            let data = decode_utf8(&mut pending, &buffer[..n]);
+            if data.is_empty() {
+                continue;
+            }
+            append_scrollback(&mut pump.state.lock().unwrap().scrollback, &data);
+            emit(event(TerminalEventKind::Output { data }));
+        }
+
+        let exit_code = child.wait().ok().map(|status| status.exit_code());
+        {
+            let mut state = pump.state.lock().unwrap();
+            state.exited = true;
+            state.exit_code = exit_code;
+        }
+        emit(event(TerminalEventKind::Exited { exit_code }));
+    });
+
+    Ok(terminal)
+}
+
+/// Decode a chunk of terminal output, holding back a multi-byte character
+/// split across reads until the rest of it arrives
+fn decode_utf8(pending: &mut Vec<u8>, chunk: &[u8]) -> String {
+    pending.extend_from_slice(chunk);
+    let complete = match std::str::from_utf8(pending) {
+        Ok(_) => pending.len(),
+        // Only an incomplete sequence at the very end is worth waiting for
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        Err(_) => pending.len(),
+    };
+    let data = String::from_utf8_lossy(&pending[..complete]).to_string();
+    pending.drain(..complete);
+    data
+}
+
+/// Append output to a scrollback buffer, dropping the oldest output past the limit
+fn append_scrollback(scrollback: &mut String, data: &str) {
+    scrollback.push_str(data);
+    if scrollback.len() > SCROLLBACK_LIMIT {
+        let mut cut = scrollback.len() - SCROLLBACK_LIMIT;
+        while !scrollback.is_char_boundary(cut) {
+            cut += 1;
+        }
+        scrollback.drain(..cut);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_utf8_split_character() {
+        let mut pending = Vec::new();
+        let bytes = "héllo".as_bytes();
+        assert_eq!(decode_utf8(&mut pending, &bytes[..2]), "h");
+        assert_eq!(decode_utf8(&mut pending, &bytes[2..]), "éllo");
+        assert!(pending.is_empty());
+        assert_eq!(decode_utf8(&mut pending, &[0xff, b'a']), "\u{fffd}a");
+    }
+
+    #[test]
+    fn test_append_scrollback_caps_size() {
+        let mut scrollback = String::new();
+        append_scrollback(&mut scrollback, &"a".repeat(SCROLLBACK_LIMIT));
+        append_scrollback(&mut scrollback, "é!");
+        assert_eq!(scrollback.len(), SCROLLBACK_LIMIT);
+        assert!(scrollback.ends_with("é!"));
+    }
+}